@@ -1,4 +1,9 @@
+use pm5::decoupling::DecouplingOptions;
+use pm5::pace::Ergometer;
+use pm5::predict::RacePredictor;
+use pm5::technique::{StrokeTechnique, TechniqueSummary};
 use pm5::workout::*;
+use pm5::zones::PowerZones;
 use polars::prelude::*;
 use uuid::Uuid;
 
@@ -24,6 +29,7 @@ async fn record_workout_example() -> anyhow::Result<Uuid> {
                 Some(240 + (i % 60) as u16),
                 Some(10 + i as u16),
             );
+            recorder.set_stroke_recovery(1600 + (i % 7) * 20, 1000);
         }
 
         recorder.add_general_sample(elapsed_ms, distance, hr, stroke_rate, pace);
@@ -37,6 +43,9 @@ async fn record_workout_example() -> anyhow::Result<Uuid> {
         summary.total_distance_m, summary.duration_ms
     );
     println!("Avg Power: {:?}W", summary.avg_power_watts);
+    if let Some(pace) = summary.avg_pace_ms_per_500m {
+        println!("Avg Pace: {}", summary.ergometer().format_pace(pace));
+    }
 
     let storage = WorkoutStorage::new_disk("rowing-workouts").await?;
 
@@ -50,13 +59,18 @@ async fn analyze_single_workout(id: uuid::Uuid) -> anyhow::Result<()> {
     let storage = WorkoutStorage::new_disk("rowing-workouts").await?;
 
     let lf = storage.load_workout_lazy("user_abc123", id).await?;
+    let mut profile = storage.load_profile("user_abc123").await?;
+    if profile.zones.power.is_none() {
+        profile.zones.power = Some(PowerZones::from_ftp(220));
+        storage.save_profile(&profile).await?;
+    }
 
     tokio::task::block_in_place(|| {
-        let with_zones = WorkoutAnalytics::power_zones(lf.clone());
+        let with_zones = WorkoutAnalytics::power_zones(lf.clone(), &profile.zones);
         let with_zones = WorkoutAnalytics::delta_time(with_zones);
         let high_intensity = with_zones
             .clone()
-            .filter(col("power_watts").gt(lit(250)))
+            .filter(col("power_zone").gt_eq(lit(4)))
             .collect()?;
 
         println!("High intensity samples: {}", high_intensity.height());
@@ -79,77 +93,64 @@ async fn analyze_single_workout(id: uuid::Uuid) -> anyhow::Result<()> {
     })
 }
 
-async fn analyze_user_power_trends(id: Uuid) -> anyhow::Result<()> {
+async fn analyze_user_power_trends() -> anyhow::Result<()> {
     let storage = WorkoutStorage::new_disk("rowing-workouts").await?;
 
-    let lf = storage.load_workout_lazy("user_abc123", id).await?;
+    // Every stored workout of at least 2k, as one frame.
+    let filter = SummaryFilter {
+        min_distance_m: Some(2000),
+        ..Default::default()
+    };
+    let lf = storage.scan_workouts_lazy("user_abc123", &filter).await?;
 
     tokio::task::block_in_place(|| {
-        let with_rolling = lf
-            .clone()
-            .with_column(
-                col("elapsed_time_ms")
-                    .cast(DataType::Datetime(TimeUnit::Milliseconds, None))
-                    .alias("time"),
-            )
-            .with_column(
-                col("power_watts")
-                    .cast(DataType::Float64)
-                    .rolling_mean_by(
-                        col("time"),
-                        RollingOptionsDynamicWindow {
-                            window_size: Duration::parse("60s"),
-                            min_periods: 1,
-                            closed_window: ClosedWindow::None,
-                            fn_params: Default::default(),
-                        },
-                    )
-                    .alias("power_1min_avg"),
-            );
-
-        let peak_1min = with_rolling
-            .clone()
-            .select([col("power_1min_avg").max()])
-            .collect()?;
-
-        println!("\nPeak 1-minute power: {:?}", peak_1min);
-
-        let splits = lf
+        let per_workout = lf
             .clone()
-            .with_column(
-                (col("distance_m") / lit(500))
-                    .cast(DataType::Int32)
-                    .alias("split_500m"),
-            )
-            .group_by([col("split_500m")])
+            .group_by([col("workout_id"), col("start_time")])
             .agg([
-                col("elapsed_time_ms").first().alias("split_start_ms"),
-                col("elapsed_time_ms").last().alias("split_end_ms"),
-                col("power_watts").mean().alias("split_avg_power"),
-                col("heart_rate_bpm").mean().alias("split_avg_hr"),
+                col("power_watts").mean().alias("avg_power"),
+                col("power_watts").max().alias("max_power"),
+                col("heart_rate_bpm").mean().alias("avg_hr"),
+                col("distance_m").max().alias("distance_m"),
             ])
-            .with_column((col("split_end_ms") - col("split_start_ms")).alias("split_duration_ms"))
-            .sort(["split_500m"], Default::default())
-            .collect()?;
-
-        println!("\n500m Splits:");
-        println!("{}", splits);
-
-        let fastest_split = splits
-            .lazy()
-            .filter(col("split_duration_ms").is_not_null())
-            .sort(["split_duration_ms"], Default::default())
-            .limit(1)
+            .sort(["start_time"], Default::default())
+            .with_column((col("avg_power") - col("avg_power").first()).alias("avg_power_change"))
             .collect()?;
 
-        println!("\nFastest 500m Split:");
-        println!("{}", fastest_split);
+        println!("\nPower trend across workouts:");
+        println!("{}", per_workout);
+
+        let efforts = WorkoutAnalytics::best_efforts(lf)?;
+        println!("\nPower curve:");
+        println!("{}", efforts.power_curve()?);
+        for effort in &efforts.distances {
+            println!(
+                "Fastest {}m: {:.1}s",
+                effort.distance_m,
+                effort.time_ms as f64 / 1000.0
+            );
+        }
+        if let Some(cp) = efforts.critical_power() {
+            println!(
+                "Critical power: {:.0}W, W': {:.0}J",
+                cp.cp_watts, cp.w_prime_j
+            );
+        }
+        let predictor = RacePredictor::from_efforts(&efforts, Ergometer::RowErg);
+        for prediction in predictor.predict_races() {
+            println!(
+                "Predicted {}m ({:?}): {:.1}s",
+                prediction.distance_m,
+                prediction.model,
+                prediction.time_ms as f64 / 1000.0
+            );
+        }
         Ok::<_, anyhow::Error>(())
     })
 }
 
 async fn calculate_race_results() -> anyhow::Result<()> {
-    let race_summaries = vec![
+    let race_summaries = [
         WorkoutSummary {
             workout_id: Uuid::now_v7(),
             user_id: "user1".into(),
@@ -165,6 +166,7 @@ async fn calculate_race_results() -> anyhow::Result<()> {
             avg_pace_ms_per_500m: Some(100000),
             race_id: Some("race123".into()),
             race_position: None,
+            ..Default::default()
         },
         WorkoutSummary {
             workout_id: Uuid::now_v7(),
@@ -181,6 +183,7 @@ async fn calculate_race_results() -> anyhow::Result<()> {
             avg_pace_ms_per_500m: Some(103000),
             race_id: Some("race123".into()),
             race_position: None,
+            ..Default::default()
         },
         WorkoutSummary {
             workout_id: Uuid::now_v7(),
@@ -197,6 +200,7 @@ async fn calculate_race_results() -> anyhow::Result<()> {
             avg_pace_ms_per_500m: Some(98000),
             race_id: Some("race123".into()),
             race_position: None,
+            ..Default::default()
         },
     ];
 
@@ -262,46 +266,26 @@ async fn analyze_stroke_quality(id: Uuid) -> anyhow::Result<()> {
     let storage = WorkoutStorage::new_disk("rowing-workouts").await?;
 
     let lf = storage.load_workout_lazy("user_abc123", id).await?;
+    let profile = storage.load_profile("user_abc123").await?;
 
     tokio::task::block_in_place(|| {
-        let analysis = lf
-            .filter(col("peak_drive_force_n").is_not_null())
-            .with_column(
-                (col("peak_drive_force_n").cast(DataType::Float64)
-                    / col("avg_drive_force_n").cast(DataType::Float64))
-                .alias("force_ratio"),
-            )
-            .with_column(
-                when(col("force_ratio").gt(lit(1.3)))
-                    .then(lit("Uneven force application"))
-                    .when(col("force_ratio").lt(lit(1.1)))
-                    .then(lit("Very consistent"))
-                    .otherwise(lit("Good"))
-                    .alias("technique_rating"),
-            );
-
-        let stats = analysis
-            .clone()
-            .group_by([col("technique_rating")])
-            .agg([
-                col("force_ratio").count().alias("stroke_count"),
-                col("force_ratio").mean().alias("avg_force_ratio"),
-            ])
-            .collect()?;
+        let body_weight_kg = profile.body_weight_kg.map(f64::from);
+        let strokes = WorkoutAnalytics::stroke_technique(lf, body_weight_kg)?;
+        let summary = TechniqueSummary::from_strokes(&strokes);
 
         println!("Stroke Quality Analysis:");
-        println!("{}", stats);
+        println!("{:#?}", summary);
 
-        let problematic = analysis
-            .filter(col("force_ratio").gt(lit(1.4)))
+        let problematic = StrokeTechnique::to_dataframe(&strokes)?
+            .lazy()
+            .filter(col("peak_force_ratio").gt(lit(1.4)))
             .select([
                 col("elapsed_time_ms"),
-                col("peak_drive_force_n"),
-                col("avg_drive_force_n"),
-                col("force_ratio"),
+                col("peak_force_ratio"),
+                col("drive_recovery_ratio"),
             ])
             .sort(
-                ["force_ratio"],
+                ["peak_force_ratio"],
                 SortMultipleOptions::default().with_order_descending(true),
             )
             .limit(10)
@@ -340,31 +324,23 @@ async fn time_series_analysis(id: Uuid) -> anyhow::Result<()> {
             println!("5-Second Interval Summary:");
             println!("{}", resampled);
 
-            // Calculate fatigue index (power drop from first 20% to last 20%)
-            let total_intervals = resampled.height();
-            let first_20pct = (total_intervals as f64 * 0.2) as usize;
-            let last_20pct_start = (total_intervals as f64 * 0.8) as usize;
-
-            let fatigue_analysis = resampled
-                .lazy()
-                .with_column(
-                    when(col("interval_5s").lt(lit(first_20pct as i32)))
-                        .then(lit("first_20pct"))
-                        .when(col("interval_5s").gt(lit(last_20pct_start as i32)))
-                        .then(lit("last_20pct"))
-                        .otherwise(lit("middle"))
-                        .alias("segment"),
-                )
-                .filter(col("segment").neq(lit("middle")))
-                .group_by([col("segment")])
-                .agg([
-                    col("avg_power").mean().alias("segment_avg_power"),
-                    col("avg_hr").mean().alias("segment_avg_hr"),
-                ])
-                .collect()?;
-
-            println!("\nFatigue Analysis (First 20% vs Last 20%):");
-            println!("{}", fatigue_analysis);
+            // Heart rate drift against power, past a 5 minute warm-up.
+            let options = DecouplingOptions {
+                warm_up_ms: 5 * 60 * 1000,
+                ..Default::default()
+            };
+            if let Some(decoupling) = WorkoutAnalytics::aerobic_decoupling(lf.clone(), &options)? {
+                println!(
+                    "\nAerobic decoupling: {:.1}% (EF {:.2}, {})",
+                    decoupling.drift_percent,
+                    decoupling.efficiency_factor,
+                    if decoupling.is_steady {
+                        "steady"
+                    } else {
+                        "not steady"
+                    }
+                );
+            }
 
             // Calculate coefficient of variation for power (consistency metric)
             let power_consistency = lf
@@ -389,7 +365,7 @@ async fn time_series_analysis(id: Uuid) -> anyhow::Result<()> {
 async fn main() -> anyhow::Result<()> {
     let id = record_workout_example().await?;
     analyze_single_workout(id).await?;
-    analyze_user_power_trends(id).await?;
+    analyze_user_power_trends().await?;
     analyze_stroke_quality(id).await?;
     time_series_analysis(id).await?;
     calculate_race_results().await?;
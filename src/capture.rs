@@ -0,0 +1,343 @@
+use std::time::Duration;
+
+use futures::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use time::UtcDateTime;
+use uuid::Uuid;
+
+use crate::services::{Pm5, Pm5Data, ServiceData, ServiceDataError};
+
+/// Written at the start of binary captures, followed by a format version byte.
+const BINARY_MAGIC: &[u8; 6] = b"PM5CAP";
+const BINARY_VERSION: u8 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    #[error("failed to read or write capture")]
+    Io(#[from] std::io::Error),
+    #[error("invalid capture record")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported capture version {0}")]
+    Version(u8),
+    #[error("invalid hex in capture record")]
+    Hex,
+}
+
+/// A notification exactly as it came off the BLE notification stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedNotification {
+    pub uuid: Uuid,
+    /// Unix timestamp in nanoseconds of when the notification was received.
+    pub timestamp: i128,
+    #[serde(with = "hex")]
+    pub value: Vec<u8>,
+}
+
+impl CapturedNotification {
+    pub fn now(uuid: Uuid, value: Vec<u8>) -> Self {
+        Self {
+            uuid,
+            timestamp: UtcDateTime::now().unix_timestamp_nanos(),
+            value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Length-prefixed records behind a small header, for long sessions.
+    Binary,
+    /// One JSON object per line, for captures attached to bug reports.
+    JsonLines,
+}
+
+pub struct CaptureWriter<W> {
+    writer: W,
+    format: CaptureFormat,
+    header_written: bool,
+}
+
+impl<W: AsyncWrite + Unpin> CaptureWriter<W> {
+    pub fn new(writer: W, format: CaptureFormat) -> Self {
+        Self {
+            writer,
+            format,
+            header_written: false,
+        }
+    }
+
+    /// Capture a notification received just now.
+    pub async fn capture(&mut self, uuid: Uuid, value: &[u8]) -> Result<(), CaptureError> {
+        self.write(&CapturedNotification::now(uuid, value.to_vec()))
+            .await
+    }
+
+    pub async fn write(&mut self, notification: &CapturedNotification) -> Result<(), CaptureError> {
+        match self.format {
+            CaptureFormat::Binary => {
+                if !self.header_written {
+                    self.writer.write_all(BINARY_MAGIC).await?;
+                    self.writer.write_all(&[BINARY_VERSION]).await?;
+                    self.header_written = true;
+                }
+                let mut record = Vec::with_capacity(34 + notification.value.len());
+                record.extend_from_slice(&(notification.timestamp as i64).to_le_bytes());
+                record.extend_from_slice(notification.uuid.as_bytes());
+                record.extend_from_slice(&(notification.value.len() as u16).to_le_bytes());
+                record.extend_from_slice(&notification.value);
+                self.writer.write_all(&record).await?;
+            }
+            CaptureFormat::JsonLines => {
+                let mut line = serde_json::to_vec(notification)?;
+                line.push(b'\n');
+                self.writer.write_all(&line).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn close(mut self) -> Result<W, CaptureError> {
+        self.writer.flush().await?;
+        self.writer.close().await?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads captures in either format, detected from the first bytes.
+pub struct CaptureReader<R> {
+    reader: R,
+    format: Option<CaptureFormat>,
+}
+
+impl<R: AsyncBufRead + Unpin> CaptureReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            format: None,
+        }
+    }
+
+    async fn detect_format(&mut self) -> Result<CaptureFormat, CaptureError> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        let buffer = self.reader.fill_buf().await?;
+        let format = if buffer.starts_with(BINARY_MAGIC) {
+            let mut header = [0u8; BINARY_MAGIC.len() + 1];
+            self.reader.read_exact(&mut header).await?;
+            let version = header[BINARY_MAGIC.len()];
+            if version != BINARY_VERSION {
+                return Err(CaptureError::Version(version));
+            }
+            CaptureFormat::Binary
+        } else {
+            CaptureFormat::JsonLines
+        };
+        self.format = Some(format);
+        Ok(format)
+    }
+
+    pub async fn next(&mut self) -> Result<Option<CapturedNotification>, CaptureError> {
+        match self.detect_format().await? {
+            CaptureFormat::Binary => {
+                if self.reader.fill_buf().await?.is_empty() {
+                    return Ok(None);
+                }
+                let mut header = [0u8; 26];
+                self.reader.read_exact(&mut header).await?;
+                let timestamp = i64::from_le_bytes(header[0..8].try_into().unwrap());
+                let uuid = Uuid::from_bytes(header[8..24].try_into().unwrap());
+                let len = u16::from_le_bytes(header[24..26].try_into().unwrap());
+                let mut value = vec![0u8; len as usize];
+                self.reader.read_exact(&mut value).await?;
+                Ok(Some(CapturedNotification {
+                    uuid,
+                    timestamp: timestamp as i128,
+                    value,
+                }))
+            }
+            CaptureFormat::JsonLines => loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).await? == 0 {
+                    return Ok(None);
+                }
+                if !line.trim().is_empty() {
+                    return Ok(Some(serde_json::from_str(&line)?));
+                }
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Keep the gaps between notifications as they were recorded.
+    RealTime,
+    /// Don't wait between notifications.
+    AsFastAsPossible,
+    /// Play back this many times faster than real time.
+    Scaled(f64),
+}
+
+impl ReplaySpeed {
+    fn delay(&self, previous: i128, next: i128) -> Duration {
+        let gap = Duration::from_nanos((next - previous).max(0) as u64);
+        match self {
+            ReplaySpeed::RealTime => gap,
+            ReplaySpeed::AsFastAsPossible => Duration::ZERO,
+            ReplaySpeed::Scaled(factor) if *factor > 0.0 => gap.div_f64(*factor),
+            ReplaySpeed::Scaled(_) => Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplayedNotification {
+    pub notification: CapturedNotification,
+    pub data: Result<Pm5Data, ServiceDataError>,
+}
+
+/// Feeds a capture back through [`Pm5::parse`], pacing the notifications
+/// according to a [`ReplaySpeed`].
+pub struct Replay<R> {
+    reader: CaptureReader<R>,
+    speed: ReplaySpeed,
+    previous_timestamp: Option<i128>,
+}
+
+impl<R: AsyncBufRead + Unpin> Replay<R> {
+    pub fn new(reader: CaptureReader<R>, speed: ReplaySpeed) -> Self {
+        Self {
+            reader,
+            speed,
+            previous_timestamp: None,
+        }
+    }
+
+    pub async fn next(&mut self) -> Result<Option<ReplayedNotification>, CaptureError> {
+        let Some(notification) = self.reader.next().await? else {
+            return Ok(None);
+        };
+        if let Some(previous) = self.previous_timestamp {
+            let delay = self.speed.delay(previous, notification.timestamp);
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        self.previous_timestamp = Some(notification.timestamp);
+
+        let data = Pm5::parse(notification.uuid, notification.value.clone());
+        Ok(Some(ReplayedNotification { notification, data }))
+    }
+}
+
+mod hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        decode(&hex).map_err(serde::de::Error::custom)
+    }
+
+    pub fn decode(hex: &str) -> Result<Vec<u8>, super::CaptureError> {
+        let digit = |c: u8| (c as char).to_digit(16).ok_or(super::CaptureError::Hex);
+        let bytes = hex.as_bytes();
+        if !bytes.len().is_multiple_of(2) {
+            return Err(super::CaptureError::Hex);
+        }
+        bytes
+            .chunks(2)
+            .map(|pair| Ok(((digit(pair[0])? << 4) | digit(pair[1])?) as u8))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::io::Cursor;
+
+    use crate::services::{Rowing, RowingData, Service};
+
+    use super::*;
+
+    fn notifications() -> Vec<CapturedNotification> {
+        let samples = [
+            [
+                186u8, 5, 0, 237, 1, 0, 1, 1, 1, 1, 4, 0, 0, 0, 0, 0, 0, 128, 79,
+            ],
+            [30, 6, 0, 19, 2, 0, 1, 1, 1, 1, 4, 0, 0, 0, 0, 0, 0, 128, 79],
+        ];
+        samples
+            .iter()
+            .enumerate()
+            .map(|(i, value)| CapturedNotification {
+                uuid: Rowing::GeneralStatus.id(),
+                timestamp: 1_700_000_000_000_000_000 + i as i128 * 500_000_000,
+                value: value.to_vec(),
+            })
+            .collect()
+    }
+
+    async fn round_trip(format: CaptureFormat) {
+        let mut writer = CaptureWriter::new(Cursor::new(Vec::new()), format);
+        for notification in notifications() {
+            writer.write(&notification).await.unwrap();
+        }
+        let bytes = writer.close().await.unwrap().into_inner();
+
+        let reader = CaptureReader::new(Cursor::new(bytes));
+        let mut replay = Replay::new(reader, ReplaySpeed::AsFastAsPossible);
+        let mut replayed = Vec::new();
+        while let Some(next) = replay.next().await.unwrap() {
+            assert!(matches!(
+                next.data,
+                Ok(Pm5Data::Rowing(RowingData::GeneralStatus { .. }))
+            ));
+            replayed.push(next.notification);
+        }
+        assert_eq!(replayed, notifications());
+    }
+
+    #[tokio::test]
+    async fn test_binary_round_trip() {
+        round_trip(CaptureFormat::Binary).await;
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_round_trip() {
+        round_trip(CaptureFormat::JsonLines).await;
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex::decode("00ff4f").unwrap(), vec![0, 255, 79]);
+        assert!(matches!(hex::decode("0"), Err(CaptureError::Hex)));
+        assert!(matches!(hex::decode("0g"), Err(CaptureError::Hex)));
+        // Multi-byte characters are not split, and are not hex.
+        assert!(matches!(hex::decode("é0"), Err(CaptureError::Hex)));
+        assert!(matches!(hex::decode("+f"), Err(CaptureError::Hex)));
+    }
+
+    #[test]
+    fn test_replay_delay() {
+        let second = 1_000_000_000;
+        assert_eq!(
+            ReplaySpeed::RealTime.delay(0, second),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            ReplaySpeed::Scaled(4.0).delay(0, second),
+            Duration::from_millis(250)
+        );
+        assert_eq!(
+            ReplaySpeed::AsFastAsPossible.delay(0, second),
+            Duration::ZERO
+        );
+        assert_eq!(ReplaySpeed::RealTime.delay(second, 0), Duration::ZERO);
+    }
+}
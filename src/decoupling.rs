@@ -0,0 +1,187 @@
+//! Aerobic decoupling: how much output per heart beat drops from the first
+//! to the second half of a steady session.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What heart rate is compared against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecouplingBasis {
+    #[default]
+    Power,
+    /// Speed from the pace, in meters per minute.
+    Pace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DecouplingOptions {
+    pub basis: DecouplingBasis,
+    /// Samples this long after the first are left out as warm-up.
+    pub warm_up_ms: u32,
+    /// The largest coefficient of variation of the output for which the
+    /// session still counts as steady.
+    pub max_variation: f64,
+}
+
+impl Default for DecouplingOptions {
+    fn default() -> Self {
+        Self {
+            basis: DecouplingBasis::Power,
+            warm_up_ms: 10 * 60 * 1000,
+            max_variation: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AerobicDecoupling {
+    /// Mean output over mean heart rate, after the warm-up.
+    pub efficiency_factor: f64,
+    pub first_half_efficiency_factor: f64,
+    pub second_half_efficiency_factor: f64,
+    /// How much lower the second half's efficiency factor is, in percent.
+    /// Under 5% is usually taken as aerobically fit for the effort.
+    pub drift_percent: f64,
+    /// The coefficient of variation of the output after the warm-up.
+    pub output_variation: f64,
+    /// Whether the output was steady enough for the drift to mean anything.
+    pub is_steady: bool,
+}
+
+impl AerobicDecoupling {
+    /// Compare the halves of the samples in `df`, split at the midpoint in
+    /// time. `None` without at least two samples with both heart rate and
+    /// output in each half.
+    pub fn from_dataframe(
+        df: &DataFrame,
+        options: &DecouplingOptions,
+    ) -> PolarsResult<Option<Self>> {
+        let elapsed = df.column("elapsed_time_ms")?.cast(&DataType::UInt32)?;
+        let heart_rate = df.column("heart_rate_bpm")?.cast(&DataType::Float64)?;
+        let output = match options.basis {
+            DecouplingBasis::Power => df.column("power_watts")?.cast(&DataType::Float64)?,
+            DecouplingBasis::Pace => df.column("pace_ms_per_500m")?.cast(&DataType::Float64)?,
+        };
+        let (elapsed, heart_rate, output) = (elapsed.u32()?, heart_rate.f64()?, output.f64()?);
+
+        let Some(start) = elapsed.first() else {
+            return Ok(None);
+        };
+        let points: Vec<(u32, f64, f64)> = elapsed
+            .iter()
+            .zip(heart_rate.iter())
+            .zip(output.iter())
+            .filter_map(|((t, hr), output)| {
+                let (t, hr, output) = (t?, hr?, output?);
+                let output = match options.basis {
+                    DecouplingBasis::Power => output,
+                    DecouplingBasis::Pace if output > 0.0 => 500.0 * 60_000.0 / output,
+                    DecouplingBasis::Pace => return None,
+                };
+                (t >= start + options.warm_up_ms && hr > 0.0).then_some((t, hr, output))
+            })
+            .collect();
+        let (Some(first), Some(last)) = (points.first(), points.last()) else {
+            return Ok(None);
+        };
+        let midpoint = first.0 + (last.0 - first.0) / 2;
+        let (first_half, second_half): (Vec<_>, Vec<_>) =
+            points.iter().partition(|(t, _, _)| *t < midpoint);
+        if first_half.len() < 2 || second_half.len() < 2 {
+            return Ok(None);
+        }
+
+        let first_half_efficiency_factor = efficiency_factor(&first_half);
+        let second_half_efficiency_factor = efficiency_factor(&second_half);
+        let outputs: Vec<f64> = points.iter().map(|(_, _, output)| *output).collect();
+        let output_variation = coefficient_of_variation(&outputs);
+        Ok(Some(Self {
+            efficiency_factor: efficiency_factor(&points),
+            first_half_efficiency_factor,
+            second_half_efficiency_factor,
+            drift_percent: (first_half_efficiency_factor - second_half_efficiency_factor)
+                / first_half_efficiency_factor
+                * 100.0,
+            output_variation,
+            is_steady: output_variation <= options.max_variation,
+        }))
+    }
+}
+
+fn efficiency_factor(points: &[(u32, f64, f64)]) -> f64 {
+    let output: f64 = points.iter().map(|(_, _, output)| output).sum();
+    let heart_rate: f64 = points.iter().map(|(_, hr, _)| hr).sum();
+    output / heart_rate
+}
+
+pub(crate) fn coefficient_of_variation(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    if mean == 0.0 {
+        0.0
+    } else {
+        variance.sqrt() / mean
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Steady 200 W for an hour, with heart rate rising from 140 to 154 bpm.
+    fn drifting() -> DataFrame {
+        let elapsed: Vec<u32> = (0..=3600).map(|s| s * 1000).collect();
+        let heart_rate: Vec<u32> = (0..=3600).map(|s| 140 + s * 14 / 3600).collect();
+        df!(
+            "elapsed_time_ms" => elapsed,
+            "heart_rate_bpm" => heart_rate,
+            "power_watts" => vec![200u32; 3601],
+            "pace_ms_per_500m" => vec![120_000u32; 3601],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_drift() {
+        let decoupling = AerobicDecoupling::from_dataframe(&drifting(), &Default::default())
+            .unwrap()
+            .unwrap();
+        assert!(decoupling.is_steady);
+        assert_eq!(decoupling.output_variation, 0.0);
+        assert!(decoupling.drift_percent > 3.0 && decoupling.drift_percent < 6.0);
+        assert!(decoupling.efficiency_factor > 1.3 && decoupling.efficiency_factor < 1.4);
+
+        let options = DecouplingOptions {
+            basis: DecouplingBasis::Pace,
+            ..Default::default()
+        };
+        let by_pace = AerobicDecoupling::from_dataframe(&drifting(), &options)
+            .unwrap()
+            .unwrap();
+        assert!((by_pace.drift_percent - decoupling.drift_percent).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unsteady_and_short() {
+        let df = df!(
+            "elapsed_time_ms" => [0u32, 1000, 2000, 3000],
+            "heart_rate_bpm" => [150u32, 150, 150, 150],
+            "power_watts" => [100u32, 300, 100, 300],
+        )
+        .unwrap();
+        let options = DecouplingOptions {
+            warm_up_ms: 0,
+            ..Default::default()
+        };
+        let decoupling = AerobicDecoupling::from_dataframe(&df, &options)
+            .unwrap()
+            .unwrap();
+        assert!(!decoupling.is_steady);
+
+        assert_eq!(
+            AerobicDecoupling::from_dataframe(&df, &Default::default()).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Drag factor: how it held up over a workout, and the range to set the
+//! damper to for a user, machine and kind of training.
+//!
+//! The drag factor is the PM5's measure of how quickly the flywheel slows
+//! down. The damper sets it, but the same damper setting gives different
+//! drag factors on different machines, so shared machines are best set by
+//! the drag factor shown on the PM5.
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::pace::Ergometer;
+use crate::workout::WorkoutSample;
+
+/// The body weight recommendations are centred on, when none is known.
+const REFERENCE_WEIGHT_KG: f64 = 75.0;
+/// Heavier users are recommended a little more drag, up to a limit.
+const DRAG_PER_KG: f64 = 0.4;
+const MAX_WEIGHT_ADJUSTMENT: f64 = 12.0;
+/// Half the width of a recommended range.
+const RANGE_HALF_WIDTH: f64 = 8.0;
+/// A change in drag factor of more than this within a session means the
+/// damper was moved or the machine changed.
+const MAX_DRIFT: u8 = 10;
+
+/// What a session trains, which shifts the recommended drag factor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrainingFocus {
+    /// Long steady work, best done with a lighter feel.
+    #[default]
+    Endurance,
+    Threshold,
+    /// Short, hard efforts and starts.
+    Sprint,
+}
+
+/// A recommended drag factor range, inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DragRecommendation {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl DragRecommendation {
+    /// The range for a user of `body_weight_kg` training `focus` on
+    /// `ergometer`. These follow the usual coaching guidance: around 110–130
+    /// on the RowErg, less on the SkiErg and BikeErg, and more for heavier
+    /// users and sprint work.
+    pub fn new(ergometer: Ergometer, body_weight_kg: Option<u16>, focus: TrainingFocus) -> Self {
+        let base = match ergometer {
+            Ergometer::RowErg => 120.0,
+            Ergometer::SkiErg => 95.0,
+            Ergometer::BikeErg => 75.0,
+        };
+        let weight = body_weight_kg.map_or(REFERENCE_WEIGHT_KG, f64::from);
+        let weight_adjustment = ((weight - REFERENCE_WEIGHT_KG) * DRAG_PER_KG)
+            .clamp(-MAX_WEIGHT_ADJUSTMENT, MAX_WEIGHT_ADJUSTMENT);
+        let focus_adjustment = match focus {
+            TrainingFocus::Endurance => -5.0,
+            TrainingFocus::Threshold => 0.0,
+            TrainingFocus::Sprint => 10.0,
+        };
+        let centre = base + weight_adjustment + focus_adjustment;
+        Self {
+            min: (centre - RANGE_HALF_WIDTH).round() as u8,
+            max: (centre + RANGE_HALF_WIDTH).round() as u8,
+        }
+    }
+
+    pub fn contains(&self, drag_factor: u8) -> bool {
+        (self.min..=self.max).contains(&drag_factor)
+    }
+}
+
+/// How the drag factor of a session differed from what it should have been.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DragDeviation {
+    /// The average was below the recommended range.
+    TooLow,
+    /// The average was above the recommended range.
+    TooHigh,
+    /// The drag factor changed by more than 10 during the session.
+    Drifted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DragFactorSummary {
+    /// Weighted by how long each drag factor was in effect.
+    pub avg: f64,
+    pub min: u8,
+    pub max: u8,
+    /// The last drag factor minus the first.
+    pub drift: i16,
+}
+
+impl DragFactorSummary {
+    /// Summarize the drag factors recorded with `samples`. `None` if there
+    /// are none. Each drag factor holds until the next, and the last until
+    /// the last sample.
+    pub fn from_samples(samples: &[WorkoutSample]) -> Option<Self> {
+        let readings: Vec<(u32, u8)> = samples
+            .iter()
+            .filter_map(|s| Some((s.elapsed_time_ms, s.drag_factor?)))
+            .collect();
+        let (first, last) = (readings.first()?, readings.last()?);
+        let end_ms = samples.last().map_or(last.0, |s| s.elapsed_time_ms);
+        let until = readings.iter().skip(1).map(|(ms, _)| *ms).chain([end_ms]);
+        let (mut weighted, mut time_ms) = (0.0, 0.0);
+        for ((since_ms, drag_factor), until_ms) in readings.iter().zip(until) {
+            let held_ms = until_ms.saturating_sub(*since_ms) as f64;
+            weighted += held_ms * *drag_factor as f64;
+            time_ms += held_ms;
+        }
+        let avg = if time_ms > 0.0 {
+            weighted / time_ms
+        } else {
+            readings.iter().map(|(_, d)| *d as f64).sum::<f64>() / readings.len() as f64
+        };
+        Some(Self {
+            avg,
+            min: readings.iter().map(|(_, d)| *d).min()?,
+            max: readings.iter().map(|(_, d)| *d).max()?,
+            drift: last.1 as i16 - first.1 as i16,
+        })
+    }
+
+    /// For a workout with only its average drag factor, such as one whose
+    /// samples were dropped. How it changed can't be told.
+    pub fn from_average(avg_drag_factor: u8) -> Self {
+        Self {
+            avg: avg_drag_factor as f64,
+            min: avg_drag_factor,
+            max: avg_drag_factor,
+            drift: 0,
+        }
+    }
+
+    /// Ways the session's drag factor deviated from `recommendation`, or
+    /// from itself.
+    pub fn deviations(&self, recommendation: &DragRecommendation) -> Vec<DragDeviation> {
+        let mut deviations = Vec::new();
+        let avg = self.avg.round() as u8;
+        if avg < recommendation.min {
+            deviations.push(DragDeviation::TooLow);
+        } else if avg > recommendation.max {
+            deviations.push(DragDeviation::TooHigh);
+        }
+        if self.max - self.min > MAX_DRIFT {
+            deviations.push(DragDeviation::Drifted);
+        }
+        deviations
+    }
+}
+
+/// A workout's drag factor against the range recommended for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DragFactorReport {
+    pub workout_id: Uuid,
+    pub start_time: i128,
+    pub ergometer: Ergometer,
+    pub summary: DragFactorSummary,
+    pub recommendation: DragRecommendation,
+    pub deviations: Vec<DragDeviation>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recommendation() {
+        let row = DragRecommendation::new(Ergometer::RowErg, None, TrainingFocus::Threshold);
+        assert_eq!(row, DragRecommendation { min: 112, max: 128 });
+        assert!(row.contains(120));
+        assert!(!row.contains(130));
+
+        let heavy_sprint =
+            DragRecommendation::new(Ergometer::RowErg, Some(120), TrainingFocus::Sprint);
+        assert_eq!(heavy_sprint.min, 134);
+        let light_endurance =
+            DragRecommendation::new(Ergometer::RowErg, Some(55), TrainingFocus::Endurance);
+        assert_eq!(light_endurance.min, 99);
+        let ski = DragRecommendation::new(Ergometer::SkiErg, None, TrainingFocus::Threshold);
+        assert!(ski.max < row.min);
+    }
+
+    #[test]
+    fn test_summary_and_deviations() {
+        let samples: Vec<_> = [
+            (0, Some(120)),
+            (1000, None),
+            (60_000, Some(118)),
+            (90_000, Some(135)),
+            (120_000, None),
+        ]
+        .into_iter()
+        .map(|(elapsed_time_ms, drag_factor)| WorkoutSample {
+            elapsed_time_ms,
+            drag_factor,
+            ..Default::default()
+        })
+        .collect();
+        let summary = DragFactorSummary::from_samples(&samples).unwrap();
+        // 120 for 60 s, then 118 and 135 for 30 s each.
+        let avg = (120.0 * 60.0 + 118.0 * 30.0 + 135.0 * 30.0) / 120.0;
+        assert!((summary.avg - avg).abs() < 1e-9);
+        assert_eq!((summary.min, summary.max, summary.drift), (118, 135, 15));
+
+        let recommendation = DragRecommendation { min: 100, max: 115 };
+        assert_eq!(
+            summary.deviations(&recommendation),
+            vec![DragDeviation::TooHigh, DragDeviation::Drifted]
+        );
+        assert!(DragFactorSummary::from_samples(&samples[1..2]).is_none());
+        // A single reading at the end of the workout.
+        let last = DragFactorSummary::from_samples(&samples[3..4]).unwrap();
+        assert_eq!(last, DragFactorSummary::from_average(135));
+    }
+}
@@ -0,0 +1,448 @@
+//! Best efforts: maximal mean power over standard durations, fastest times
+//! over standard distances, and a critical power model fitted to them.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::workout::WorkoutSample;
+
+/// Durations of the power curve, in seconds.
+pub const EFFORT_DURATIONS_S: [u32; 11] = [1, 5, 10, 30, 60, 120, 300, 600, 1200, 1800, 3600];
+/// Distances with a fastest time, in meters. The last is a half marathon.
+pub const EFFORT_DISTANCES_M: [u32; 8] = [100, 500, 1000, 2000, 5000, 6000, 10000, 21097];
+/// Times with a most distance, in seconds.
+pub const EFFORT_TIMES_S: [u32; 4] = [60, 240, 1800, 3600];
+
+/// Efforts of 2–20 minutes are used to fit the critical power model.
+/// Shorter ones are limited by more than W′, longer ones by fatigue.
+const CP_MIN_DURATION_S: u32 = 120;
+const CP_MAX_DURATION_S: u32 = 1200;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PowerEffort {
+    pub duration_s: u32,
+    pub avg_power_watts: f64,
+    /// Elapsed time at the start of the effort.
+    pub start_ms: u32,
+    /// The workout the effort comes from, when computed over many.
+    pub workout_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistanceEffort {
+    pub distance_m: u32,
+    pub time_ms: u32,
+    /// Elapsed time at the start of the effort.
+    pub start_ms: u32,
+    /// The workout the effort comes from, when computed over many.
+    pub workout_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DurationEffort {
+    pub duration_s: u32,
+    pub distance_m: u32,
+    /// Elapsed time at the start of the effort.
+    pub start_ms: u32,
+    /// The workout the effort comes from, when computed over many.
+    pub workout_id: Option<String>,
+}
+
+/// The two-parameter critical power model: power `cp_watts` can be held
+/// indefinitely, and `w_prime_j` joules of work can be done above it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CriticalPower {
+    pub cp_watts: f64,
+    pub w_prime_j: f64,
+}
+
+impl CriticalPower {
+    /// The power the model predicts can be held for `duration_s`.
+    pub fn power_for(&self, duration_s: f64) -> f64 {
+        self.cp_watts + self.w_prime_j / duration_s
+    }
+}
+
+/// The best efforts of one or more workouts. Durations and distances longer
+/// than the workouts are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BestEfforts {
+    /// Maximal mean power for each standard duration.
+    pub power: Vec<PowerEffort>,
+    /// Fastest time for each standard distance.
+    pub distances: Vec<DistanceEffort>,
+    /// Most distance for each standard time.
+    pub durations: Vec<DurationEffort>,
+}
+
+impl BestEfforts {
+    /// The best efforts of a single workout.
+    pub fn from_samples(samples: &[WorkoutSample]) -> Self {
+        Track::from_samples(samples).best_efforts(None)
+    }
+
+    /// The best efforts over the samples in `df`. With a `workout_id` column,
+    /// as from [`crate::workout::WorkoutStorage::scan_workouts_lazy`], each
+    /// workout is searched separately and the best of them kept.
+    pub fn from_dataframe(df: &DataFrame) -> PolarsResult<Self> {
+        let elapsed = df.column("elapsed_time_ms")?.cast(&DataType::UInt32)?;
+        let distance = df.column("distance_m")?.cast(&DataType::UInt32)?;
+        let power = df.column("power_watts")?.cast(&DataType::Float64)?;
+        let (elapsed, distance, power) = (elapsed.u32()?, distance.u32()?, power.f64()?);
+        let workout_ids: Vec<Option<&str>> = match df.column("workout_id") {
+            Ok(ids) => ids.str()?.iter().collect(),
+            Err(_) => vec![None; df.height()],
+        };
+
+        let mut best = BestEfforts::default();
+        let mut start = 0;
+        while start < df.height() {
+            let id = workout_ids[start];
+            let end = (start..df.height())
+                .find(|&i| workout_ids[i] != id)
+                .unwrap_or(df.height());
+            let track = Track {
+                elapsed_ms: (start..end).map(|i| elapsed.get(i).unwrap_or(0)).collect(),
+                distance_m: (start..end).map(|i| distance.get(i).unwrap_or(0)).collect(),
+                power_watts: (start..end).map(|i| power.get(i)).collect(),
+            };
+            best.merge(track.best_efforts(id));
+            start = end;
+        }
+        Ok(best)
+    }
+
+    /// Keep the better of each effort.
+    pub fn merge(&mut self, other: BestEfforts) {
+        for effort in other.power {
+            match self
+                .power
+                .iter_mut()
+                .find(|e| e.duration_s == effort.duration_s)
+            {
+                Some(e) if e.avg_power_watts >= effort.avg_power_watts => {}
+                Some(e) => *e = effort,
+                None => self.power.push(effort),
+            }
+        }
+        for effort in other.distances {
+            match self
+                .distances
+                .iter_mut()
+                .find(|e| e.distance_m == effort.distance_m)
+            {
+                Some(e) if e.time_ms <= effort.time_ms => {}
+                Some(e) => *e = effort,
+                None => self.distances.push(effort),
+            }
+        }
+        for effort in other.durations {
+            match self
+                .durations
+                .iter_mut()
+                .find(|e| e.duration_s == effort.duration_s)
+            {
+                Some(e) if e.distance_m >= effort.distance_m => {}
+                Some(e) => *e = effort,
+                None => self.durations.push(effort),
+            }
+        }
+        self.power.sort_by_key(|e| e.duration_s);
+        self.distances.sort_by_key(|e| e.distance_m);
+        self.durations.sort_by_key(|e| e.duration_s);
+    }
+
+    /// Fit the critical power model to the 2–20 minute efforts, by linear
+    /// regression of work against duration. `None` with fewer than two such
+    /// efforts or a fit that makes no physical sense.
+    pub fn critical_power(&self) -> Option<CriticalPower> {
+        let points: Vec<(f64, f64)> = self
+            .power
+            .iter()
+            .filter(|e| (CP_MIN_DURATION_S..=CP_MAX_DURATION_S).contains(&e.duration_s))
+            .map(|e| {
+                let t = e.duration_s as f64;
+                (t, e.avg_power_watts * t)
+            })
+            .collect();
+        if points.len() < 2 {
+            return None;
+        }
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_w = points.iter().map(|(_, w)| w).sum::<f64>() / n;
+        let covariance: f64 = points
+            .iter()
+            .map(|(t, w)| (t - mean_t) * (w - mean_w))
+            .sum();
+        let variance: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        let cp_watts = covariance / variance;
+        let w_prime_j = mean_w - cp_watts * mean_t;
+        (cp_watts > 0.0 && w_prime_j >= 0.0).then_some(CriticalPower {
+            cp_watts,
+            w_prime_j,
+        })
+    }
+
+    /// The power curve as `duration_s` and `avg_power_watts` columns, for
+    /// charting.
+    pub fn power_curve(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "duration_s" => self.power.iter().map(|e| e.duration_s).collect::<Vec<_>>(),
+            "avg_power_watts" => self.power.iter().map(|e| e.avg_power_watts).collect::<Vec<_>>(),
+        )
+    }
+}
+
+/// Power for each whole second of a workout, each sample's power holding
+/// until the next sample with one.
+pub(crate) fn power_per_second(samples: &[WorkoutSample]) -> Vec<f64> {
+    Track::from_samples(samples).power_per_second()
+}
+
+/// A single workout's samples, in order.
+struct Track {
+    elapsed_ms: Vec<u32>,
+    distance_m: Vec<u32>,
+    power_watts: Vec<Option<f64>>,
+}
+
+impl Track {
+    fn from_samples(samples: &[WorkoutSample]) -> Self {
+        Track {
+            elapsed_ms: samples.iter().map(|s| s.elapsed_time_ms).collect(),
+            distance_m: samples.iter().map(|s| s.distance_m).collect(),
+            power_watts: samples
+                .iter()
+                .map(|s| s.power_watts.map(f64::from))
+                .collect(),
+        }
+    }
+
+    fn best_efforts(&self, workout_id: Option<&str>) -> BestEfforts {
+        let workout_id = workout_id.map(str::to_owned);
+        let first_ms = self.elapsed_ms.first().copied().unwrap_or(0);
+        let curve = self.power_per_second();
+        let mut energy = Vec::with_capacity(curve.len() + 1);
+        energy.push(0.0);
+        for watts in &curve {
+            energy.push(energy.last().unwrap_or(&0.0) + watts);
+        }
+
+        let power = EFFORT_DURATIONS_S
+            .iter()
+            .filter(|&&d| d as usize <= curve.len())
+            .map(|&duration_s| {
+                let d = duration_s as usize;
+                let (start, best) = (0..=curve.len() - d)
+                    .map(|i| (i, energy[i + d] - energy[i]))
+                    .fold(
+                        (0, 0.0),
+                        |best, window| {
+                            if window.1 > best.1 {
+                                window
+                            } else {
+                                best
+                            }
+                        },
+                    );
+                PowerEffort {
+                    duration_s,
+                    avg_power_watts: best / duration_s as f64,
+                    start_ms: first_ms + start as u32 * 1000,
+                    workout_id: workout_id.clone(),
+                }
+            })
+            .collect();
+        let distances = EFFORT_DISTANCES_M
+            .iter()
+            .filter_map(|&distance_m| {
+                let (start_ms, time_ms) = self.fastest_time_ms(distance_m)?;
+                Some(DistanceEffort {
+                    distance_m,
+                    time_ms,
+                    start_ms,
+                    workout_id: workout_id.clone(),
+                })
+            })
+            .collect();
+        let durations = EFFORT_TIMES_S
+            .iter()
+            .filter_map(|&duration_s| {
+                let (start_ms, distance_m) = self.most_distance_m(duration_s)?;
+                Some(DurationEffort {
+                    duration_s,
+                    distance_m,
+                    start_ms,
+                    workout_id: workout_id.clone(),
+                })
+            })
+            .collect();
+        BestEfforts {
+            power,
+            distances,
+            durations,
+        }
+    }
+
+    /// Power for each whole second of the workout, each sample's power
+    /// holding until the next sample with one.
+    fn power_per_second(&self) -> Vec<f64> {
+        let (Some(&first), Some(&last)) = (self.elapsed_ms.first(), self.elapsed_ms.last()) else {
+            return Vec::new();
+        };
+        let seconds = (last.saturating_sub(first) / 1000) as usize;
+        let mut curve = Vec::with_capacity(seconds);
+        let mut next = 0;
+        let mut watts = 0.0;
+        for s in 0..seconds {
+            let t = first + s as u32 * 1000;
+            while next < self.elapsed_ms.len() && self.elapsed_ms[next] <= t {
+                if let Some(w) = self.power_watts[next] {
+                    watts = w;
+                }
+                next += 1;
+            }
+            curve.push(watts);
+        }
+        curve
+    }
+
+    /// The shortest time to cover `distance_m` from any sample, interpolating
+    /// between samples where it is reached, with the sample's elapsed time.
+    fn fastest_time_ms(&self, distance_m: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(u32, f64)> = None;
+        let mut end = 0;
+        for start in 0..self.distance_m.len() {
+            let target = self.distance_m[start] + distance_m;
+            while end < self.distance_m.len() && self.distance_m[end] < target {
+                end += 1;
+            }
+            if end == self.distance_m.len() {
+                break;
+            }
+            let (d0, d1) = (self.distance_m[end - 1], self.distance_m[end]);
+            let (t0, t1) = (self.elapsed_ms[end - 1], self.elapsed_ms[end]);
+            let fraction = (target - d0) as f64 / (d1 - d0) as f64;
+            let reached = t0 as f64 + fraction * (t1 - t0) as f64;
+            let time = reached - self.elapsed_ms[start] as f64;
+            if best.is_none_or(|(_, b)| time < b) {
+                best = Some((self.elapsed_ms[start], time));
+            }
+        }
+        best.map(|(start_ms, ms)| (start_ms, ms.round() as u32))
+    }
+
+    /// The most distance covered in `duration_s` from any sample,
+    /// interpolating between samples where the time runs out, with the
+    /// sample's elapsed time.
+    fn most_distance_m(&self, duration_s: u32) -> Option<(u32, u32)> {
+        let mut best: Option<(u32, f64)> = None;
+        let mut end = 0;
+        for start in 0..self.elapsed_ms.len() {
+            let target = self.elapsed_ms[start] + duration_s * 1000;
+            while end < self.elapsed_ms.len() && self.elapsed_ms[end] < target {
+                end += 1;
+            }
+            if end == self.elapsed_ms.len() {
+                break;
+            }
+            let (t0, t1) = (self.elapsed_ms[end - 1], self.elapsed_ms[end]);
+            let (d0, d1) = (self.distance_m[end - 1], self.distance_m[end]);
+            let fraction = (target - t0) as f64 / (t1 - t0) as f64;
+            let covered = d0 as f64 + fraction * d1.saturating_sub(d0) as f64;
+            let distance = covered - self.distance_m[start] as f64;
+            if best.is_none_or(|(_, b)| distance > b) {
+                best = Some((self.elapsed_ms[start], distance));
+            }
+        }
+        best.map(|(start_ms, m)| (start_ms, m.round() as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(elapsed_s: u32, distance_m: u32, power_watts: u16) -> WorkoutSample {
+        WorkoutSample {
+            elapsed_time_ms: elapsed_s * 1000,
+            distance_m,
+            power_watts: Some(power_watts),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_best_efforts() {
+        // 200 W at 4 m/s for a minute, then 400 W at 5 m/s for 30 seconds.
+        let mut samples: Vec<_> = (0..60).map(|s| sample(s, s * 4, 200)).collect();
+        samples.extend((60..=90).map(|s| sample(s, 240 + (s - 60) * 5, 400)));
+
+        let efforts = BestEfforts::from_samples(&samples);
+        let power: Vec<_> = efforts
+            .power
+            .iter()
+            .map(|e| (e.duration_s, e.avg_power_watts))
+            .collect();
+        assert_eq!(
+            power,
+            [
+                (1, 400.0),
+                (5, 400.0),
+                (10, 400.0),
+                (30, 400.0),
+                (60, 300.0)
+            ]
+        );
+        assert_eq!(efforts.distances.len(), 1);
+        assert_eq!(efforts.distances[0].distance_m, 100);
+        assert_eq!(efforts.distances[0].time_ms, 20_000);
+        assert_eq!(efforts.distances[0].start_ms, 60_000);
+        assert_eq!(efforts.power[3].start_ms, 60_000);
+        assert_eq!(efforts.durations.len(), 1);
+        assert_eq!(efforts.durations[0].distance_m, 270);
+    }
+
+    #[test]
+    fn test_from_dataframe_per_workout() {
+        let df = df!(
+            "workout_id" => ["a", "a", "a", "b", "b", "b"],
+            "elapsed_time_ms" => [0u32, 1000, 2000, 0, 1000, 2000],
+            "distance_m" => [0u32, 50, 100, 0, 60, 120],
+            "power_watts" => [Some(100u32), Some(100), Some(100), Some(300), None, Some(300)],
+        )
+        .unwrap();
+        let efforts = BestEfforts::from_dataframe(&df).unwrap();
+        assert_eq!(efforts.power[0].avg_power_watts, 300.0);
+        assert_eq!(efforts.power[0].workout_id.as_deref(), Some("b"));
+        // "b" covers 100m fastest, between its last two samples.
+        assert_eq!(efforts.distances[0].time_ms, 1667);
+    }
+
+    #[test]
+    fn test_critical_power() {
+        let model = CriticalPower {
+            cp_watts: 250.0,
+            w_prime_j: 18_000.0,
+        };
+        let efforts = BestEfforts {
+            power: [60, 180, 300, 720, 1200, 3600]
+                .into_iter()
+                .map(|duration_s| PowerEffort {
+                    duration_s,
+                    avg_power_watts: model.power_for(duration_s as f64),
+                    start_ms: 0,
+                    workout_id: None,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let fitted = efforts.critical_power().unwrap();
+        assert!((fitted.cp_watts - 250.0).abs() < 1e-6);
+        assert!((fitted.w_prime_j - 18_000.0).abs() < 1e-3);
+        assert_eq!(efforts.power_curve().unwrap().height(), 6);
+
+        assert_eq!(BestEfforts::default().critical_power(), None);
+    }
+}
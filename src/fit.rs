@@ -0,0 +1,796 @@
+//! Garmin FIT activity files.
+//!
+//! Only the messages needed for indoor workouts are written: `file_id`,
+//! `record`, `lap`, `session` and `activity`. Stroke metrics without a FIT
+//! profile field are stored as developer fields named after the sample
+//! columns, so they survive a round trip.
+
+use std::collections::HashMap;
+
+use crate::pace::Ergometer;
+use crate::types::ErgMachineType;
+use crate::workout::{IntervalSummary, WorkoutSample, WorkoutSummary};
+
+/// Seconds between the Unix epoch and the FIT epoch, 1989-12-31T00:00:00Z.
+const FIT_EPOCH_OFFSET_S: i128 = 631_065_600;
+const PROTOCOL_VERSION: u8 = 0x20;
+const PROFILE_VERSION: u16 = 2132;
+/// Manufacturer id the FIT profile assigns to Concept2.
+const MANUFACTURER_CONCEPT2: u16 = 40;
+/// Identifies this crate as the source of the developer fields.
+const APPLICATION_ID: [u8; 16] = *b"pm5-workout-fit1";
+
+const MESG_FILE_ID: u16 = 0;
+const MESG_SESSION: u16 = 18;
+const MESG_LAP: u16 = 19;
+const MESG_RECORD: u16 = 20;
+const MESG_ACTIVITY: u16 = 34;
+const MESG_FIELD_DESCRIPTION: u16 = 206;
+const MESG_DEVELOPER_DATA_ID: u16 = 207;
+
+const FIELD_TIMESTAMP: u8 = 253;
+const FIELD_MESSAGE_INDEX: u8 = 254;
+
+const SPORT_CYCLING: u8 = 2;
+const SPORT_CROSS_COUNTRY_SKIING: u8 = 12;
+const SPORT_ROWING: u8 = 15;
+const SUB_SPORT_GENERIC: u8 = 0;
+const SUB_SPORT_INDOOR_CYCLING: u8 = 6;
+const SUB_SPORT_INDOOR_ROWING: u8 = 14;
+
+/// Stroke metrics written as developer fields, by field number.
+const DEVELOPER_FIELDS: [(&str, BaseType, &str); 9] = [
+    ("elapsed_time_ms", BaseType::Uint32, "ms"),
+    ("drive_length_cm", BaseType::Uint16, "cm"),
+    ("drive_time_ms", BaseType::Uint16, "ms"),
+    ("peak_drive_force_n", BaseType::Uint16, "N"),
+    ("avg_drive_force_n", BaseType::Uint16, "N"),
+    ("work_per_stroke_j", BaseType::Uint16, "J"),
+    ("recovery_time_ms", BaseType::Uint32, "ms"),
+    ("stroke_distance_cm", BaseType::Uint16, "cm"),
+    ("drag_factor", BaseType::Uint8, ""),
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum FitError {
+    #[error("not a FIT file")]
+    InvalidHeader,
+    #[error("FIT file is truncated")]
+    UnexpectedEnd,
+    #[error("FIT file CRC mismatch")]
+    Crc,
+    #[error("data message for undefined local message type {0}")]
+    UndefinedLocalMessage(u8),
+    #[error("FIT file has no records")]
+    NoRecords,
+}
+
+/// A workout read from a FIT file.
+#[derive(Debug, Clone, Default)]
+pub struct FitActivity {
+    /// Unix timestamp in nanoseconds.
+    pub start_time: i128,
+    pub machine_type: Option<ErgMachineType>,
+    pub samples: Vec<WorkoutSample>,
+    pub laps: Vec<IntervalSummary>,
+    pub total_calories: Option<u16>,
+    pub total_strokes: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BaseType {
+    Enum,
+    Uint8,
+    Uint16,
+    Uint32,
+    String,
+    Byte,
+}
+
+impl BaseType {
+    fn id(self) -> u8 {
+        match self {
+            BaseType::Enum => 0x00,
+            BaseType::Uint8 => 0x02,
+            BaseType::Uint16 => 0x84,
+            BaseType::Uint32 => 0x86,
+            BaseType::String => 0x07,
+            BaseType::Byte => 0x0D,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Enum(u8),
+    Uint8(u8),
+    Uint16(u16),
+    Uint32(u32),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    fn base_type(&self) -> BaseType {
+        match self {
+            Value::Enum(_) => BaseType::Enum,
+            Value::Uint8(_) => BaseType::Uint8,
+            Value::Uint16(_) => BaseType::Uint16,
+            Value::Uint32(_) => BaseType::Uint32,
+            Value::String(_) => BaseType::String,
+            Value::Bytes(_) => BaseType::Byte,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Value::Enum(v) | Value::Uint8(v) => vec![*v],
+            Value::Uint16(v) => v.to_le_bytes().to_vec(),
+            Value::Uint32(v) => v.to_le_bytes().to_vec(),
+            Value::String(v) => {
+                let mut bytes = v.as_bytes().to_vec();
+                bytes.push(0);
+                bytes
+            }
+            Value::Bytes(v) => v.clone(),
+        }
+    }
+}
+
+/// Writes messages, emitting a definition whenever a message's layout changes.
+struct Encoder {
+    data: Vec<u8>,
+    definitions: HashMap<u8, Vec<(u8, u8, u8)>>,
+}
+
+impl Encoder {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            definitions: HashMap::new(),
+        }
+    }
+
+    fn write(
+        &mut self,
+        global: u16,
+        fields: &[(u8, Option<Value>)],
+        developer: &[(u8, Option<Value>)],
+    ) {
+        let local = local_message_type(global);
+        let fields: Vec<_> = fields
+            .iter()
+            .filter_map(|(num, value)| Some((*num, value.as_ref()?)))
+            .collect();
+        let developer: Vec<_> = developer
+            .iter()
+            .filter_map(|(num, value)| Some((*num, value.as_ref()?)))
+            .collect();
+
+        // Developer fields are marked with base type 0xFF, as their types
+        // come from the field descriptions.
+        let layout: Vec<(u8, u8, u8)> = fields
+            .iter()
+            .map(|(num, value)| (*num, value.encode().len() as u8, value.base_type().id()))
+            .chain(
+                developer
+                    .iter()
+                    .map(|(num, value)| (*num, value.encode().len() as u8, 0xFF)),
+            )
+            .collect();
+        if self.definitions.get(&local) != Some(&layout) {
+            let has_developer = !developer.is_empty();
+            let developer_flag = if has_developer { 0x20 } else { 0 };
+            self.data.push(0x40 | developer_flag | local);
+            self.data.push(0);
+            self.data.push(0); // little endian
+            self.data.extend_from_slice(&global.to_le_bytes());
+            self.data.push(fields.len() as u8);
+            for (num, size, base_type) in &layout[..fields.len()] {
+                self.data.extend_from_slice(&[*num, *size, *base_type]);
+            }
+            if has_developer {
+                self.data.push(developer.len() as u8);
+                for (num, size, _) in &layout[fields.len()..] {
+                    self.data.extend_from_slice(&[*num, *size, 0]);
+                }
+            }
+            self.definitions.insert(local, layout);
+        }
+
+        self.data.push(local);
+        for (_, value) in fields.iter().chain(developer.iter()) {
+            self.data.extend(value.encode());
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(self.data.len() + 16);
+        file.push(14);
+        file.push(PROTOCOL_VERSION);
+        file.extend_from_slice(&PROFILE_VERSION.to_le_bytes());
+        file.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b".FIT");
+        let header_crc = crc(&file);
+        file.extend_from_slice(&header_crc.to_le_bytes());
+        file.extend(self.data);
+        let file_crc = crc(&file);
+        file.extend_from_slice(&file_crc.to_le_bytes());
+        file
+    }
+}
+
+fn local_message_type(global: u16) -> u8 {
+    match global {
+        MESG_FILE_ID => 0,
+        MESG_DEVELOPER_DATA_ID => 1,
+        MESG_FIELD_DESCRIPTION => 2,
+        MESG_RECORD => 3,
+        MESG_LAP => 4,
+        MESG_SESSION => 5,
+        _ => 6,
+    }
+}
+
+fn sport(ergometer: Ergometer) -> (u8, u8) {
+    match ergometer {
+        Ergometer::BikeErg => (SPORT_CYCLING, SUB_SPORT_INDOOR_CYCLING),
+        Ergometer::SkiErg => (SPORT_CROSS_COUNTRY_SKIING, SUB_SPORT_GENERIC),
+        Ergometer::RowErg => (SPORT_ROWING, SUB_SPORT_INDOOR_ROWING),
+    }
+}
+
+fn machine_type(sport: u8) -> Option<ErgMachineType> {
+    let ergometer = match sport {
+        SPORT_ROWING => Ergometer::RowErg,
+        SPORT_CROSS_COUNTRY_SKIING => Ergometer::SkiErg,
+        SPORT_CYCLING => Ergometer::BikeErg,
+        _ => return None,
+    };
+    Some(ergometer.machine_type())
+}
+
+fn fit_time(unix_nanos: i128) -> Value {
+    Value::Uint32((unix_nanos / 1_000_000_000 - FIT_EPOCH_OFFSET_S) as u32)
+}
+
+/// m/s scaled by 1000, from a pace per 500m.
+fn speed(pace_ms_per_500m: Option<u32>) -> Option<Value> {
+    pace_ms_per_500m
+        .filter(|pace| *pace > 0)
+        .map(|pace| Value::Uint16((500_000_000 / pace).min(u16::MAX as u32 - 1) as u16))
+}
+
+/// Encode a workout as a FIT activity file.
+pub fn encode(summary: &WorkoutSummary, samples: &[WorkoutSample]) -> Vec<u8> {
+    let (sport, sub_sport) = sport(summary.ergometer());
+    let end_time = samples
+        .last()
+        .map_or(summary.end_time, |s| s.timestamp.max(summary.end_time));
+    let mut encoder = Encoder::new();
+
+    encoder.write(
+        MESG_FILE_ID,
+        &[
+            (0, Some(Value::Enum(4))), // activity
+            (1, Some(Value::Uint16(MANUFACTURER_CONCEPT2))),
+            (4, Some(fit_time(summary.start_time))),
+        ],
+        &[],
+    );
+
+    encoder.write(
+        MESG_DEVELOPER_DATA_ID,
+        &[
+            (1, Some(Value::Bytes(APPLICATION_ID.to_vec()))),
+            (3, Some(Value::Uint8(0))),
+        ],
+        &[],
+    );
+    for (num, (name, base_type, units)) in DEVELOPER_FIELDS.iter().enumerate() {
+        encoder.write(
+            MESG_FIELD_DESCRIPTION,
+            &[
+                (0, Some(Value::Uint8(0))),
+                (1, Some(Value::Uint8(num as u8))),
+                (2, Some(Value::Uint8(base_type.id()))),
+                (3, Some(Value::String(name.to_string()))),
+                (8, Some(Value::String(units.to_string()))),
+            ],
+            &[],
+        );
+    }
+
+    for sample in samples {
+        encoder.write(
+            MESG_RECORD,
+            &[
+                (FIELD_TIMESTAMP, Some(fit_time(sample.timestamp))),
+                (3, sample.heart_rate_bpm.map(Value::Uint8)),
+                (4, sample.stroke_rate.map(Value::Uint8)),
+                (5, Some(Value::Uint32(sample.distance_m * 100))),
+                (6, speed(sample.pace_ms_per_500m)),
+                (7, sample.power_watts.map(Value::Uint16)),
+                (33, sample.calories.map(Value::Uint16)),
+            ],
+            &[
+                (0, Some(Value::Uint32(sample.elapsed_time_ms))),
+                (1, sample.drive_length_cm.map(Value::Uint16)),
+                (2, sample.drive_time_ms.map(Value::Uint16)),
+                (3, sample.peak_drive_force_n.map(Value::Uint16)),
+                (4, sample.avg_drive_force_n.map(Value::Uint16)),
+                (5, sample.work_per_stroke_j.map(Value::Uint16)),
+                (6, sample.recovery_time_ms.map(Value::Uint32)),
+                (7, sample.stroke_distance_cm.map(Value::Uint16)),
+                (8, sample.drag_factor.map(Value::Uint8)),
+            ],
+        );
+    }
+
+    let laps = summary.laps(samples);
+    for (index, lap) in laps.iter().enumerate() {
+        let lap_end = lap.samples.last().map_or(end_time, |s| s.timestamp);
+        let elapsed_ms = lap.end_ms.saturating_sub(lap.start_ms);
+        encoder.write(
+            MESG_LAP,
+            &[
+                (FIELD_MESSAGE_INDEX, Some(Value::Uint16(index as u16))),
+                (FIELD_TIMESTAMP, Some(fit_time(lap_end))),
+                (0, Some(Value::Enum(9))), // lap
+                (1, Some(Value::Enum(1))), // stop
+                (2, Some(fit_time(lap.start_time))),
+                (7, Some(Value::Uint32(elapsed_ms))),
+                (8, Some(Value::Uint32(elapsed_ms))),
+                (9, Some(Value::Uint32(lap.distance_m * 100))),
+                (11, Some(Value::Uint16(lap.calories))),
+                (15, lap.avg_heart_rate_bpm.map(Value::Uint8)),
+                (16, lap.max_heart_rate_bpm.map(Value::Uint8)),
+                (17, lap.avg_stroke_rate.map(Value::Uint8)),
+                (19, lap.avg_power_watts.map(Value::Uint16)),
+                (25, Some(Value::Enum(sport))),
+                (39, Some(Value::Enum(sub_sport))),
+            ],
+            &[],
+        );
+    }
+
+    encoder.write(
+        MESG_SESSION,
+        &[
+            (FIELD_MESSAGE_INDEX, Some(Value::Uint16(0))),
+            (FIELD_TIMESTAMP, Some(fit_time(end_time))),
+            (0, Some(Value::Enum(8))), // session
+            (1, Some(Value::Enum(1))), // stop
+            (2, Some(fit_time(summary.start_time))),
+            (5, Some(Value::Enum(sport))),
+            (6, Some(Value::Enum(sub_sport))),
+            (7, Some(Value::Uint32(summary.duration_ms))),
+            (8, Some(Value::Uint32(summary.duration_ms))),
+            (9, Some(Value::Uint32(summary.total_distance_m * 100))),
+            (10, summary.total_strokes.map(|v| Value::Uint32(v as u32))),
+            (11, Some(Value::Uint16(summary.total_calories))),
+            (16, summary.avg_heart_rate_bpm.map(Value::Uint8)),
+            (17, summary.max_heart_rate_bpm.map(Value::Uint8)),
+            (18, summary.avg_stroke_rate.map(Value::Uint8)),
+            (20, summary.avg_power_watts.map(Value::Uint16)),
+            (25, Some(Value::Uint16(0))),
+            (26, Some(Value::Uint16(laps.len() as u16))),
+        ],
+        &[],
+    );
+
+    encoder.write(
+        MESG_ACTIVITY,
+        &[
+            (FIELD_TIMESTAMP, Some(fit_time(end_time))),
+            (0, Some(Value::Uint32(summary.duration_ms))),
+            (1, Some(Value::Uint16(1))),
+            (2, Some(Value::Enum(0))),  // manual
+            (3, Some(Value::Enum(26))), // activity
+            (4, Some(Value::Enum(1))),  // stop
+        ],
+        &[],
+    );
+
+    encoder.finish()
+}
+
+struct Definition {
+    global: u16,
+    big_endian: bool,
+    fields: Vec<(u8, u8)>,
+    developer_fields: Vec<(u8, u8, u8)>,
+}
+
+/// A decoded data message. Values are kept as raw bytes until read.
+struct Message {
+    global: u16,
+    big_endian: bool,
+    fields: HashMap<u8, Vec<u8>>,
+    /// Keyed by developer data index and field number.
+    developer_fields: HashMap<(u8, u8), Vec<u8>>,
+}
+
+impl Message {
+    fn uint(&self, bytes: &[u8]) -> Option<u32> {
+        let value = match bytes.len() {
+            1 => bytes[0] as u32,
+            2 => {
+                let bytes = [bytes[0], bytes[1]];
+                if self.big_endian {
+                    u16::from_be_bytes(bytes) as u32
+                } else {
+                    u16::from_le_bytes(bytes) as u32
+                }
+            }
+            4 => {
+                let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                if self.big_endian {
+                    u32::from_be_bytes(bytes)
+                } else {
+                    u32::from_le_bytes(bytes)
+                }
+            }
+            _ => return None,
+        };
+        // All bits set is FIT's invalid value for unsigned types.
+        let invalid = match bytes.len() {
+            1 => u8::MAX as u32,
+            2 => u16::MAX as u32,
+            _ => u32::MAX,
+        };
+        (value != invalid).then_some(value)
+    }
+
+    fn field(&self, num: u8) -> Option<u32> {
+        self.uint(self.fields.get(&num)?)
+    }
+
+    fn developer_field(&self, key: Option<&(u8, u8)>) -> Option<u32> {
+        self.uint(self.developer_fields.get(key?)?)
+    }
+
+    fn string(&self, num: u8) -> Option<String> {
+        let bytes = self.fields.get(&num)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..end].to_vec()).ok()
+    }
+}
+
+fn read<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], FitError> {
+    let bytes = data.get(*pos..*pos + len).ok_or(FitError::UnexpectedEnd)?;
+    *pos += len;
+    Ok(bytes)
+}
+
+fn decode_messages(data: &[u8]) -> Result<Vec<Message>, FitError> {
+    let header_size = *data.first().ok_or(FitError::InvalidHeader)? as usize;
+    if header_size < 12 || data.get(8..12) != Some(b".FIT") {
+        return Err(FitError::InvalidHeader);
+    }
+    let data_size = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+    let end = header_size + data_size;
+    let file_crc = data.get(end..end + 2).ok_or(FitError::UnexpectedEnd)?;
+    if crc(&data[..end]) != u16::from_le_bytes([file_crc[0], file_crc[1]]) {
+        return Err(FitError::Crc);
+    }
+
+    let mut definitions: HashMap<u8, Definition> = HashMap::new();
+    let mut messages = Vec::new();
+    // The last full timestamp, which compressed timestamps are offsets from.
+    let mut last_timestamp: Option<u32> = None;
+    let mut pos = header_size;
+    while pos < end {
+        let header = read(data, &mut pos, 1)?[0];
+        if header & 0x80 == 0 && header & 0x40 != 0 {
+            let local = header & 0x0F;
+            let fixed = read(data, &mut pos, 5)?;
+            let big_endian = fixed[1] == 1;
+            let global = if big_endian {
+                u16::from_be_bytes([fixed[2], fixed[3]])
+            } else {
+                u16::from_le_bytes([fixed[2], fixed[3]])
+            };
+            let fields = read(data, &mut pos, fixed[4] as usize * 3)?
+                .chunks(3)
+                .map(|field| (field[0], field[1]))
+                .collect();
+            let mut developer_fields = Vec::new();
+            if header & 0x20 != 0 {
+                let count = read(data, &mut pos, 1)?[0] as usize;
+                developer_fields = read(data, &mut pos, count * 3)?
+                    .chunks(3)
+                    .map(|field| (field[0], field[1], field[2]))
+                    .collect();
+            }
+            definitions.insert(
+                local,
+                Definition {
+                    global,
+                    big_endian,
+                    fields,
+                    developer_fields,
+                },
+            );
+            continue;
+        }
+
+        // Compressed timestamp headers carry the local type in bits 5-6 and
+        // the low 5 bits of the timestamp in bits 0-4.
+        let compressed = header & 0x80 != 0;
+        let local = if compressed {
+            (header >> 5) & 0x03
+        } else {
+            header & 0x0F
+        };
+        let definition = definitions
+            .get(&local)
+            .ok_or(FitError::UndefinedLocalMessage(local))?;
+        let mut message = Message {
+            global: definition.global,
+            big_endian: definition.big_endian,
+            fields: HashMap::new(),
+            developer_fields: HashMap::new(),
+        };
+        for (num, size) in &definition.fields {
+            let value = read(data, &mut pos, *size as usize)?;
+            message.fields.insert(*num, value.to_vec());
+        }
+        for (num, size, index) in &definition.developer_fields {
+            let value = read(data, &mut pos, *size as usize)?;
+            message
+                .developer_fields
+                .insert((*index, *num), value.to_vec());
+        }
+        if compressed {
+            // The offset rolls over every 32 seconds.
+            if let Some(last) = last_timestamp {
+                let offset = (header & 0x1F) as u32;
+                let mut timestamp = (last & !0x1F) | offset;
+                if offset < last & 0x1F {
+                    timestamp = timestamp.wrapping_add(0x20);
+                }
+                let bytes = if message.big_endian {
+                    timestamp.to_be_bytes()
+                } else {
+                    timestamp.to_le_bytes()
+                };
+                message.fields.insert(FIELD_TIMESTAMP, bytes.to_vec());
+                last_timestamp = Some(timestamp);
+            }
+        } else if let Some(timestamp) = message.field(FIELD_TIMESTAMP) {
+            last_timestamp = Some(timestamp);
+        }
+        messages.push(message);
+    }
+    Ok(messages)
+}
+
+fn unix_nanos(fit_time: u32) -> i128 {
+    (fit_time as i128 + FIT_EPOCH_OFFSET_S) * 1_000_000_000
+}
+
+/// Decode a FIT activity file, e.g. one exported by another rowing app.
+pub fn decode(data: &[u8]) -> Result<FitActivity, FitError> {
+    let messages = decode_messages(data)?;
+    let mut activity = FitActivity::default();
+
+    // Developer fields are matched by name, whichever app wrote them.
+    let mut developer_fields: HashMap<&str, (u8, u8)> = HashMap::new();
+    for message in messages
+        .iter()
+        .filter(|m| m.global == MESG_FIELD_DESCRIPTION)
+    {
+        let (Some(index), Some(num), Some(name)) =
+            (message.field(0), message.field(1), message.string(3))
+        else {
+            continue;
+        };
+        if let Some((name, _, _)) = DEVELOPER_FIELDS.iter().find(|(n, _, _)| *n == name) {
+            developer_fields.insert(name, (index as u8, num as u8));
+        }
+    }
+    let developer =
+        |message: &Message, name: &str| message.developer_field(developer_fields.get(name));
+
+    let mut first_timestamp = None;
+    for message in &messages {
+        match message.global {
+            MESG_RECORD => {
+                let Some(timestamp) = message.field(FIELD_TIMESTAMP).map(unix_nanos) else {
+                    continue;
+                };
+                let first = *first_timestamp.get_or_insert(timestamp);
+                let elapsed_time_ms = developer(message, "elapsed_time_ms")
+                    .unwrap_or(((timestamp - first) / 1_000_000) as u32);
+                activity.samples.push(WorkoutSample {
+                    timestamp,
+                    elapsed_time_ms,
+                    distance_m: message.field(5).map_or(0, |d| d / 100),
+                    heart_rate_bpm: message.field(3).map(|v| v as u8),
+                    power_watts: message.field(7).map(|v| v as u16),
+                    stroke_rate: message.field(4).map(|v| v as u8),
+                    pace_ms_per_500m: message
+                        .field(6)
+                        .filter(|speed| *speed > 0)
+                        .map(|speed| 500_000_000 / speed),
+                    calories: message.field(33).map(|v| v as u16),
+                    drive_length_cm: developer(message, "drive_length_cm").map(|v| v as u16),
+                    drive_time_ms: developer(message, "drive_time_ms").map(|v| v as u16),
+                    peak_drive_force_n: developer(message, "peak_drive_force_n").map(|v| v as u16),
+                    avg_drive_force_n: developer(message, "avg_drive_force_n").map(|v| v as u16),
+                    work_per_stroke_j: developer(message, "work_per_stroke_j").map(|v| v as u16),
+                    recovery_time_ms: developer(message, "recovery_time_ms"),
+                    stroke_distance_cm: developer(message, "stroke_distance_cm").map(|v| v as u16),
+                    drag_factor: developer(message, "drag_factor").map(|v| v as u8),
+                });
+            }
+            MESG_LAP => {
+                // Lap start times are relative to the first record, as
+                // the session with the workout's start time comes last.
+                let start = message.field(2).map(unix_nanos);
+                let start_elapsed_ms = match (start, first_timestamp) {
+                    (Some(start), Some(first)) => ((start - first).max(0) / 1_000_000) as u32,
+                    _ => 0,
+                };
+                activity.laps.push(IntervalSummary {
+                    interval_number: u8::try_from(activity.laps.len() + 1).unwrap_or(u8::MAX),
+                    start_elapsed_ms,
+                    work_time_ms: message.field(8).or(message.field(7)).unwrap_or(0),
+                    work_distance_m: message.field(9).map_or(0, |d| d / 100),
+                    avg_power_watts: message.field(19).map(|v| v as u16),
+                    avg_stroke_rate: message.field(17).map(|v| v as u8),
+                    work_heart_rate_bpm: message.field(15).map(|v| v as u8),
+                    ..Default::default()
+                });
+            }
+            MESG_SESSION => {
+                if let Some(start) = message.field(2) {
+                    activity.start_time = unix_nanos(start);
+                }
+                activity.machine_type = message.field(5).and_then(|s| machine_type(s as u8));
+                activity.total_strokes = message.field(10);
+                activity.total_calories = message.field(11).map(|v| v as u16);
+            }
+            _ => {}
+        }
+    }
+
+    let Some(first_timestamp) = first_timestamp else {
+        return Err(FitError::NoRecords);
+    };
+    if activity.start_time == 0 {
+        activity.start_time = first_timestamp;
+    }
+    Ok(activity)
+}
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+fn crc(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, byte| {
+        let tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ CRC_TABLE[(byte & 0xF) as usize];
+        let tmp = CRC_TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc ^ tmp ^ CRC_TABLE[((byte >> 4) & 0xF) as usize]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::workout::WorkoutRecorder;
+
+    use super::*;
+
+    fn recorder() -> WorkoutRecorder {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_machine_type(ErgMachineType::StaticD);
+        for i in 0..30u32 {
+            recorder.set_stroke_data(
+                i * 2000,
+                i * 10,
+                140,
+                800,
+                450,
+                380,
+                85,
+                Some(200 + i as u16),
+                Some(i as u16),
+            );
+            recorder.set_stroke_recovery(1600, 1000);
+            recorder.set_drag_factor(i * 2000, 120);
+            recorder.add_general_sample(i * 2000, i * 10, Some(150), Some(28), Some(100_000));
+        }
+        recorder
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut recorder = recorder();
+        let summary = recorder.generate_summary(None).unwrap();
+        let samples = recorder.drain_samples();
+
+        let data = encode(&summary, &samples);
+        assert_eq!(&data[8..12], b".FIT");
+        let activity = decode(&data).unwrap();
+
+        assert_eq!(activity.machine_type, Some(ErgMachineType::StaticD));
+        assert_eq!(activity.laps.len(), 1);
+        assert_eq!(activity.laps[0].work_distance_m, 290);
+        assert_eq!(activity.samples.len(), samples.len());
+        for (decoded, sample) in activity.samples.iter().zip(&samples) {
+            assert_eq!(
+                decoded.timestamp / 1_000_000_000,
+                sample.timestamp / 1_000_000_000
+            );
+            assert_eq!(decoded.elapsed_time_ms, sample.elapsed_time_ms);
+            assert_eq!(decoded.distance_m, sample.distance_m);
+            assert_eq!(decoded.heart_rate_bpm, sample.heart_rate_bpm);
+            assert_eq!(decoded.power_watts, sample.power_watts);
+            assert_eq!(decoded.stroke_rate, sample.stroke_rate);
+            assert_eq!(decoded.pace_ms_per_500m, sample.pace_ms_per_500m);
+            assert_eq!(decoded.drive_length_cm, sample.drive_length_cm);
+            assert_eq!(decoded.peak_drive_force_n, sample.peak_drive_force_n);
+            assert_eq!(decoded.work_per_stroke_j, sample.work_per_stroke_j);
+            assert_eq!(decoded.recovery_time_ms, Some(1600));
+            assert_eq!(decoded.stroke_distance_cm, sample.stroke_distance_cm);
+            assert_eq!(decoded.drag_factor, Some(120));
+        }
+    }
+
+    #[test]
+    fn test_compressed_timestamps() {
+        // A full timestamp 2 s before the 5-bit offset rolls over.
+        let start = (1_000_000_000 & !0x1F) + 30;
+        let mut encoder = Encoder::new();
+        encoder.write(
+            MESG_RECORD,
+            &[
+                (FIELD_TIMESTAMP, Some(Value::Uint32(start))),
+                (5, Some(Value::Uint32(0))),
+            ],
+            &[],
+        );
+        // Records with only a distance, their timestamps compressed.
+        let local = local_message_type(MESG_RECORD);
+        encoder
+            .data
+            .extend_from_slice(&[0x40 | local, 0, 0, 20, 0, 1, 5, 4, 0x86]);
+        for (i, seconds) in [1u32, 2, 5].into_iter().enumerate() {
+            let offset = ((start + seconds) & 0x1F) as u8;
+            encoder.data.push(0x80 | (local << 5) | offset);
+            encoder
+                .data
+                .extend_from_slice(&((i as u32 + 1) * 1000).to_le_bytes());
+        }
+        let activity = decode(&encoder.finish()).unwrap();
+
+        let times: Vec<_> = activity.samples.iter().map(|s| s.timestamp).collect();
+        assert_eq!(
+            times,
+            [0, 1, 2, 5].map(|seconds| unix_nanos(start + seconds))
+        );
+        assert_eq!(activity.samples[3].elapsed_time_ms, 5000);
+        assert_eq!(activity.samples[3].distance_m, 30);
+    }
+
+    #[test]
+    fn test_rejects_corrupt_files() {
+        let mut recorder = recorder();
+        let summary = recorder.generate_summary(None).unwrap();
+        let mut data = encode(&summary, &recorder.drain_samples());
+        assert!(matches!(decode(&data[..20]), Err(FitError::UnexpectedEnd)));
+        let last = data.len() - 3;
+        data[last] ^= 0xFF;
+        assert!(matches!(decode(&data), Err(FitError::Crc)));
+        assert!(matches!(
+            decode(b"not a fit file"),
+            Err(FitError::InvalidHeader)
+        ));
+    }
+}
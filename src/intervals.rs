@@ -0,0 +1,381 @@
+//! Work and rest bouts found in the samples of workouts the PM5 reported no
+//! intervals for, such as JustRow sessions and imports.
+//!
+//! Power, speed and stroke rate are each smoothed and taken relative to the
+//! workout's hard efforts in them. A sample is work when every one of these
+//! it has is above a threshold, so a drop in any one of them is rest: easing
+//! off the rate at steady power, say, or pace dropping while a noisy power
+//! reading doesn't. Bouts too short to be intervals are then merged into
+//! their neighbours.
+
+use serde::{Deserialize, Serialize};
+
+use crate::workout::{mean, IntervalSummary, WorkoutSample};
+
+/// The intensity percentile taken as a hard effort.
+const WORK_PERCENTILE: f64 = 0.9;
+
+/// The metrics intensity is measured in. A stopped pace is no speed.
+const METRICS: [fn(&WorkoutSample) -> Option<f64>; 3] = [
+    |s| s.power_watts.map(f64::from),
+    |s| {
+        s.pace_ms_per_500m.map(|pace| {
+            if pace > 0 {
+                500_000.0 / pace as f64
+            } else {
+                0.0
+            }
+        })
+    },
+    |s| s.stroke_rate.map(f64::from),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IntervalDetection {
+    /// The fraction of hard effort intensity, in each metric, above which a
+    /// sample is work. Higher values only count harder efforts as work.
+    pub sensitivity: f64,
+    /// Intensity is averaged over this window around each sample.
+    pub smoothing_ms: u32,
+    /// Shorter work and rest bouts are merged into their neighbours.
+    pub min_work_ms: u32,
+    pub min_rest_ms: u32,
+}
+
+impl Default for IntervalDetection {
+    fn default() -> Self {
+        Self {
+            sensitivity: 0.65,
+            smoothing_ms: 6_000,
+            min_work_ms: 20_000,
+            min_rest_ms: 10_000,
+        }
+    }
+}
+
+/// Consecutive samples of the same kind, by index.
+#[derive(Debug, Clone, Copy)]
+struct Bout {
+    work: bool,
+    start: usize,
+    end: usize,
+}
+
+/// Split `samples` into intervals of work followed by rest. Anything before
+/// the first work bout is left out.
+pub fn detect_intervals(
+    samples: &[WorkoutSample],
+    options: &IntervalDetection,
+) -> Vec<IntervalSummary> {
+    let times = monotonic_times(samples);
+    let intensities = intensities(samples, &times, options.smoothing_ms);
+    if intensities.is_empty() {
+        return Vec::new();
+    }
+    let mut bouts = bouts((0..samples.len()).map(|i| {
+        let levels: Vec<f64> = intensities.iter().filter_map(|metric| metric[i]).collect();
+        !levels.is_empty() && levels.iter().all(|level| *level >= options.sensitivity)
+    }));
+    merge_short_bouts(&times, &mut bouts, options);
+
+    let first_work = bouts.iter().position(|bout| bout.work);
+    let bouts = &bouts[first_work.unwrap_or(bouts.len())..];
+    let mut intervals = Vec::new();
+    let mut i = 0;
+    while i < bouts.len() {
+        let work = bouts[i];
+        let rest = bouts.get(i + 1).filter(|bout| !bout.work);
+        let end = rest.map_or(work.end, |rest| rest.end);
+        intervals.push(interval(
+            samples,
+            u8::try_from(intervals.len() + 1).unwrap_or(u8::MAX),
+            work,
+            rest.copied(),
+            end,
+        ));
+        i += if rest.is_some() { 2 } else { 1 };
+    }
+    intervals
+}
+
+/// Elapsed times that never go backwards, as imported samples' might.
+fn monotonic_times(samples: &[WorkoutSample]) -> Vec<u32> {
+    samples
+        .iter()
+        .scan(0, |latest, sample| {
+            *latest = sample.elapsed_time_ms.max(*latest);
+            Some(*latest)
+        })
+        .collect()
+}
+
+/// Each of [`METRICS`] that the samples have, smoothed, as a fraction of
+/// the workout's hard effort in it. Hard efforts are taken from the smoothed
+/// values so that noise doesn't raise them.
+fn intensities(samples: &[WorkoutSample], times: &[u32], window_ms: u32) -> Vec<Vec<Option<f64>>> {
+    METRICS
+        .iter()
+        .filter_map(|metric| {
+            let values: Vec<Option<f64>> = samples.iter().map(metric).collect();
+            let smoothed = smooth(times, &values, window_ms);
+            let mut positive: Vec<f64> = smoothed
+                .iter()
+                .flatten()
+                .copied()
+                .filter(|v| *v > 0.0)
+                .collect();
+            if positive.is_empty() {
+                return None;
+            }
+            positive.sort_by(f64::total_cmp);
+            let hard = positive[((positive.len() - 1) as f64 * WORK_PERCENTILE) as usize];
+            Some(smoothed.iter().map(|v| v.map(|v| v / hard)).collect())
+        })
+        .collect()
+}
+
+/// The mean of the values within half of `window_ms` of each sample. `None`
+/// where there are none.
+fn smooth(times: &[u32], values: &[Option<f64>], window_ms: u32) -> Vec<Option<f64>> {
+    let half = window_ms / 2;
+    let (mut from, mut to, mut sum, mut count) = (0, 0, 0.0, 0);
+    times
+        .iter()
+        .map(|&t| {
+            while to < times.len() && times[to] <= t.saturating_add(half) {
+                if let Some(value) = values[to] {
+                    sum += value;
+                    count += 1;
+                }
+                to += 1;
+            }
+            while times[from].saturating_add(half) < t {
+                if let Some(value) = values[from] {
+                    sum -= value;
+                    count -= 1;
+                }
+                from += 1;
+            }
+            (count > 0).then(|| sum / count as f64)
+        })
+        .collect()
+}
+
+fn bouts(work: impl Iterator<Item = bool>) -> Vec<Bout> {
+    let mut bouts: Vec<Bout> = Vec::new();
+    for (i, work) in work.enumerate() {
+        match bouts.last_mut() {
+            Some(bout) if bout.work == work => bout.end = i + 1,
+            _ => bouts.push(Bout {
+                work,
+                start: i,
+                end: i + 1,
+            }),
+        }
+    }
+    bouts
+}
+
+/// Flip the shortest bout that is too short into its neighbours until none
+/// are left. The first and last bouts are kept, as they may be cut short by
+/// the start and end of the workout.
+fn merge_short_bouts(times: &[u32], bouts: &mut Vec<Bout>, options: &IntervalDetection) {
+    let duration = |bout: &Bout| {
+        let end = times.get(bout.end).unwrap_or(&times[bout.end - 1]);
+        end - times[bout.start]
+    };
+    loop {
+        let shortest = (1..bouts.len().saturating_sub(1))
+            .filter(|&i| {
+                let min = if bouts[i].work {
+                    options.min_work_ms
+                } else {
+                    options.min_rest_ms
+                };
+                duration(&bouts[i]) < min
+            })
+            .min_by_key(|&i| duration(&bouts[i]));
+        let Some(i) = shortest else {
+            break;
+        };
+        // The neighbours are of the other kind, so all three become one.
+        let merged = Bout {
+            work: !bouts[i].work,
+            start: bouts[i - 1].start,
+            end: bouts[i + 1].end,
+        };
+        bouts.splice(i - 1..=i + 1, [merged]);
+    }
+}
+
+fn interval(
+    samples: &[WorkoutSample],
+    number: u8,
+    work: Bout,
+    rest: Option<Bout>,
+    end: usize,
+) -> IntervalSummary {
+    let at = |i: usize| samples.get(i).unwrap_or(&samples[samples.len() - 1]);
+    let (work_start, rest_start, rest_end) = (at(work.start), at(work.end), at(end));
+    let work_samples = &samples[work.start..work.end];
+    let rest_samples = rest.map_or(&[][..], |rest| &samples[rest.start..rest.end]);
+
+    // Imported samples' times and distances may not only go forwards.
+    let work_time_ms = rest_start
+        .elapsed_time_ms
+        .saturating_sub(work_start.elapsed_time_ms);
+    let work_distance_m = rest_start.distance_m.saturating_sub(work_start.distance_m);
+    IntervalSummary {
+        interval_number: number,
+        interval_type: None,
+        start_elapsed_ms: work_start.elapsed_time_ms,
+        work_time_ms,
+        rest_time_ms: rest_end
+            .elapsed_time_ms
+            .saturating_sub(rest_start.elapsed_time_ms),
+        work_distance_m,
+        rest_distance_m: rest_end.distance_m.saturating_sub(rest_start.distance_m),
+        avg_pace_ms_per_500m: (work_distance_m > 0)
+            .then(|| (work_time_ms as u64 * 500 / work_distance_m as u64) as u32),
+        avg_power_watts: mean(work_samples.iter().map(|s| s.power_watts)),
+        avg_stroke_rate: mean(work_samples.iter().map(|s| s.stroke_rate)),
+        work_heart_rate_bpm: mean(work_samples.iter().map(|s| s.heart_rate_bpm)),
+        rest_heart_rate_bpm: mean(rest_samples.iter().map(|s| s.heart_rate_bpm)),
+        avg_drag_factor: mean(work_samples.iter().map(|s| s.drag_factor)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polars::prelude::*;
+
+    use super::*;
+
+    /// 4 × (1 min at 250 W, 1 min easy at 60 W), after 2 minutes warm-up at
+    /// 120 W, with some noise.
+    fn samples() -> Vec<WorkoutSample> {
+        let mut distance = 0;
+        (0..=600)
+            .map(|s: u32| {
+                let hard = s >= 120 && (s - 120) % 120 < 60 && s < 600;
+                let power = if s < 120 {
+                    120
+                } else if hard {
+                    250
+                } else {
+                    60
+                };
+                let power = power + (s * 7 % 11) as u16;
+                let sample = WorkoutSample {
+                    elapsed_time_ms: s * 1000,
+                    distance_m: distance,
+                    power_watts: Some(power),
+                    stroke_rate: Some(if hard { 30 } else { 18 }),
+                    heart_rate_bpm: Some(if hard { 165 } else { 130 }),
+                    ..Default::default()
+                };
+                distance += if hard { 5 } else { 3 };
+                sample
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detects_work_and_rest() {
+        let intervals = detect_intervals(&samples(), &Default::default());
+        assert_eq!(intervals.len(), 4);
+        for (i, interval) in intervals.iter().enumerate() {
+            assert_eq!(interval.interval_number, i as u8 + 1);
+            assert!(
+                interval
+                    .start_elapsed_ms
+                    .abs_diff(120_000 + i as u32 * 120_000)
+                    <= 3_000
+            );
+            assert!(interval.work_time_ms.abs_diff(60_000) <= 4_000);
+            assert_eq!(interval.avg_stroke_rate, Some(30));
+        }
+        // Smoothing can shift a boundary by a sample or two.
+        assert!(intervals[0].work_heart_rate_bpm.unwrap().abs_diff(165) <= 1);
+        assert!(intervals[0].rest_heart_rate_bpm.unwrap().abs_diff(130) <= 1);
+        assert!(intervals[0].rest_time_ms.abs_diff(60_000) <= 4_000);
+    }
+
+    #[test]
+    fn test_interval_table() {
+        let df = WorkoutSample::to_dataframe(&samples()).unwrap();
+        let intervals =
+            crate::workout::WorkoutAnalytics::detect_intervals(df.lazy(), &Default::default())
+                .unwrap();
+        let table = IntervalSummary::to_dataframe(&intervals).unwrap();
+        assert_eq!(table.height(), 4);
+        assert_eq!(
+            table
+                .column("avg_stroke_rate")
+                .unwrap()
+                .u32()
+                .unwrap()
+                .get(0),
+            Some(30)
+        );
+    }
+
+    #[test]
+    fn test_combines_metrics() {
+        // Rests at steady power but a lower rate.
+        let steady_power: Vec<_> = samples()
+            .into_iter()
+            .map(|s| WorkoutSample {
+                power_watts: Some(200 + (s.elapsed_time_ms / 1000 * 7 % 11) as u16),
+                ..s
+            })
+            .collect();
+        let intervals = detect_intervals(&steady_power, &Default::default());
+        assert_eq!(intervals.len(), 4);
+        assert!(intervals[1].start_elapsed_ms.abs_diff(240_000) <= 3_000);
+
+        // Pace and rate follow the intervals, power is noise around 200 W.
+        let noisy_power: Vec<_> = samples()
+            .into_iter()
+            .map(|s| {
+                let second = s.elapsed_time_ms / 1000;
+                WorkoutSample {
+                    pace_ms_per_500m: Some(if s.stroke_rate == Some(30) {
+                        105_000
+                    } else {
+                        180_000
+                    }),
+                    power_watts: Some(150 + (second * 37 % 101) as u16),
+                    stroke_rate: None,
+                    ..s
+                }
+            })
+            .collect();
+        let intervals = detect_intervals(&noisy_power, &Default::default());
+        assert_eq!(intervals.len(), 4);
+        assert!(intervals[2].work_time_ms.abs_diff(60_000) <= 4_000);
+    }
+
+    #[test]
+    fn test_out_of_order_samples() {
+        let mut samples = samples();
+        samples[300].elapsed_time_ms = 0;
+        samples[301].distance_m = 0;
+        let intervals = detect_intervals(&samples, &Default::default());
+        assert_eq!(intervals.len(), 4);
+    }
+
+    #[test]
+    fn test_sensitivity() {
+        // Counting the warm-up as work merges it with the first interval.
+        let options = IntervalDetection {
+            sensitivity: 0.4,
+            ..Default::default()
+        };
+        let intervals = detect_intervals(&samples(), &options);
+        assert_eq!(intervals.len(), 4);
+        assert_eq!(intervals[0].start_elapsed_ms, 0);
+
+        assert!(detect_intervals(&[], &Default::default()).is_empty());
+    }
+}
@@ -1,7 +1,22 @@
+pub mod capture;
+pub mod decoupling;
+pub mod drag;
+pub mod efforts;
+pub mod fit;
+pub mod intervals;
+pub mod load;
+pub mod logbook;
+pub mod logbook_csv;
+pub mod pace;
 pub mod parse;
+pub mod predict;
+pub mod records;
 pub mod services;
+pub mod tcx;
+pub mod technique;
 pub mod types;
 pub mod workout;
+pub mod zones;
 
 // use anyhow::bail;
 // use btleplug::api::{Central, Manager as _, Peripheral as _, ScanFilter};
@@ -11,6 +26,7 @@ pub mod workout;
 // use std::time::Duration;
 // use tokio::time;
 
+// use crate::capture::{CaptureFormat, CaptureWriter};
 // use crate::services::{Pm5, Rowing, ServiceData};
 
 // const PERIPHERAL_NAME_MATCH_PREFIX_FILTER: &str = "PM5";
@@ -55,15 +71,15 @@ pub mod workout;
 
 //     pub async fn listen(&mut self, peripheral: &Peripheral) -> anyhow::Result<()> {
 //         let mut notification_stream = peripheral.notifications().await?;
-//         //let mut writer = self.storage.writer("data").await?;
+//         let writer = self.storage.writer("capture.pm5").await?;
+//         let mut capture = CaptureWriter::new(writer.into_futures_async_write(), CaptureFormat::Binary);
 //         while let Some(data) = notification_stream.next().await {
 //             println!("Received data [{:?}]", data.uuid);
+//             capture.capture(data.uuid, &data.value).await?;
 //             let parsed = Pm5::parse(data.uuid, data.value);
 //             println!("frame: {:?}", parsed);
-//             // writer.write(data.uuid.as_bytes().to_vec()).await?;
-//             // writer.write(data.value).await?;
 //         }
-//         // writer.close().await?;
+//         capture.close().await?;
 //         println!("Disconnecting from peripheral");
 //         peripheral.disconnect().await?;
 
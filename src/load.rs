@@ -0,0 +1,222 @@
+//! Training load: per-workout stress from power (TSS) or heart rate (TRIMP),
+//! and the acute and chronic load and training stress balance built from it.
+
+use serde::{Deserialize, Serialize};
+use time::Date;
+
+use crate::efforts::power_per_second;
+use crate::workout::WorkoutSample;
+use crate::zones::UserProfile;
+
+/// Normalized power averages power over 30 second windows.
+const NP_WINDOW_S: usize = 30;
+/// Time constants of the acute and chronic load, in days.
+const ACUTE_DAYS: f64 = 7.0;
+const CHRONIC_DAYS: f64 = 42.0;
+/// Banister's weighting of heart rate reserve.
+const TRIMP_FACTOR: f64 = 0.64;
+const TRIMP_EXPONENT: f64 = 1.92;
+
+/// Which per-workout stress the daily load is built from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoadMetric {
+    Tss,
+    Trimp,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrainingStress {
+    pub normalized_power_watts: Option<f64>,
+    /// Normalized power as a fraction of FTP.
+    pub intensity_factor: Option<f64>,
+    /// Training stress score; an hour at FTP scores 100.
+    pub tss: Option<f64>,
+    /// Banister's training impulse.
+    pub trimp: Option<f64>,
+}
+
+impl TrainingStress {
+    /// The stress of a workout for the FTP and heart rates in `profile`.
+    /// Scores that need a missing setting or samples are `None`.
+    pub fn from_samples(samples: &[WorkoutSample], profile: &UserProfile) -> Self {
+        let normalized_power_watts = normalized_power(samples);
+        let seconds = power_per_second(samples).len() as f64;
+        let intensity_factor = normalized_power_watts
+            .zip(profile.ftp_watts.filter(|ftp| *ftp > 0))
+            .map(|(np, ftp)| np / ftp as f64);
+        let tss =
+            intensity_factor.map(|intensity| seconds / 3600.0 * intensity * intensity * 100.0);
+        let trimp = match (profile.max_heart_rate_bpm, profile.resting_heart_rate_bpm) {
+            (Some(max), Some(resting)) if max > resting => trimp(samples, max, resting),
+            _ => None,
+        };
+        Self {
+            normalized_power_watts,
+            intensity_factor,
+            tss,
+            trimp,
+        }
+    }
+
+    pub fn metric(&self, metric: LoadMetric) -> Option<f64> {
+        match metric {
+            LoadMetric::Tss => self.tss,
+            LoadMetric::Trimp => self.trimp,
+        }
+    }
+}
+
+/// The fourth-power mean of 30 second rolling power, or plain mean power for
+/// workouts shorter than that.
+fn normalized_power(samples: &[WorkoutSample]) -> Option<f64> {
+    if samples.iter().all(|s| s.power_watts.is_none()) {
+        return None;
+    }
+    let curve = power_per_second(samples);
+    if curve.is_empty() {
+        return None;
+    }
+    if curve.len() < NP_WINDOW_S {
+        return Some(curve.iter().sum::<f64>() / curve.len() as f64);
+    }
+    let rolling: Vec<f64> = curve
+        .windows(NP_WINDOW_S)
+        .map(|window| window.iter().sum::<f64>() / NP_WINDOW_S as f64)
+        .collect();
+    let mean = rolling.iter().map(|p| p.powi(4)).sum::<f64>() / rolling.len() as f64;
+    Some(mean.powf(0.25))
+}
+
+/// Minutes weighted by heart rate reserve, each sample's heart rate holding
+/// until the next sample.
+fn trimp(samples: &[WorkoutSample], max_bpm: u8, resting_bpm: u8) -> Option<f64> {
+    let reserve = (max_bpm - resting_bpm) as f64;
+    let mut heart_rate = None;
+    let mut total = None;
+    for pair in samples.windows(2) {
+        heart_rate = pair[0].heart_rate_bpm.or(heart_rate);
+        let Some(bpm) = heart_rate else {
+            continue;
+        };
+        let minutes = pair[1]
+            .elapsed_time_ms
+            .saturating_sub(pair[0].elapsed_time_ms) as f64
+            / 60_000.0;
+        let fraction = ((bpm as f64 - resting_bpm as f64) / reserve).clamp(0.0, 1.0);
+        *total.get_or_insert(0.0) +=
+            minutes * fraction * TRIMP_FACTOR * (TRIMP_EXPONENT * fraction).exp();
+    }
+    total
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DailyLoad {
+    pub date: Date,
+    /// The day's summed workout stress.
+    pub load: f64,
+    /// Acute training load, or fatigue: the 7 day average load.
+    pub acute: f64,
+    /// Chronic training load, or fitness: the 42 day average load.
+    pub chronic: f64,
+    /// Training stress balance, or form: the previous day's chronic minus
+    /// acute load.
+    pub balance: f64,
+}
+
+/// Roll per-workout stress up into a day-by-day series, from the first
+/// workout's day through `to`. Averages are exponentially weighted.
+pub fn daily_load(workouts: &[(Date, f64)], to: Date) -> Vec<DailyLoad> {
+    let Some(mut date) = workouts.iter().map(|(date, _)| *date).min() else {
+        return Vec::new();
+    };
+    let mut days = Vec::new();
+    let (mut acute, mut chronic) = (0.0, 0.0);
+    while date <= to {
+        let load: f64 = workouts
+            .iter()
+            .filter(|(day, _)| *day == date)
+            .map(|(_, stress)| stress)
+            .sum();
+        let balance = chronic - acute;
+        acute += (load - acute) / ACUTE_DAYS;
+        chronic += (load - chronic) / CHRONIC_DAYS;
+        days.push(DailyLoad {
+            date,
+            load,
+            acute,
+            chronic,
+            balance,
+        });
+        let Some(next) = date.next_day() else {
+            break;
+        };
+        date = next;
+    }
+    days
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use super::*;
+
+    fn profile() -> UserProfile {
+        UserProfile {
+            ftp_watts: Some(200),
+            max_heart_rate_bpm: Some(190),
+            resting_heart_rate_bpm: Some(50),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hour_at_ftp() {
+        let samples: Vec<_> = (0..=3600)
+            .map(|s| WorkoutSample {
+                elapsed_time_ms: s * 1000,
+                power_watts: Some(200),
+                heart_rate_bpm: Some(155),
+                ..Default::default()
+            })
+            .collect();
+        let stress = TrainingStress::from_samples(&samples, &profile());
+        assert_eq!(stress.normalized_power_watts, Some(200.0));
+        assert_eq!(stress.intensity_factor, Some(1.0));
+        assert!((stress.tss.unwrap() - 100.0).abs() < 1e-9);
+        // 60 minutes at 75% of reserve.
+        let expected = 60.0 * 0.75 * 0.64 * (1.92f64 * 0.75).exp();
+        assert!((stress.trimp.unwrap() - expected).abs() < 1e-9);
+
+        let stress = TrainingStress::from_samples(&samples, &UserProfile::default());
+        assert_eq!(stress.tss, None);
+        assert_eq!(stress.trimp, None);
+    }
+
+    #[test]
+    fn test_normalized_power_weights_surges() {
+        let samples: Vec<_> = (0..=600)
+            .map(|s| WorkoutSample {
+                elapsed_time_ms: s * 1000,
+                power_watts: Some(if (s / 60) % 2 == 0 { 300 } else { 100 }),
+                ..Default::default()
+            })
+            .collect();
+        let np = normalized_power(&samples).unwrap();
+        assert!(np > 200.0, "{np}");
+    }
+
+    #[test]
+    fn test_daily_load() {
+        let day = |d| Date::from_calendar_date(2025, Month::March, d).unwrap();
+        let days = daily_load(&[(day(1), 70.0), (day(1), 30.0), (day(3), 50.0)], day(4));
+        assert_eq!(days.len(), 4);
+        assert_eq!(days[0].load, 100.0);
+        assert!((days[0].acute - 100.0 / 7.0).abs() < 1e-9);
+        assert!((days[0].chronic - 100.0 / 42.0).abs() < 1e-9);
+        assert_eq!(days[0].balance, 0.0);
+        assert!((days[1].balance - (days[0].chronic - days[0].acute)).abs() < 1e-9);
+        assert_eq!(days[3].load, 0.0);
+        assert!(days[3].acute < days[2].acute);
+    }
+}
@@ -0,0 +1,564 @@
+//! Results in the shape the Concept2 Online Logbook API accepts.
+//!
+//! Times are in tenths of a second and stroke distances in decimeters, as in
+//! the Logbook. Samples only keep whole meters, so stroke distances are
+//! rounded on import.
+
+use chrono::{DateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use time::{Date, Month, Time, UtcDateTime};
+use uuid::Uuid;
+
+use crate::pace::Ergometer;
+use crate::types::{ErgMachineType, IntervalType, WorkoutType};
+use crate::workout::{IntervalSummary, WorkoutSample, WorkoutSummary};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogbookError {
+    #[error("invalid result date {0:?}")]
+    Date(String),
+    #[error("unknown timezone {0:?}")]
+    TimeZone(String),
+    #[error("result {0} is out of range")]
+    OutOfRange(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogbookResult {
+    #[serde(rename = "type")]
+    pub machine: LogbookMachine,
+    /// `YYYY-MM-DD HH:MM:SS` in `timezone`.
+    pub date: String,
+    /// An IANA timezone name, e.g. `Europe/Helsinki`.
+    pub timezone: String,
+    pub distance: u32,
+    /// Tenths of a second.
+    pub time: u32,
+    pub workout_type: LogbookWorkoutType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_rate: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_count: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calories_total: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drag_factor: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heart_rate: Option<LogbookHeartRate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workout: Option<LogbookWorkout>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stroke_data: Vec<LogbookStroke>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogbookMachine {
+    Rower,
+    #[serde(rename = "skierg")]
+    SkiErg,
+    Bike,
+    Dynamic,
+    Slides,
+}
+
+impl From<Option<ErgMachineType>> for LogbookMachine {
+    fn from(machine_type: Option<ErgMachineType>) -> Self {
+        use ErgMachineType::*;
+        match (Ergometer::from_machine_type(machine_type), machine_type) {
+            (Ergometer::SkiErg, _) => LogbookMachine::SkiErg,
+            (Ergometer::BikeErg, _) => LogbookMachine::Bike,
+            (_, Some(StaticDynamic | LinkedDynamic)) => LogbookMachine::Dynamic,
+            (_, Some(SlidesA | SlidesB | SlidesC | SlidesD | SlidesE)) => LogbookMachine::Slides,
+            _ => LogbookMachine::Rower,
+        }
+    }
+}
+
+impl From<LogbookMachine> for ErgMachineType {
+    fn from(machine: LogbookMachine) -> Self {
+        match machine {
+            LogbookMachine::Rower => Ergometer::RowErg.machine_type(),
+            LogbookMachine::SkiErg => Ergometer::SkiErg.machine_type(),
+            LogbookMachine::Bike => Ergometer::BikeErg.machine_type(),
+            LogbookMachine::Dynamic => ErgMachineType::StaticDynamic,
+            LogbookMachine::Slides => ErgMachineType::SlidesD,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogbookWorkoutType {
+    #[serde(rename = "unknown")]
+    Unknown,
+    JustRow,
+    FixedDistanceSplits,
+    FixedTimeSplits,
+    FixedCalorie,
+    FixedWattMinute,
+    FixedTimeInterval,
+    FixedDistanceInterval,
+    FixedCalorieInterval,
+    VariableInterval,
+    VariableIntervalUndefinedRest,
+}
+
+impl LogbookWorkoutType {
+    fn is_interval(self) -> bool {
+        matches!(
+            self,
+            LogbookWorkoutType::FixedTimeInterval
+                | LogbookWorkoutType::FixedDistanceInterval
+                | LogbookWorkoutType::FixedCalorieInterval
+                | LogbookWorkoutType::VariableInterval
+                | LogbookWorkoutType::VariableIntervalUndefinedRest
+        )
+    }
+}
+
+impl From<WorkoutType> for LogbookWorkoutType {
+    fn from(workout_type: WorkoutType) -> Self {
+        match workout_type {
+            WorkoutType::JustrowNosplits | WorkoutType::JustrowSplits => {
+                LogbookWorkoutType::JustRow
+            }
+            WorkoutType::FixeddistNosplits | WorkoutType::FixeddistSplits => {
+                LogbookWorkoutType::FixedDistanceSplits
+            }
+            WorkoutType::FixedtimeNosplits | WorkoutType::FixedtimeSplits => {
+                LogbookWorkoutType::FixedTimeSplits
+            }
+            WorkoutType::FixedtimeInterval => LogbookWorkoutType::FixedTimeInterval,
+            WorkoutType::FixeddistInterval => LogbookWorkoutType::FixedDistanceInterval,
+            WorkoutType::VariableInterval => LogbookWorkoutType::VariableInterval,
+            WorkoutType::VariableUndefinedrestInterval => {
+                LogbookWorkoutType::VariableIntervalUndefinedRest
+            }
+            WorkoutType::FixedcalorieSplits => LogbookWorkoutType::FixedCalorie,
+            WorkoutType::FixedwattminuteSplits => LogbookWorkoutType::FixedWattMinute,
+            WorkoutType::FixedcalsInterval => LogbookWorkoutType::FixedCalorieInterval,
+            WorkoutType::Num => LogbookWorkoutType::Unknown,
+        }
+    }
+}
+
+impl LogbookWorkoutType {
+    /// The PM5 workout type, taking the variant with splits where the Logbook
+    /// doesn't distinguish.
+    fn workout_type(self) -> Option<WorkoutType> {
+        Some(match self {
+            LogbookWorkoutType::Unknown => return None,
+            LogbookWorkoutType::JustRow => WorkoutType::JustrowSplits,
+            LogbookWorkoutType::FixedDistanceSplits => WorkoutType::FixeddistSplits,
+            LogbookWorkoutType::FixedTimeSplits => WorkoutType::FixedtimeSplits,
+            LogbookWorkoutType::FixedCalorie => WorkoutType::FixedcalorieSplits,
+            LogbookWorkoutType::FixedWattMinute => WorkoutType::FixedwattminuteSplits,
+            LogbookWorkoutType::FixedTimeInterval => WorkoutType::FixedtimeInterval,
+            LogbookWorkoutType::FixedDistanceInterval => WorkoutType::FixeddistInterval,
+            LogbookWorkoutType::FixedCalorieInterval => WorkoutType::FixedcalsInterval,
+            LogbookWorkoutType::VariableInterval => WorkoutType::VariableInterval,
+            LogbookWorkoutType::VariableIntervalUndefinedRest => {
+                WorkoutType::VariableUndefinedrestInterval
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogbookHeartRate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub average: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ending: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rest: Option<u8>,
+}
+
+impl LogbookHeartRate {
+    fn non_empty(self) -> Option<Self> {
+        (self != Self::default()).then_some(self)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogbookWorkout {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub splits: Vec<LogbookInterval>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub intervals: Vec<LogbookInterval>,
+}
+
+/// A split or an interval. Only intervals have rest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogbookInterval {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub interval_type: Option<String>,
+    /// Tenths of a second.
+    pub time: u32,
+    pub distance: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rest_time: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rest_distance: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stroke_rate: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heart_rate: Option<LogbookHeartRate>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogbookStroke {
+    /// Elapsed time in tenths of a second.
+    pub t: u32,
+    /// Distance in decimeters.
+    pub d: u32,
+    /// Pace in tenths of a second per 500m, or per 1000m on the BikeErg.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spm: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hr: Option<u8>,
+}
+
+fn tenths(ms: u32) -> u32 {
+    (ms + 50) / 100
+}
+
+/// Tenths of a second read from a result as milliseconds.
+fn tenths_to_ms(tenths: u32, field: &'static str) -> Result<u32, LogbookError> {
+    tenths
+        .checked_mul(100)
+        .ok_or(LogbookError::OutOfRange(field))
+}
+
+fn interval_type_name(interval_type: IntervalType) -> Option<&'static str> {
+    match interval_type {
+        IntervalType::Time | IntervalType::TimerestUndefined => Some("time"),
+        IntervalType::Dist | IntervalType::DistancerestUndefined => Some("distance"),
+        IntervalType::Calorie | IntervalType::CalorierestUndefined => Some("calorie"),
+        IntervalType::Wattminute | IntervalType::WattminuterestUndefined => Some("watt"),
+        IntervalType::Rest | IntervalType::RestUndefined | IntervalType::None => None,
+    }
+}
+
+fn interval_type(name: &str) -> Option<IntervalType> {
+    match name {
+        "time" => Some(IntervalType::Time),
+        "distance" => Some(IntervalType::Dist),
+        "calorie" => Some(IntervalType::Calorie),
+        "watt" => Some(IntervalType::Wattminute),
+        _ => None,
+    }
+}
+
+impl LogbookResult {
+    /// Convert a workout, with its intervals and stroke data, to a result.
+    pub fn from_workout(summary: &WorkoutSummary, samples: &[WorkoutSample]) -> Self {
+        let workout_type = summary
+            .workout_type
+            .map_or(LogbookWorkoutType::Unknown, LogbookWorkoutType::from);
+        let is_interval = workout_type.is_interval();
+        let ergometer = summary.ergometer();
+
+        let intervals: Vec<LogbookInterval> = summary
+            .intervals
+            .iter()
+            .map(|interval| LogbookInterval {
+                interval_type: interval
+                    .interval_type
+                    .and_then(interval_type_name)
+                    .map(str::to_owned),
+                time: tenths(interval.work_time_ms),
+                distance: interval.work_distance_m,
+                rest_time: is_interval.then(|| tenths(interval.rest_time_ms)),
+                rest_distance: is_interval.then_some(interval.rest_distance_m),
+                stroke_rate: interval.avg_stroke_rate,
+                heart_rate: LogbookHeartRate {
+                    average: interval.work_heart_rate_bpm,
+                    rest: interval.rest_heart_rate_bpm,
+                    ..Default::default()
+                }
+                .non_empty(),
+            })
+            .collect();
+        let workout = (!intervals.is_empty()).then(|| {
+            if is_interval {
+                LogbookWorkout {
+                    intervals,
+                    ..Default::default()
+                }
+            } else {
+                LogbookWorkout {
+                    splits: intervals,
+                    ..Default::default()
+                }
+            }
+        });
+
+        let start = UtcDateTime::from_unix_timestamp_nanos(summary.start_time)
+            .unwrap_or(UtcDateTime::UNIX_EPOCH);
+        LogbookResult {
+            machine: summary.machine_type.into(),
+            date: format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                start.year(),
+                start.month() as u8,
+                start.day(),
+                start.hour(),
+                start.minute(),
+                start.second()
+            ),
+            timezone: "UTC".into(),
+            distance: summary.total_distance_m,
+            time: tenths(summary.duration_ms),
+            workout_type,
+            stroke_rate: summary.avg_stroke_rate,
+            stroke_count: summary.total_strokes,
+            calories_total: Some(summary.total_calories),
+            drag_factor: summary.avg_drag_factor,
+            heart_rate: LogbookHeartRate {
+                average: summary.avg_heart_rate_bpm,
+                min: summary.min_heart_rate_bpm,
+                max: summary.max_heart_rate_bpm,
+                ending: summary.ending_heart_rate_bpm,
+                recovery: summary.recovery_heart_rate_bpm,
+                rest: None,
+            }
+            .non_empty(),
+            workout,
+            stroke_data: samples
+                .iter()
+                .map(|sample| LogbookStroke {
+                    t: tenths(sample.elapsed_time_ms),
+                    d: sample.distance_m * 10,
+                    p: sample
+                        .pace_ms_per_500m
+                        .map(|pace| tenths(ergometer.display_pace(pace))),
+                    spm: sample.stroke_rate,
+                    hr: sample.heart_rate_bpm,
+                })
+                .collect(),
+        }
+    }
+
+    /// Convert a result back to a workout for `user_id`, with a new workout id.
+    pub fn to_workout(
+        &self,
+        user_id: &str,
+    ) -> Result<(WorkoutSummary, Vec<WorkoutSample>), LogbookError> {
+        let start_time = self.start_time()?;
+        let duration_ms = tenths_to_ms(self.time, "time")?;
+        let heart_rate = self.heart_rate.clone().unwrap_or_default();
+
+        let workout = self.workout.clone().unwrap_or_default();
+        let mut start_elapsed_ms: u32 = 0;
+        let mut intervals = Vec::new();
+        for (i, interval) in workout
+            .intervals
+            .into_iter()
+            .chain(workout.splits)
+            .enumerate()
+        {
+            let summary = IntervalSummary {
+                interval_number: u8::try_from(i + 1).unwrap_or(u8::MAX),
+                interval_type: interval.interval_type.as_deref().and_then(interval_type),
+                start_elapsed_ms,
+                work_time_ms: tenths_to_ms(interval.time, "interval time")?,
+                rest_time_ms: tenths_to_ms(interval.rest_time.unwrap_or(0), "rest time")?,
+                work_distance_m: interval.distance,
+                rest_distance_m: interval.rest_distance.unwrap_or(0),
+                avg_stroke_rate: interval.stroke_rate,
+                work_heart_rate_bpm: interval.heart_rate.as_ref().and_then(|hr| hr.average),
+                rest_heart_rate_bpm: interval.heart_rate.as_ref().and_then(|hr| hr.rest),
+                ..Default::default()
+            };
+            start_elapsed_ms = start_elapsed_ms
+                .checked_add(summary.work_time_ms)
+                .and_then(|ms| ms.checked_add(summary.rest_time_ms))
+                .ok_or(LogbookError::OutOfRange("interval time"))?;
+            intervals.push(summary);
+        }
+
+        let summary = WorkoutSummary {
+            workout_id: Uuid::now_v7(),
+            user_id: user_id.to_owned(),
+            start_time,
+            end_time: start_time + duration_ms as i128 * 1_000_000,
+            duration_ms,
+            total_distance_m: self.distance,
+            total_calories: self.calories_total.unwrap_or(0),
+            machine_type: Some(self.machine.into()),
+            workout_type: self.workout_type.workout_type(),
+            avg_heart_rate_bpm: heart_rate.average,
+            max_heart_rate_bpm: heart_rate.max,
+            avg_stroke_rate: self.stroke_rate,
+            avg_pace_ms_per_500m: (self.distance > 0)
+                .then(|| (duration_ms as u64 * 500 / self.distance as u64) as u32),
+            min_heart_rate_bpm: heart_rate.min,
+            ending_heart_rate_bpm: heart_rate.ending,
+            recovery_heart_rate_bpm: heart_rate.recovery,
+            avg_drag_factor: self.drag_factor,
+            total_strokes: self.stroke_count,
+            intervals,
+            ..Default::default()
+        };
+        let ergometer = summary.ergometer();
+        let samples = self
+            .stroke_data
+            .iter()
+            .map(|stroke| {
+                let pace_ms = stroke
+                    .p
+                    .map(|p| tenths_to_ms(p, "stroke pace"))
+                    .transpose()?;
+                Ok(WorkoutSample {
+                    timestamp: start_time + stroke.t as i128 * 100_000_000,
+                    elapsed_time_ms: tenths_to_ms(stroke.t, "stroke time")?,
+                    distance_m: ((stroke.d as u64 + 5) / 10) as u32,
+                    heart_rate_bpm: stroke.hr,
+                    stroke_rate: stroke.spm,
+                    pace_ms_per_500m: pace_ms.map(|pace| ergometer.pace_per_500m(pace)),
+                    ..Default::default()
+                })
+            })
+            .collect::<Result<_, LogbookError>>()?;
+        Ok((summary, samples))
+    }
+
+    /// The date as a Unix timestamp in nanoseconds, read in the result's
+    /// timezone. Times skipped by a daylight saving change are read with the
+    /// offset in effect before it; repeated times as their first occurrence.
+    fn start_time(&self) -> Result<i128, LogbookError> {
+        let tz: Tz = self
+            .timezone
+            .parse()
+            .map_err(|_| LogbookError::TimeZone(self.timezone.clone()))?;
+        let local =
+            parse_date_time(&self.date).ok_or_else(|| LogbookError::Date(self.date.clone()))?;
+        let wall_clock = DateTime::from_timestamp((local / 1_000_000_000) as i64, 0)
+            .ok_or_else(|| LogbookError::Date(self.date.clone()))?
+            .naive_utc();
+        let offset = tz
+            .offset_from_local_datetime(&wall_clock)
+            .earliest()
+            .unwrap_or_else(|| tz.offset_from_utc_datetime(&wall_clock));
+        Ok(local - offset.fix().local_minus_utc() as i128 * 1_000_000_000)
+    }
+}
+
+/// Parse a Logbook `YYYY-MM-DD HH:MM:SS` date as a Unix timestamp in
+/// nanoseconds.
+pub(crate) fn parse_date_time(date: &str) -> Option<i128> {
+    let parts: Vec<u32> = date
+        .split(['-', ' ', ':'])
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [year, month, day, hour, minute, second] = parts[..] else {
+        return None;
+    };
+    let month = Month::try_from(u8::try_from(month).ok()?).ok()?;
+    let date = Date::from_calendar_date(year as i32, month, u8::try_from(day).ok()?).ok()?;
+    let time = Time::from_hms(
+        u8::try_from(hour).ok()?,
+        u8::try_from(minute).ok()?,
+        u8::try_from(second).ok()?,
+    )
+    .ok()?;
+    Some(UtcDateTime::new(date, time).unix_timestamp_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> LogbookResult {
+        let json = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/data/logbook_result.json"
+        ));
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_fixture_round_trip() {
+        let result = fixture();
+        let (summary, samples) = result.to_workout("user").unwrap();
+        assert_eq!(summary.machine_type, Some(ErgMachineType::StaticSki));
+        assert_eq!(summary.workout_type, Some(WorkoutType::FixeddistInterval));
+        assert_eq!(summary.duration_ms, 252_300);
+        assert_eq!(summary.intervals.len(), 2);
+        assert_eq!(summary.intervals[1].start_elapsed_ms, 186_100);
+        assert_eq!(samples[2].distance_m, 21);
+
+        assert_eq!(LogbookResult::from_workout(&summary, &samples), result);
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let json = serde_json::to_value(fixture()).unwrap();
+        assert_eq!(json["type"], "skierg");
+        assert_eq!(json["workout_type"], "FixedDistanceInterval");
+        assert_eq!(json["workout"]["intervals"][0]["rest_time"], 600);
+        assert_eq!(json["stroke_data"][1]["spm"], 38);
+    }
+
+    #[test]
+    fn test_timezones() {
+        let utc = fixture().to_workout("user").unwrap().0.start_time;
+        // Two hours ahead of UTC in winter, three in summer.
+        let result = LogbookResult {
+            timezone: "Europe/Helsinki".into(),
+            ..fixture()
+        };
+        let helsinki = result.to_workout("user").unwrap().0.start_time;
+        assert_eq!(utc - helsinki, 2 * 3600 * 1_000_000_000);
+        let summer = LogbookResult {
+            date: "2025-07-14 06:45:12".into(),
+            ..result
+        };
+        let summer_utc = LogbookResult {
+            timezone: "UTC".into(),
+            ..summer.clone()
+        };
+        assert_eq!(
+            summer_utc.to_workout("user").unwrap().0.start_time
+                - summer.to_workout("user").unwrap().0.start_time,
+            3 * 3600 * 1_000_000_000
+        );
+
+        let result = LogbookResult {
+            timezone: "Mars/Olympus_Mons".into(),
+            ..fixture()
+        };
+        assert!(matches!(
+            result.to_workout("user"),
+            Err(LogbookError::TimeZone(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_times() {
+        let mut result = fixture();
+        result.stroke_data[0].t = u32::MAX;
+        assert!(matches!(
+            result.to_workout("user"),
+            Err(LogbookError::OutOfRange("stroke time"))
+        ));
+        let result = LogbookResult {
+            time: u32::MAX / 10,
+            ..fixture()
+        };
+        assert!(matches!(
+            result.to_workout("user"),
+            Err(LogbookError::OutOfRange("time"))
+        ));
+    }
+}
@@ -0,0 +1,273 @@
+//! CSV exports from the Concept2 Online Logbook and ErgData.
+//!
+//! The Logbook's season export has one row per workout, with totals only.
+//! Stroke data is exported separately, one file per workout, with a row per
+//! stroke. Dates carry no timezone and are read as UTC. Paces are per 500m,
+//! or per 1000m on the BikeErg.
+
+use std::io::Read;
+
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::logbook::parse_date_time;
+use crate::pace::Ergometer;
+use crate::types::ErgMachineType;
+use crate::workout::{WorkoutSample, WorkoutSummary};
+
+#[derive(Debug, thiserror::Error)]
+pub enum CsvImportError {
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error("invalid {column} {value:?}")]
+    InvalidValue { column: &'static str, value: String },
+}
+
+/// A row of the Logbook's season export.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogbookCsvRow {
+    #[serde(rename = "Log ID")]
+    pub log_id: Option<u64>,
+    #[serde(rename = "Date")]
+    pub date: String,
+    #[serde(rename = "Description", default)]
+    pub description: Option<String>,
+    #[serde(rename = "Work Time (Formatted)", default)]
+    pub work_time: Option<String>,
+    #[serde(rename = "Work Time (Seconds)", default)]
+    pub work_time_s: Option<f64>,
+    #[serde(rename = "Rest Time (Seconds)", default)]
+    pub rest_time_s: Option<f64>,
+    #[serde(rename = "Work Distance", default)]
+    pub work_distance_m: Option<f64>,
+    #[serde(rename = "Stroke Rate/Cadence", default)]
+    pub stroke_rate: Option<f64>,
+    #[serde(rename = "Stroke Count", default)]
+    pub stroke_count: Option<f64>,
+    #[serde(rename = "Pace", default)]
+    pub pace: Option<String>,
+    #[serde(rename = "Avg Watts", default)]
+    pub avg_watts: Option<f64>,
+    #[serde(rename = "Total Cal", default)]
+    pub total_calories: Option<f64>,
+    #[serde(rename = "Avg Heart Rate", default)]
+    pub avg_heart_rate: Option<f64>,
+    #[serde(rename = "Drag Factor", default)]
+    pub drag_factor: Option<f64>,
+    #[serde(rename = "Type", default)]
+    pub machine: Option<String>,
+}
+
+/// A row of a per-workout stroke data export.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct StrokeCsvRow {
+    #[serde(rename = "Time (seconds)", alias = "Time")]
+    time: String,
+    #[serde(rename = "Distance (meters)", alias = "Distance")]
+    distance_m: f64,
+    #[serde(rename = "Pace (seconds)", alias = "Pace", default)]
+    pace: Option<String>,
+    #[serde(rename = "Watts", default)]
+    watts: Option<f64>,
+    #[serde(rename = "Stroke Rate", default)]
+    stroke_rate: Option<f64>,
+    #[serde(rename = "Heart Rate", default)]
+    heart_rate: Option<f64>,
+}
+
+/// Parse a time such as `1:52.3`, `1:02:03.4` or `112.3` as milliseconds.
+pub fn parse_time_ms(time: &str) -> Option<u32> {
+    let time = time.trim();
+    if time.is_empty() {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in time.split(':') {
+        let value: f64 = part.parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some((seconds * 1000.0).round() as u32)
+}
+
+/// A pace as shown on `ergometer`, stored per 500m.
+fn parse_pace(pace: Option<&str>, ergometer: Ergometer) -> Result<Option<u32>, CsvImportError> {
+    match pace {
+        Some(pace) if !pace.is_empty() => {
+            let pace_ms = parse_time_ms(pace).ok_or_else(|| invalid("Pace", pace))?;
+            Ok(Some(ergometer.pace_per_500m(pace_ms)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn invalid(column: &'static str, value: &str) -> CsvImportError {
+    CsvImportError::InvalidValue {
+        column,
+        value: value.to_owned(),
+    }
+}
+
+/// Rounds a non-negative number into the target type, dropping values that
+/// don't fit. The Logbook writes zero for missing heart rates and the like.
+fn positive<T: TryFrom<u64>>(value: Option<f64>) -> Option<T> {
+    value
+        .filter(|v| *v > 0.0)
+        .and_then(|v| T::try_from(v.round() as u64).ok())
+}
+
+fn machine_type(name: &str) -> ErgMachineType {
+    let name = name.to_ascii_lowercase();
+    if name.contains("ski") {
+        Ergometer::SkiErg.machine_type()
+    } else if name.contains("bike") {
+        Ergometer::BikeErg.machine_type()
+    } else if name.contains("dynamic") {
+        ErgMachineType::StaticDynamic
+    } else if name.contains("slides") {
+        ErgMachineType::SlidesD
+    } else {
+        Ergometer::RowErg.machine_type()
+    }
+}
+
+/// Read the rows of a Logbook season export.
+pub fn read_logbook_csv<R: Read>(reader: R) -> Result<Vec<LogbookCsvRow>, CsvImportError> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+        .map(|row| row.map_err(CsvImportError::from))
+        .collect()
+}
+
+impl LogbookCsvRow {
+    /// Convert the row to a summary for `user_id`, with a new workout id.
+    pub fn to_summary(&self, user_id: &str) -> Result<WorkoutSummary, CsvImportError> {
+        let start_time = parse_date_time(&self.date).ok_or_else(|| invalid("Date", &self.date))?;
+        let duration_ms = match (self.work_time_s, &self.work_time) {
+            (Some(seconds), _) => (seconds * 1000.0).round() as u32,
+            (None, Some(time)) => {
+                parse_time_ms(time).ok_or_else(|| invalid("Work Time (Formatted)", time))?
+            }
+            (None, None) => 0,
+        };
+        let rest_ms = (self.rest_time_s.unwrap_or(0.0) * 1000.0).round() as u32;
+        let machine_type = machine_type(self.machine.as_deref().unwrap_or_default());
+        let avg_pace_ms_per_500m = parse_pace(
+            self.pace.as_deref(),
+            Ergometer::from_machine_type(Some(machine_type)),
+        )?;
+
+        Ok(WorkoutSummary {
+            workout_id: Uuid::now_v7(),
+            user_id: user_id.to_owned(),
+            start_time,
+            end_time: start_time + (duration_ms + rest_ms) as i128 * 1_000_000,
+            duration_ms,
+            total_distance_m: positive(self.work_distance_m).unwrap_or(0),
+            total_calories: positive(self.total_calories).unwrap_or(0),
+            machine_type: Some(machine_type),
+            avg_heart_rate_bpm: positive(self.avg_heart_rate),
+            avg_power_watts: positive(self.avg_watts),
+            avg_stroke_rate: positive(self.stroke_rate),
+            avg_pace_ms_per_500m,
+            avg_drag_factor: positive(self.drag_factor),
+            total_strokes: positive(self.stroke_count),
+            ..Default::default()
+        })
+    }
+}
+
+/// Read a stroke data export for a workout on `ergometer` that started at
+/// `start_time` (Unix nanoseconds).
+pub fn read_stroke_csv<R: Read>(
+    reader: R,
+    ergometer: Ergometer,
+    start_time: i128,
+) -> Result<Vec<WorkoutSample>, CsvImportError> {
+    let mut samples = Vec::new();
+    for row in csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .deserialize()
+    {
+        let row: StrokeCsvRow = row?;
+        let elapsed_time_ms = parse_time_ms(&row.time).ok_or_else(|| invalid("Time", &row.time))?;
+        let pace_ms_per_500m = parse_pace(row.pace.as_deref(), ergometer)?;
+        samples.push(WorkoutSample {
+            timestamp: start_time + elapsed_time_ms as i128 * 1_000_000,
+            elapsed_time_ms,
+            distance_m: positive(Some(row.distance_m)).unwrap_or(0),
+            heart_rate_bpm: positive(row.heart_rate),
+            power_watts: positive(row.watts),
+            stroke_rate: positive(row.stroke_rate),
+            pace_ms_per_500m,
+            ..Default::default()
+        });
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGBOOK_CSV: &str = "\
+\"Log ID\",\"Date\",\"Description\",\"Work Time (Formatted)\",\"Work Time (Seconds)\",\"Rest Time (Formatted)\",\"Rest Time (Seconds)\",\"Work Distance\",\"Rest Distance\",\"Stroke Rate/Cadence\",\"Stroke Count\",\"Pace\",\"Avg Watts\",\"Cal/Hour\",\"Total Cal\",\"Avg Heart Rate\",\"Drag Factor\",\"Age\",\"Weight\",\"Type\",\"Ranked\",\"Comments\",\"Date Entered\"
+\"81234567\",\"2024-03-02 07:15:00\",\"2000m row\",\"7:30.0\",\"450.0\",\"\",\"\",\"2000\",\"\",\"28\",\"210\",\"1:52.5\",\"246\",\"1146\",\"143\",\"0\",\"125\",\"38\",\"H\",\"RowErg\",\"Yes\",\"\",\"2024-03-02\"
+\"81234568\",\"2024-03-04 18:00:00\",\"3x1000m/3:00r\",\"12:03.7\",\"723.7\",\"6:00.0\",\"360.0\",\"3000\",\"120\",\"44\",\"530\",\"2:00.6\",\"199\",\"985\",\"190\",\"151\",\"80\",\"38\",\"H\",\"SkiErg\",\"No\",\"felt good, strong finish\",\"2024-03-04\"
+";
+
+    #[test]
+    fn test_parse_time_ms() {
+        assert_eq!(parse_time_ms("1:52.3"), Some(112_300));
+        assert_eq!(parse_time_ms("1:02:03.4"), Some(3_723_400));
+        assert_eq!(parse_time_ms("112.3"), Some(112_300));
+        assert_eq!(parse_time_ms(""), None);
+        assert_eq!(parse_time_ms("1:xx"), None);
+    }
+
+    #[test]
+    fn test_logbook_csv() {
+        let rows = read_logbook_csv(LOGBOOK_CSV.as_bytes()).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].log_id, Some(81234568));
+
+        let summary = rows[0].to_summary("user").unwrap();
+        assert_eq!(summary.duration_ms, 450_000);
+        assert_eq!(summary.total_distance_m, 2000);
+        assert_eq!(summary.avg_pace_ms_per_500m, Some(112_500));
+        assert_eq!(summary.avg_power_watts, Some(246));
+        assert_eq!(summary.avg_heart_rate_bpm, None);
+        assert_eq!(summary.machine_type, Some(ErgMachineType::StaticD));
+
+        let summary = rows[1].to_summary("user").unwrap();
+        assert_eq!(summary.machine_type, Some(ErgMachineType::StaticSki));
+        assert_eq!(summary.end_time - summary.start_time, 1_083_700_000_000);
+        assert_eq!(summary.total_strokes, Some(530));
+    }
+
+    #[test]
+    fn test_stroke_csv() {
+        let csv = "\
+Number,Time (seconds),Distance (meters),Pace (seconds),Watts,Cal/Hr,Stroke Rate,Heart Rate
+1,2.1,8.6,1:58.4,210,1022,30,0
+2,4.0,17.9,112.3,247,1149,31,121
+";
+        let samples = read_stroke_csv(csv.as_bytes(), Ergometer::RowErg, 1_000_000_000).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].timestamp, 3_100_000_000);
+        assert_eq!(samples[0].distance_m, 9);
+        assert_eq!(samples[0].pace_ms_per_500m, Some(118_400));
+        assert_eq!(samples[0].heart_rate_bpm, None);
+        assert_eq!(samples[1].pace_ms_per_500m, Some(112_300));
+        assert_eq!(samples[1].power_watts, Some(247));
+
+        // BikeErg paces are per 1000m.
+        let samples = read_stroke_csv(csv.as_bytes(), Ergometer::BikeErg, 0).unwrap();
+        assert_eq!(samples[0].pace_ms_per_500m, Some(59_200));
+    }
+}
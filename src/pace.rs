@@ -0,0 +1,241 @@
+//! The conventions of each Concept2 machine, and conversions between pace,
+//! power and calories using the formulas the PM5 displays them with.
+//!
+//! RowErg and SkiErg paces are shown per 500 m. BikeErg paces are shown per
+//! 1000 m, and its flywheel is geared so that a BikeErg meter takes the work
+//! of half a RowErg meter: 2:00/1000m on the bike is the same power as
+//! 2:00/500m on the rower. Samples and summaries store paces per 500 m on
+//! every machine, as the PM5 reports them; see [`Ergometer::display_pace`].
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::ErgMachineType;
+
+/// Watts for a speed of one meter per second on the RowErg and SkiErg.
+const WATTS_FACTOR: f64 = 2.8;
+/// Calories per hour from power: the PM5 takes 4 kcal of metabolic work per
+/// kcal at the handle, at 0.8604 kcal/h per watt, plus 300 kcal/h at rest.
+const CALORIES_PER_WATT_HOUR: f64 = 4.0 * 0.8604;
+const RESTING_CALORIES_PER_HOUR: f64 = 300.0;
+
+/// The Concept2 machine a workout was done on. A stroke is a pull on the
+/// RowErg, a double-pole plant on the SkiErg and a pedal revolution on the
+/// BikeErg.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Ergometer {
+    #[default]
+    RowErg,
+    SkiErg,
+    BikeErg,
+}
+
+impl Ergometer {
+    /// The machine for a PM5 machine type. Unknown machines are taken as
+    /// rowers, the PM5's default.
+    pub fn from_machine_type(machine_type: Option<ErgMachineType>) -> Self {
+        use ErgMachineType::*;
+        match machine_type {
+            Some(StaticSki | StaticSkiSimulator | MultiergSki) => Self::SkiErg,
+            Some(Bike | BikeArms | BikeNoarms | BikeSimulator | MultiergBike) => Self::BikeErg,
+            _ => Self::RowErg,
+        }
+    }
+
+    /// The machine type imported workouts on this machine are given.
+    pub fn machine_type(&self) -> ErgMachineType {
+        match self {
+            Self::RowErg => ErgMachineType::StaticD,
+            Self::SkiErg => ErgMachineType::StaticSki,
+            Self::BikeErg => ErgMachineType::Bike,
+        }
+    }
+
+    /// Every PM5 machine type that is this machine.
+    pub fn machine_types(self) -> impl Iterator<Item = ErgMachineType> {
+        (0..=u8::MAX)
+            .filter_map(|value| ErgMachineType::try_from(value).ok())
+            .filter(move |machine_type| Self::from_machine_type(Some(*machine_type)) == self)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RowErg => "RowErg",
+            Self::SkiErg => "SkiErg",
+            Self::BikeErg => "BikeErg",
+        }
+    }
+
+    /// The unit of stroke rate: strokes per minute, or revolutions per
+    /// minute on the BikeErg.
+    pub fn stroke_rate_unit(&self) -> &'static str {
+        match self {
+            Self::RowErg | Self::SkiErg => "spm",
+            Self::BikeErg => "rpm",
+        }
+    }
+
+    /// The distance a pace is shown over, in meters.
+    pub fn pace_distance_m(&self) -> u32 {
+        match self {
+            Self::RowErg | Self::SkiErg => 500,
+            Self::BikeErg => 1000,
+        }
+    }
+
+    /// How many RowErg meters one meter on this machine is worth.
+    fn meter_scale(&self) -> f64 {
+        match self {
+            Self::RowErg | Self::SkiErg => 1.0,
+            Self::BikeErg => 0.5,
+        }
+    }
+
+    /// A stored pace per 500 m as shown on this machine, over
+    /// [`Self::pace_distance_m`].
+    pub fn display_pace(&self, pace_ms_per_500m: u32) -> u32 {
+        pace_ms_per_500m * (self.pace_distance_m() / 500)
+    }
+
+    /// A pace as shown on this machine, back to per 500 m for storage.
+    pub fn pace_per_500m(&self, pace_ms: u32) -> u32 {
+        pace_ms / (self.pace_distance_m() / 500)
+    }
+
+    /// A stored pace per 500 m as shown on this machine, e.g. `1:52.5/500m`.
+    pub fn format_pace(&self, pace_ms_per_500m: u32) -> String {
+        format!(
+            "{}/{}m",
+            format_time_ms(self.display_pace(pace_ms_per_500m)),
+            self.pace_distance_m()
+        )
+    }
+
+    /// Average power for a speed in meters per second.
+    pub fn watts_from_speed(&self, meters_per_second: f64) -> f64 {
+        WATTS_FACTOR * (meters_per_second * self.meter_scale()).powi(3)
+    }
+
+    /// The speed in meters per second that takes `watts`.
+    pub fn speed_from_watts(&self, watts: f64) -> f64 {
+        (watts.max(0.0) / WATTS_FACTOR).cbrt() / self.meter_scale()
+    }
+
+    /// Average power for a pace over [`Self::pace_distance_m`]. A zero pace
+    /// is no power.
+    pub fn watts_from_pace(&self, pace_ms: u32) -> f64 {
+        if pace_ms == 0 {
+            return 0.0;
+        }
+        self.watts_from_speed(self.pace_distance_m() as f64 * 1000.0 / pace_ms as f64)
+    }
+
+    /// The pace over [`Self::pace_distance_m`] that takes `watts`. `None` for
+    /// no power, which has no pace.
+    pub fn pace_from_watts(&self, watts: f64) -> Option<u32> {
+        let speed = self.speed_from_watts(watts);
+        (speed > 0.0).then(|| (self.pace_distance_m() as f64 * 1000.0 / speed).round() as u32)
+    }
+
+    pub fn calories_per_hour_from_pace(&self, pace_ms: u32) -> f64 {
+        calories_per_hour(self.watts_from_pace(pace_ms))
+    }
+
+    pub fn pace_from_calories_per_hour(&self, calories_per_hour: f64) -> Option<u32> {
+        self.pace_from_watts(watts_from_calories_per_hour(calories_per_hour))
+    }
+}
+
+/// Calories per hour the PM5 shows for an average power. The same on all
+/// machines.
+pub fn calories_per_hour(watts: f64) -> f64 {
+    watts.max(0.0) * CALORIES_PER_WATT_HOUR + RESTING_CALORIES_PER_HOUR
+}
+
+/// The average power that burns `calories_per_hour`. Rates at or below the
+/// resting rate are no power.
+pub fn watts_from_calories_per_hour(calories_per_hour: f64) -> f64 {
+    ((calories_per_hour - RESTING_CALORIES_PER_HOUR) / CALORIES_PER_WATT_HOUR).max(0.0)
+}
+
+/// Format a time as the PM5 shows it, e.g. `1:52.5` or `1:02:03.4`, to a
+/// tenth of a second.
+pub fn format_time_ms(ms: u32) -> String {
+    let tenths = (ms + 50) / 100;
+    let (hours, minutes) = (tenths / 36_000, tenths / 600 % 60);
+    let seconds = tenths % 600;
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{:02}.{}", seconds / 10, seconds % 10)
+    } else {
+        format!("{minutes}:{:02}.{}", seconds / 10, seconds % 10)
+    }
+}
+
+/// The time to cover `distance_m` at a pace over `pace_distance_m`.
+pub fn time_for_distance(pace_ms: u32, pace_distance_m: u32, distance_m: u32) -> u32 {
+    (pace_ms as u64 * distance_m as u64 / pace_distance_m.max(1) as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pace_and_watts() {
+        // 2:00.0/500m is 202.5 W, as on the Concept2 pace calculator.
+        let watts = Ergometer::RowErg.watts_from_pace(120_000);
+        assert!((watts - 202.546).abs() < 0.001, "{watts}");
+        assert_eq!(Ergometer::RowErg.pace_from_watts(watts), Some(120_000));
+        assert_eq!(Ergometer::SkiErg.watts_from_pace(120_000), watts);
+        // The bike's pace is per 1000 m, at the same power.
+        assert!((Ergometer::BikeErg.watts_from_pace(120_000) - watts).abs() < 1e-9);
+        assert_eq!(Ergometer::BikeErg.pace_from_watts(watts), Some(120_000));
+
+        assert_eq!(Ergometer::RowErg.watts_from_pace(0), 0.0);
+        assert_eq!(Ergometer::RowErg.pace_from_watts(0.0), None);
+    }
+
+    #[test]
+    fn test_calories() {
+        let watts = Ergometer::RowErg.watts_from_pace(120_000);
+        let calories = calories_per_hour(watts);
+        assert!((calories - 997.08).abs() < 0.01, "{calories}");
+        assert!((watts_from_calories_per_hour(calories) - watts).abs() < 1e-9);
+        assert_eq!(
+            Ergometer::RowErg.pace_from_calories_per_hour(calories),
+            Some(120_000)
+        );
+        assert_eq!(watts_from_calories_per_hour(250.0), 0.0);
+    }
+
+    #[test]
+    fn test_machine_type() {
+        assert_eq!(
+            Ergometer::from_machine_type(Some(ErgMachineType::MultiergSki)),
+            Ergometer::SkiErg
+        );
+        assert_eq!(
+            Ergometer::from_machine_type(Some(ErgMachineType::BikeArms)),
+            Ergometer::BikeErg
+        );
+        assert_eq!(Ergometer::from_machine_type(None), Ergometer::RowErg);
+        for ergometer in [Ergometer::RowErg, Ergometer::SkiErg, Ergometer::BikeErg] {
+            assert_eq!(
+                Ergometer::from_machine_type(Some(ergometer.machine_type())),
+                ergometer
+            );
+        }
+        assert_eq!(Ergometer::BikeErg.machine_types().count(), 5);
+        assert_eq!(time_for_distance(120_000, 500, 2000), 480_000);
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(format_time_ms(112_500), "1:52.5");
+        assert_eq!(format_time_ms(59_960), "1:00.0");
+        assert_eq!(format_time_ms(3_723_400), "1:02:03.4");
+        assert_eq!(Ergometer::SkiErg.format_pace(112_500), "1:52.5/500m");
+        assert_eq!(Ergometer::BikeErg.format_pace(60_000), "2:00.0/1000m");
+        assert_eq!(Ergometer::BikeErg.pace_per_500m(120_000), 60_000);
+        assert_eq!(Ergometer::BikeErg.stroke_rate_unit(), "rpm");
+    }
+}
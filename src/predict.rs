@@ -0,0 +1,261 @@
+//! Race time predictions from best efforts, by three models:
+//!
+//! - Paul's Law: each doubling of distance slows the pace by 5 s/500m.
+//! - Riegel: time grows with distance to a power, fitted to the efforts
+//!   when there are enough of them.
+//! - Critical power: the distance the power held for a time covers.
+
+use serde::{Deserialize, Serialize};
+
+use crate::efforts::{BestEfforts, CriticalPower};
+use crate::pace::Ergometer;
+
+/// Distances predicted by default, in meters. The last is a half marathon.
+pub const RACE_DISTANCES_M: [u32; 5] = [2000, 5000, 6000, 10000, 21097];
+
+/// Seconds per 500 m added to the pace for each doubling of distance.
+const PAULS_LAW_S: f64 = 5.0;
+/// Riegel's own exponent, used when too few efforts to fit one.
+const RIEGEL_EXPONENT: f64 = 1.06;
+/// Fitted exponents outside this range come from efforts that weren't all
+/// maximal, and are not used.
+const RIEGEL_EXPONENT_RANGE: (f64, f64) = (1.0, 1.2);
+/// Shorter efforts are sprints, which predict longer races poorly.
+const MIN_EFFORT_DISTANCE_M: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PredictionModel {
+    PaulsLaw,
+    Riegel,
+    CriticalPower,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RacePrediction {
+    pub model: PredictionModel,
+    pub distance_m: u32,
+    pub time_ms: u32,
+    /// Over the machine's pace distance, 1000 m on the BikeErg and 500 m
+    /// otherwise.
+    pub pace_ms: u32,
+    pub avg_power_watts: f64,
+}
+
+/// Predicts race times from a set of best efforts on one machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RacePredictor {
+    pub ergometer: Ergometer,
+    /// Distances and times in milliseconds of the efforts predicted from.
+    pub efforts: Vec<(u32, u32)>,
+    pub riegel_exponent: f64,
+    pub critical_power: Option<CriticalPower>,
+}
+
+impl RacePredictor {
+    /// Predict from the fastest distances and most distance times in
+    /// `efforts` of at least 1000 m. Riegel's exponent is fitted when there
+    /// are efforts over at least two distances.
+    pub fn from_efforts(efforts: &BestEfforts, ergometer: Ergometer) -> Self {
+        let mut points: Vec<(u32, u32)> = efforts
+            .distances
+            .iter()
+            .map(|e| (e.distance_m, e.time_ms))
+            .chain(
+                efforts
+                    .durations
+                    .iter()
+                    .map(|e| (e.distance_m, e.duration_s * 1000)),
+            )
+            .filter(|(distance, time)| *distance >= MIN_EFFORT_DISTANCE_M && *time > 0)
+            .collect();
+        points.sort_unstable();
+        points.dedup_by_key(|(distance, _)| *distance);
+        Self {
+            ergometer,
+            riegel_exponent: fit_riegel_exponent(&points).unwrap_or(RIEGEL_EXPONENT),
+            efforts: points,
+            critical_power: efforts.critical_power(),
+        }
+    }
+
+    /// Predictions by each model that can make one, for each of
+    /// [`RACE_DISTANCES_M`].
+    pub fn predict_races(&self) -> Vec<RacePrediction> {
+        RACE_DISTANCES_M
+            .iter()
+            .flat_map(|distance| self.predict(*distance))
+            .collect()
+    }
+
+    /// Predictions by each model that can make one for `distance_m`.
+    pub fn predict(&self, distance_m: u32) -> Vec<RacePrediction> {
+        [
+            (PredictionModel::PaulsLaw, self.pauls_law(distance_m)),
+            (PredictionModel::Riegel, self.riegel(distance_m)),
+            (PredictionModel::CriticalPower, self.cp_model(distance_m)),
+        ]
+        .into_iter()
+        .filter_map(|(model, time_ms)| Some(self.prediction(model, distance_m, time_ms?)))
+        .collect()
+    }
+
+    /// From the effort nearest in distance, by ratio.
+    fn pauls_law(&self, distance_m: u32) -> Option<u32> {
+        let (from_m, time_ms) = self.nearest_effort(distance_m)?;
+        let split_s = time_ms as f64 / 1000.0 / from_m as f64 * 500.0;
+        let split_s = split_s + PAULS_LAW_S * (distance_m as f64 / from_m as f64).log2();
+        Some((split_s * distance_m as f64 / 500.0 * 1000.0).round() as u32)
+    }
+
+    /// From the effort nearest in distance, by ratio.
+    fn riegel(&self, distance_m: u32) -> Option<u32> {
+        let (from_m, time_ms) = self.nearest_effort(distance_m)?;
+        let ratio = distance_m as f64 / from_m as f64;
+        Some((time_ms as f64 * ratio.powf(self.riegel_exponent)).round() as u32)
+    }
+
+    /// The time at which the power the model predicts for it covers
+    /// `distance_m`, found by bisection. Distance covered grows with time.
+    fn cp_model(&self, distance_m: u32) -> Option<u32> {
+        let cp = self.critical_power?;
+        let distance_at =
+            |seconds: f64| self.ergometer.speed_from_watts(cp.power_for(seconds)) * seconds;
+        let (mut low, mut high) = (1.0, 24.0 * 3600.0);
+        if distance_at(high) < distance_m as f64 {
+            return None;
+        }
+        while high - low > 0.01 {
+            let mid = (low + high) / 2.0;
+            if distance_at(mid) < distance_m as f64 {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Some((high * 1000.0).round() as u32)
+    }
+
+    fn nearest_effort(&self, distance_m: u32) -> Option<(u32, u32)> {
+        self.efforts.iter().copied().min_by(|a, b| {
+            let log_ratio =
+                |(from_m, _): (u32, u32)| (distance_m as f64 / from_m as f64).ln().abs();
+            log_ratio(*a).total_cmp(&log_ratio(*b))
+        })
+    }
+
+    fn prediction(&self, model: PredictionModel, distance_m: u32, time_ms: u32) -> RacePrediction {
+        let pace_ms =
+            (time_ms as u64 * self.ergometer.pace_distance_m() as u64 / distance_m as u64) as u32;
+        RacePrediction {
+            model,
+            distance_m,
+            time_ms,
+            pace_ms,
+            avg_power_watts: self.ergometer.watts_from_pace(pace_ms),
+        }
+    }
+}
+
+/// Least squares slope of log time against log distance, if within
+/// [`RIEGEL_EXPONENT_RANGE`].
+fn fit_riegel_exponent(points: &[(u32, u32)]) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let logs: Vec<(f64, f64)> = points
+        .iter()
+        .map(|(distance, time)| ((*distance as f64).ln(), (*time as f64).ln()))
+        .collect();
+    let n = logs.len() as f64;
+    let mean_d = logs.iter().map(|(d, _)| d).sum::<f64>() / n;
+    let mean_t = logs.iter().map(|(_, t)| t).sum::<f64>() / n;
+    let covariance: f64 = logs.iter().map(|(d, t)| (d - mean_d) * (t - mean_t)).sum();
+    let variance: f64 = logs.iter().map(|(d, _)| (d - mean_d).powi(2)).sum();
+    let exponent = covariance / variance;
+    let (min, max) = RIEGEL_EXPONENT_RANGE;
+    (min..=max).contains(&exponent).then_some(exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::efforts::{DistanceEffort, PowerEffort};
+
+    use super::*;
+
+    fn distance(distance_m: u32, time_ms: u32) -> DistanceEffort {
+        DistanceEffort {
+            distance_m,
+            time_ms,
+            start_ms: 0,
+            workout_id: None,
+        }
+    }
+
+    #[test]
+    fn test_pauls_law_and_riegel() {
+        // A 7:00.0 2k is 1:45.0/500m.
+        let efforts = BestEfforts {
+            distances: vec![distance(500, 90_000), distance(2000, 420_000)],
+            ..Default::default()
+        };
+        let predictor = RacePredictor::from_efforts(&efforts, Ergometer::RowErg);
+        assert_eq!(predictor.efforts, vec![(2000, 420_000)]);
+        assert_eq!(predictor.riegel_exponent, 1.06);
+
+        let predictions = predictor.predict(8000);
+        assert_eq!(predictions.len(), 2);
+        // Two doublings: 1:55.0/500m for 8k.
+        assert_eq!(predictions[0].model, PredictionModel::PaulsLaw);
+        assert_eq!(predictions[0].time_ms, 16 * 115_000);
+        assert_eq!(predictions[0].pace_ms, 115_000);
+        let riegel = 420_000.0 * 4f64.powf(1.06);
+        assert_eq!(predictions[1].time_ms, riegel.round() as u32);
+        assert_eq!(predictor.predict(2000)[0].time_ms, 420_000);
+    }
+
+    #[test]
+    fn test_fitted_exponent() {
+        let time = |distance: f64| (420_000.0 * (distance / 2000.0).powf(1.08)).round() as u32;
+        let efforts = BestEfforts {
+            distances: vec![
+                distance(2000, time(2000.0)),
+                distance(5000, time(5000.0)),
+                distance(10000, time(10000.0)),
+            ],
+            ..Default::default()
+        };
+        let predictor = RacePredictor::from_efforts(&efforts, Ergometer::RowErg);
+        assert!((predictor.riegel_exponent - 1.08).abs() < 1e-4);
+
+        // Sub-maximal efforts give an exponent that isn't used.
+        assert_eq!(
+            fit_riegel_exponent(&[(2000, 420_000), (5000, 2_000_000)]),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cp_model() {
+        let power = |duration_s: u32| PowerEffort {
+            duration_s,
+            avg_power_watts: 250.0 + 20_000.0 / duration_s as f64,
+            start_ms: 0,
+            workout_id: None,
+        };
+        let efforts = BestEfforts {
+            power: vec![power(180), power(600), power(1200)],
+            ..Default::default()
+        };
+        for ergometer in [Ergometer::RowErg, Ergometer::BikeErg] {
+            let predictor = RacePredictor::from_efforts(&efforts, ergometer);
+            let predictions = predictor.predict_races();
+            assert_eq!(predictions.len(), RACE_DISTANCES_M.len());
+            for prediction in predictions {
+                assert_eq!(prediction.model, PredictionModel::CriticalPower);
+                let seconds = prediction.time_ms as f64 / 1000.0;
+                let expected = 250.0 + 20_000.0 / seconds;
+                assert!((prediction.avg_power_watts - expected).abs() < 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Personal records per user and machine type, detected as workouts are
+//! saved.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::efforts::{BestEfforts, EFFORT_DISTANCES_M, EFFORT_TIMES_S};
+use crate::types::ErgMachineType;
+use crate::workout::{WorkoutSample, WorkoutSummary};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordKind {
+    /// Fastest time over a standard distance, in milliseconds.
+    FastestDistance { distance_m: u32 },
+    /// Most distance in a standard time, in meters.
+    MostDistance { duration_s: u32 },
+    /// Highest power of a single stroke, in watts.
+    PeakStrokePower,
+    /// Highest mean power over a minute, in watts.
+    PeakMinutePower,
+}
+
+impl RecordKind {
+    fn lower_is_better(self) -> bool {
+        matches!(self, RecordKind::FastestDistance { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersonalRecord {
+    pub kind: RecordKind,
+    pub machine_type: Option<ErgMachineType>,
+    /// In the unit of the record's kind.
+    pub value: f64,
+    pub workout_id: Uuid,
+    /// When the workout started, as a Unix timestamp in nanoseconds.
+    pub start_time: i128,
+    /// Elapsed time into the workout at the start of the effort.
+    pub offset_ms: u32,
+}
+
+impl PersonalRecord {
+    /// Whether this is the better of two records of the same kind.
+    pub fn beats(&self, other: &PersonalRecord) -> bool {
+        if self.kind.lower_is_better() {
+            self.value < other.value
+        } else {
+            self.value > other.value
+        }
+    }
+
+    /// Whether both are records of the same kind on the same machine.
+    pub fn is_same_record(&self, other: &PersonalRecord) -> bool {
+        self.kind == other.kind && self.machine_type == other.machine_type
+    }
+}
+
+/// The best effort of each kind in a workout. A workout of exactly a
+/// standard distance or time also counts as a whole, so that workouts stored
+/// without samples can set records too.
+pub fn workout_records(summary: &WorkoutSummary, samples: &[WorkoutSample]) -> Vec<PersonalRecord> {
+    let peak_stroke = samples
+        .iter()
+        .filter_map(|s| Some((s.power_watts?, s.elapsed_time_ms)))
+        .max_by_key(|(power_watts, _)| *power_watts);
+    records_from_efforts(summary, &BestEfforts::from_samples(samples), peak_stroke)
+}
+
+/// Like [`workout_records`], from a frame with at least the
+/// `elapsed_time_ms`, `distance_m` and `power_watts` sample columns.
+pub fn workout_records_from_dataframe(
+    summary: &WorkoutSummary,
+    df: &DataFrame,
+) -> PolarsResult<Vec<PersonalRecord>> {
+    let elapsed = df.column("elapsed_time_ms")?.cast(&DataType::UInt32)?;
+    let power = df.column("power_watts")?.cast(&DataType::UInt32)?;
+    let peak_stroke = power
+        .u32()?
+        .iter()
+        .zip(elapsed.u32()?.iter())
+        .filter_map(|(power_watts, elapsed_ms)| {
+            Some((power_watts? as u16, elapsed_ms.unwrap_or(0)))
+        })
+        .max_by_key(|(power_watts, _)| *power_watts);
+    Ok(records_from_efforts(
+        summary,
+        &BestEfforts::from_dataframe(df)?,
+        peak_stroke,
+    ))
+}
+
+/// `peak_stroke` is the highest power of a stroke, and when it was.
+fn records_from_efforts(
+    summary: &WorkoutSummary,
+    efforts: &BestEfforts,
+    peak_stroke: Option<(u16, u32)>,
+) -> Vec<PersonalRecord> {
+    let record = |kind, value: f64, offset_ms| PersonalRecord {
+        kind,
+        machine_type: summary.machine_type,
+        value,
+        workout_id: summary.workout_id,
+        start_time: summary.start_time,
+        offset_ms,
+    };
+
+    let mut records = Vec::new();
+    if EFFORT_DISTANCES_M.contains(&summary.total_distance_m) && summary.duration_ms > 0 {
+        records.push(record(
+            RecordKind::FastestDistance {
+                distance_m: summary.total_distance_m,
+            },
+            summary.duration_ms as f64,
+            0,
+        ));
+    }
+    if summary.duration_ms.is_multiple_of(1000)
+        && EFFORT_TIMES_S.contains(&(summary.duration_ms / 1000))
+    {
+        records.push(record(
+            RecordKind::MostDistance {
+                duration_s: summary.duration_ms / 1000,
+            },
+            summary.total_distance_m as f64,
+            0,
+        ));
+    }
+
+    records.extend(efforts.distances.iter().map(|e| {
+        record(
+            RecordKind::FastestDistance {
+                distance_m: e.distance_m,
+            },
+            e.time_ms as f64,
+            e.start_ms,
+        )
+    }));
+    records.extend(efforts.durations.iter().map(|e| {
+        record(
+            RecordKind::MostDistance {
+                duration_s: e.duration_s,
+            },
+            e.distance_m as f64,
+            e.start_ms,
+        )
+    }));
+    // Workouts without power readings have a power curve of zeros.
+    if let Some(e) = efforts
+        .power
+        .iter()
+        .find(|e| e.duration_s == 60 && e.avg_power_watts > 0.0)
+    {
+        records.push(record(
+            RecordKind::PeakMinutePower,
+            e.avg_power_watts,
+            e.start_ms,
+        ));
+    }
+    if let Some((power_watts, elapsed_ms)) = peak_stroke {
+        records.push(record(
+            RecordKind::PeakStrokePower,
+            power_watts as f64,
+            elapsed_ms,
+        ));
+    }
+
+    // A whole workout and an effort within it can both cover a distance.
+    let mut best: Vec<PersonalRecord> = Vec::new();
+    for candidate in records {
+        match best.iter_mut().find(|r| r.is_same_record(&candidate)) {
+            Some(r) if candidate.beats(r) => *r = candidate,
+            Some(_) => {}
+            None => best.push(candidate),
+        }
+    }
+    best
+}
+
+/// Merge `candidates` into `records`, returning the ones that set a new
+/// record.
+pub fn update_records(
+    records: &mut Vec<PersonalRecord>,
+    candidates: Vec<PersonalRecord>,
+) -> Vec<PersonalRecord> {
+    let mut new = Vec::new();
+    for candidate in candidates {
+        match records.iter_mut().find(|r| r.is_same_record(&candidate)) {
+            Some(r) if !candidate.beats(r) => continue,
+            Some(r) => *r = candidate.clone(),
+            None => records.push(candidate.clone()),
+        }
+        new.push(candidate);
+    }
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(distance_m: u32, duration_ms: u32) -> WorkoutSummary {
+        WorkoutSummary {
+            workout_id: Uuid::now_v7(),
+            total_distance_m: distance_m,
+            duration_ms,
+            machine_type: Some(ErgMachineType::StaticD),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summary_only_records() {
+        let records = workout_records(&summary(2000, 420_000), &[]);
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].kind,
+            RecordKind::FastestDistance { distance_m: 2000 }
+        );
+
+        let records = workout_records(&summary(8000, 1_800_000), &[]);
+        assert_eq!(
+            records[0].kind,
+            RecordKind::MostDistance { duration_s: 1800 }
+        );
+        assert_eq!(records[0].value, 8000.0);
+    }
+
+    #[test]
+    fn test_update_records() {
+        let mut records = Vec::new();
+        let first = workout_records(&summary(2000, 420_000), &[]);
+        assert_eq!(update_records(&mut records, first).len(), 1);
+
+        let slower = workout_records(&summary(2000, 430_000), &[]);
+        assert!(update_records(&mut records, slower).is_empty());
+
+        let faster = workout_records(&summary(2000, 410_000), &[]);
+        let new = update_records(&mut records, faster.clone());
+        assert_eq!(new, faster);
+        assert_eq!(records, faster);
+
+        let mut ski = summary(2000, 500_000);
+        ski.machine_type = Some(ErgMachineType::StaticSki);
+        let new = update_records(&mut records, workout_records(&ski, &[]));
+        assert_eq!(new.len(), 1);
+        assert_eq!(records.len(), 2);
+    }
+}
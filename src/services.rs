@@ -13,7 +13,17 @@ pub enum Pm5 {
 
 impl Pm5 {
     pub fn rowing() -> &'static [Rowing] {
-        &[Rowing::GeneralStatus, Rowing::StrokeData]
+        &[
+            Rowing::GeneralStatus,
+            Rowing::AdditionalStatusOne,
+            Rowing::AdditionalStatusTwo,
+            Rowing::StrokeData,
+            Rowing::AdditionalStrokeData,
+            Rowing::SplitIntervalData,
+            Rowing::AdditionalSplitIntervalData,
+            Rowing::EndOfWorkoutSummaryData,
+            Rowing::AdditionalEndOfWorkoutSummaryData,
+        ]
     }
 }
 
@@ -272,6 +282,20 @@ impl ServiceData for Rowing {
             });
         }
 
+        if Rowing::AdditionalStatusTwo.id() == uuid {
+            return Ok(RowingData::AdditionalStatusTwo {
+                elapsed_time: Parse::parse(&mut data)?,
+                interval_count: Parse::parse(&mut data)?,
+                average_power: Parse::parse(&mut data)?,
+                total_calories: Parse::parse(&mut data)?,
+                split_interval_avg_pace: Parse::parse(&mut data)?,
+                split_interval_avg_power: Parse::parse(&mut data)?,
+                split_interval_avg_calories: Parse::parse(&mut data)?,
+                last_split_time: Parse::parse(&mut data)?,
+                last_split_distance: Parse::parse(&mut data)?,
+            });
+        }
+
         if Rowing::StrokeData.id() == uuid {
             return Ok(RowingData::StrokeData {
                 elapsed_time: Parse::parse(&mut data)?,
@@ -298,6 +322,69 @@ impl ServiceData for Rowing {
             });
         }
 
+        if Rowing::SplitIntervalData.id() == uuid {
+            return Ok(RowingData::SplitIntervalData {
+                elapsed_time: Parse::parse(&mut data)?,
+                distance: Parse::parse(&mut data)?,
+                split_interval_time: Parse::parse(&mut data)?,
+                split_interval_distance: Parse::parse(&mut data)?,
+                interval_rest_time: Parse::parse(&mut data)?,
+                interval_rest_distance: Parse::parse(&mut data)?,
+                split_interval_type: Parse::parse(&mut data)?,
+                split_interval_number: Parse::parse(&mut data)?,
+            });
+        }
+
+        if Rowing::AdditionalSplitIntervalData.id() == uuid {
+            return Ok(RowingData::AdditionalSplitIntervalData {
+                elapsed_time: Parse::parse(&mut data)?,
+                split_interval_avg_stroke_rate: Parse::parse(&mut data)?,
+                split_interval_work_heartrate: Parse::parse(&mut data)?,
+                split_interval_rest_heartrate: Parse::parse(&mut data)?,
+                split_interval_avg_pace: Parse::parse(&mut data)?,
+                split_interval_total_calories: Parse::parse(&mut data)?,
+                split_interval_avg_calories: Parse::parse(&mut data)?,
+                split_interval_speed: Parse::parse(&mut data)?,
+                split_interval_power: Parse::parse(&mut data)?,
+                split_avg_drag_factor: Parse::parse(&mut data)?,
+                split_interval_number: Parse::parse(&mut data)?,
+                erg_machine_type: Parse::parse(&mut data)?,
+            });
+        }
+
+        if Rowing::EndOfWorkoutSummaryData.id() == uuid {
+            return Ok(RowingData::EndOfWorkoutSummaryData {
+                log_entry_date: Parse::parse(&mut data)?,
+                log_entry_time: Parse::parse(&mut data)?,
+                elapsed_time: Parse::parse(&mut data)?,
+                distance: Parse::parse(&mut data)?,
+                avg_stroke_rate: Parse::parse(&mut data)?,
+                ending_heartrate: Parse::parse(&mut data)?,
+                avg_heartrate: Parse::parse(&mut data)?,
+                min_heartrate: Parse::parse(&mut data)?,
+                max_heartrate: Parse::parse(&mut data)?,
+                drag_factor_avg: Parse::parse(&mut data)?,
+                recover_heartrate: Parse::parse(&mut data)?,
+                workout_type: Parse::parse(&mut data)?,
+                avg_pace: Parse::parse(&mut data)?,
+            });
+        }
+
+        if Rowing::AdditionalEndOfWorkoutSummaryData.id() == uuid {
+            return Ok(RowingData::AdditionalEndOfWorkoutSummaryData {
+                log_entry_date: Parse::parse(&mut data)?,
+                log_entry_time: Parse::parse(&mut data)?,
+                split_interval_type: Parse::parse(&mut data)?,
+                split_interval_size: Parse::parse(&mut data)?,
+                split_interval_count: Parse::parse(&mut data)?,
+                total_calories: Parse::parse(&mut data)?,
+                watts: Parse::parse(&mut data)?,
+                total_rest_distance: Parse::parse(&mut data)?,
+                interval_rest_time: Parse::parse(&mut data)?,
+                avg_calories: Parse::parse(&mut data)?,
+            });
+        }
+
         Err(ServiceDataError::Id)
     }
 }
@@ -336,21 +423,21 @@ impl Service for Rowing {
     #[inline]
     fn id(&self) -> Uuid {
         let b = match self {
-            Rowing::GeneralStatus { .. } => 0x0001,
-            Rowing::AdditionalStatusOne { .. } => 0x0002,
-            Rowing::AdditionalStatusTwo { .. } => 0x0003,
-            Rowing::GeneralStatusRate { .. } => 0x0004,
-            Rowing::StrokeData { .. } => 0x0005,
-            Rowing::AdditionalStrokeData { .. } => 0x0006,
-            Rowing::SplitIntervalData { .. } => 0x0007,
-            Rowing::AdditionalSplitIntervalData { .. } => 0x0008,
-            Rowing::EndOfWorkoutSummaryData { .. } => 0x0009,
-            Rowing::AdditionalEndOfWorkoutSummaryData { .. } => 0x000A,
-            Rowing::HeartRateBeltInformation { .. } => 0x000B,
-            Rowing::AdditionalEndOfWorkoutSummaryDataTwo { .. } => 0x000C,
-            Rowing::ForceCurveData { .. } => 0x000D,
-            Rowing::AdditionalStatusThree { .. } => 0x000E,
-            Rowing::MultiplexedInformation { .. } => 0x000F,
+            Rowing::GeneralStatus => 0x0001,
+            Rowing::AdditionalStatusOne => 0x0002,
+            Rowing::AdditionalStatusTwo => 0x0003,
+            Rowing::GeneralStatusRate => 0x0004,
+            Rowing::StrokeData => 0x0005,
+            Rowing::AdditionalStrokeData => 0x0006,
+            Rowing::SplitIntervalData => 0x0007,
+            Rowing::AdditionalSplitIntervalData => 0x0008,
+            Rowing::EndOfWorkoutSummaryData => 0x0009,
+            Rowing::AdditionalEndOfWorkoutSummaryData => 0x000A,
+            Rowing::HeartRateBeltInformation => 0x000B,
+            Rowing::AdditionalEndOfWorkoutSummaryDataTwo => 0x000C,
+            Rowing::ForceCurveData => 0x000D,
+            Rowing::AdditionalStatusThree => 0x000E,
+            Rowing::MultiplexedInformation => 0x000F,
         };
         Uuid::from_u128(Self::UUID.as_u128() | b << 96)
     }
@@ -373,10 +460,6 @@ impl Service for Heartrate {
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
-
-    use crate::parse::Parse;
-
     use super::*;
 
     #[test]
@@ -468,21 +551,14 @@ mod tests {
         ];
 
         for sample in samples {
-            let mut data: Cursor<Vec<u8>> = Cursor::new(sample.to_vec());
-            // let status = RowingData::GeneralStatus {
-            //     elapsed_time: ,
-            //     distance: Default::default(),
-            //     workout_type: Default::default(),
-            //     interval_type: Default::default(),
-            //     workout_state: Default::default(),
-            //     rowing_state: Default::default(),
-            //     stroke_state: Default::default(),
-            //     total_work_distance: Default::default(),
-            //     workout_duration: Default::default(),
-            //     workout_duration_type: Default::default(),
-            //     drag_factor: Default::default(),
-            // };
-            // println!("data: {:#?}", Rowing::GeneralStatus.parse(&mut data));
+            let parsed = Rowing::parse(Rowing::GeneralStatus.id(), sample.to_vec()).unwrap();
+            assert!(matches!(
+                parsed,
+                RowingData::GeneralStatus {
+                    workout_state: WorkoutState::WorkoutRow,
+                    ..
+                }
+            ));
         }
     }
 }
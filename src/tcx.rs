@@ -0,0 +1,259 @@
+use std::fmt::Write;
+
+use time::UtcDateTime;
+
+use crate::pace::Ergometer;
+use crate::workout::{DeviceInfo, Lap, WorkoutSample, WorkoutSummary};
+
+const TCX_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2";
+const ACTIVITY_EXTENSION_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/ActivityExtension/v2";
+
+/// The TCX schema only knows running and biking, so rowing and skiing are
+/// exported as `Other` and named in the activity notes instead.
+fn sport(ergometer: Ergometer) -> &'static str {
+    match ergometer {
+        Ergometer::BikeErg => "Biking",
+        Ergometer::RowErg | Ergometer::SkiErg => "Other",
+    }
+}
+
+/// Export a workout as Garmin TCX, with one lap per interval or split,
+/// created by the monitor described by `device`.
+pub fn to_tcx(summary: &WorkoutSummary, samples: &[WorkoutSample], device: &DeviceInfo) -> String {
+    let ergometer = summary.ergometer();
+    let (sport, activity) = (sport(ergometer), ergometer.name());
+    let start = format_time(summary.start_time);
+
+    let mut tcx = String::new();
+    tcx.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        tcx,
+        "<TrainingCenterDatabase xmlns=\"{TCX_NAMESPACE}\" xmlns:ns3=\"{ACTIVITY_EXTENSION_NAMESPACE}\">"
+    );
+    tcx.push_str("  <Activities>\n");
+    let _ = writeln!(tcx, "    <Activity Sport=\"{sport}\">");
+    let _ = writeln!(tcx, "      <Id>{start}</Id>");
+
+    for lap in summary.laps(samples) {
+        write_lap(&mut tcx, &lap);
+    }
+
+    let _ = writeln!(tcx, "      <Notes>{activity}</Notes>");
+    write_creator(&mut tcx, device);
+    tcx.push_str("    </Activity>\n");
+    tcx.push_str("  </Activities>\n");
+    tcx.push_str("</TrainingCenterDatabase>\n");
+    tcx
+}
+
+/// The schema requires a unit id, product id and version for a device. The
+/// PM5 has no product id, and whatever the monitor didn't report is 0.
+fn write_creator(tcx: &mut String, device: &DeviceInfo) {
+    let unit_id = device
+        .serial_number
+        .as_deref()
+        .and_then(|serial| serial.trim().parse::<u32>().ok())
+        .unwrap_or(0);
+    let (major, minor) = device
+        .firmware_revision
+        .as_deref()
+        .map_or((0, 0), firmware_version);
+
+    tcx.push_str("      <Creator xsi:type=\"Device_t\" xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">\n");
+    tcx.push_str("        <Name>Concept2 PM5</Name>\n");
+    let _ = writeln!(tcx, "        <UnitId>{unit_id}</UnitId>");
+    tcx.push_str("        <ProductID>0</ProductID>\n");
+    tcx.push_str("        <Version>\n");
+    let _ = writeln!(tcx, "          <VersionMajor>{major}</VersionMajor>");
+    let _ = writeln!(tcx, "          <VersionMinor>{minor}</VersionMinor>");
+    tcx.push_str("        </Version>\n");
+    tcx.push_str("      </Creator>\n");
+}
+
+/// The major and minor version of a firmware revision such as `"32.05"`.
+/// Parts that aren't numbers are 0.
+fn firmware_version(revision: &str) -> (u16, u16) {
+    let mut parts = revision.trim().split('.').map(|part| {
+        let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+        digits.parse().unwrap_or(0)
+    });
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
+
+fn write_lap(tcx: &mut String, lap: &Lap) {
+    let _ = writeln!(
+        tcx,
+        "      <Lap StartTime=\"{}\">",
+        format_time(lap.start_time)
+    );
+    let _ = writeln!(
+        tcx,
+        "        <TotalTimeSeconds>{:.1}</TotalTimeSeconds>",
+        lap.end_ms.saturating_sub(lap.start_ms) as f64 / 1000.0
+    );
+    let _ = writeln!(
+        tcx,
+        "        <DistanceMeters>{}</DistanceMeters>",
+        lap.distance_m
+    );
+    let _ = writeln!(tcx, "        <Calories>{}</Calories>", lap.calories);
+    if let Some(bpm) = lap.avg_heart_rate_bpm {
+        let _ = writeln!(
+            tcx,
+            "        <AverageHeartRateBpm><Value>{bpm}</Value></AverageHeartRateBpm>"
+        );
+    }
+    if let Some(bpm) = lap.max_heart_rate_bpm {
+        let _ = writeln!(
+            tcx,
+            "        <MaximumHeartRateBpm><Value>{bpm}</Value></MaximumHeartRateBpm>"
+        );
+    }
+    tcx.push_str("        <Intensity>Active</Intensity>\n");
+    if let Some(rate) = lap.avg_stroke_rate {
+        let _ = writeln!(tcx, "        <Cadence>{rate}</Cadence>");
+    }
+    tcx.push_str("        <TriggerMethod>Manual</TriggerMethod>\n");
+
+    tcx.push_str("        <Track>\n");
+    for sample in lap.samples {
+        write_trackpoint(tcx, sample);
+    }
+    tcx.push_str("        </Track>\n");
+
+    if let Some(watts) = lap.avg_power_watts {
+        let _ = writeln!(
+            tcx,
+            "        <Extensions><ns3:LX><ns3:AvgWatts>{watts}</ns3:AvgWatts></ns3:LX></Extensions>"
+        );
+    }
+    tcx.push_str("      </Lap>\n");
+}
+
+fn write_trackpoint(tcx: &mut String, sample: &WorkoutSample) {
+    tcx.push_str("          <Trackpoint>\n");
+    let _ = writeln!(
+        tcx,
+        "            <Time>{}</Time>",
+        format_time(sample.timestamp)
+    );
+    let _ = writeln!(
+        tcx,
+        "            <DistanceMeters>{}</DistanceMeters>",
+        sample.distance_m
+    );
+    if let Some(bpm) = sample.heart_rate_bpm {
+        let _ = writeln!(
+            tcx,
+            "            <HeartRateBpm><Value>{bpm}</Value></HeartRateBpm>"
+        );
+    }
+    if let Some(rate) = sample.stroke_rate {
+        let _ = writeln!(tcx, "            <Cadence>{rate}</Cadence>");
+    }
+    if let Some(watts) = sample.power_watts {
+        let _ = writeln!(
+            tcx,
+            "            <Extensions><ns3:TPX><ns3:Watts>{watts}</ns3:Watts></ns3:TPX></Extensions>"
+        );
+    }
+    tcx.push_str("          </Trackpoint>\n");
+}
+
+/// ISO 8601 in UTC with millisecond precision, as TCX expects.
+fn format_time(unix_nanos: i128) -> String {
+    let time =
+        UtcDateTime::from_unix_timestamp_nanos(unix_nanos).unwrap_or(UtcDateTime::UNIX_EPOCH);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        time.year(),
+        time.month() as u8,
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second(),
+        time.millisecond()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::ErgMachineType;
+    use crate::workout::{IntervalSummary, WorkoutRecorder};
+
+    use super::*;
+
+    #[test]
+    fn test_laps_and_trackpoints() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_machine_type(ErgMachineType::StaticSki);
+        for i in 0..20u32 {
+            recorder.set_stroke_data(
+                i * 1000,
+                i * 4,
+                140,
+                800,
+                450,
+                380,
+                85,
+                Some(180),
+                Some(i as u16),
+            );
+            recorder.add_general_sample(i * 1000, i * 4, Some(140), Some(30), None);
+        }
+        let mut summary = recorder.generate_summary(None).unwrap();
+        summary.intervals = vec![
+            IntervalSummary {
+                interval_number: 1,
+                start_elapsed_ms: 0,
+                work_time_ms: 8_000,
+                rest_time_ms: 2_000,
+                work_distance_m: 32,
+                rest_distance_m: 8,
+                ..Default::default()
+            },
+            IntervalSummary {
+                interval_number: 2,
+                start_elapsed_ms: 10_000,
+                work_time_ms: 9_000,
+                work_distance_m: 36,
+                ..Default::default()
+            },
+        ];
+
+        let tcx = recorder.to_tcx(&summary);
+        assert!(tcx.contains("<Activity Sport=\"Other\">"));
+        assert!(tcx.contains("<Notes>SkiErg</Notes>"));
+        assert_eq!(tcx.matches("<Lap ").count(), 2);
+        assert_eq!(tcx.matches("<Trackpoint>").count(), 20);
+        // The first lap takes in its rest, in both time and distance.
+        let first_lap = tcx.split("<Lap ").nth(1).unwrap();
+        assert!(first_lap.contains("<TotalTimeSeconds>10.0</TotalTimeSeconds>"));
+        assert!(first_lap.contains("<DistanceMeters>40</DistanceMeters>"));
+        assert_eq!(first_lap.matches("<Trackpoint>").count(), 10);
+        assert_eq!(tcx.matches("<ns3:Watts>180</ns3:Watts>").count(), 20);
+        assert!(tcx.contains("<Calories>9</Calories>"));
+        assert!(tcx.contains("<Cadence>30</Cadence>"));
+        assert!(tcx.contains("<UnitId>0</UnitId>"));
+        assert!(tcx.contains("<VersionMajor>0</VersionMajor>"));
+
+        recorder.set_device(DeviceInfo {
+            serial_number: Some("430123456".into()),
+            firmware_revision: Some("32.05".into()),
+            ..Default::default()
+        });
+        let tcx = recorder.to_tcx(&summary);
+        assert!(tcx.contains("<UnitId>430123456</UnitId>"));
+        assert!(tcx.contains("<ProductID>0</ProductID>"));
+        assert!(tcx.contains("<VersionMajor>32</VersionMajor>"));
+        assert!(tcx.contains("<VersionMinor>5</VersionMinor>"));
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(
+            format_time(1_700_000_000_123_000_000),
+            "2023-11-14T22:13:20.123Z"
+        );
+    }
+}
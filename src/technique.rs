@@ -0,0 +1,198 @@
+//! Stroke technique: ratios and consistency derived from the PM5's
+//! per-stroke drive and recovery data.
+
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::decoupling::coefficient_of_variation;
+use crate::workout::WorkoutSample;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StrokeTechnique {
+    /// Elapsed time of the first sample with the stroke.
+    pub elapsed_time_ms: u32,
+    pub drive_length_cm: Option<u16>,
+    /// Recovery time over drive time. The textbook ratio is 1:2, or 2.
+    pub drive_recovery_ratio: Option<f64>,
+    /// Drive time as a fraction of the whole stroke.
+    pub rhythm: Option<f64>,
+    pub distance_per_stroke_m: Option<f64>,
+    pub work_per_stroke_j: Option<u16>,
+    /// Work per stroke over body weight, in J/kg.
+    pub work_per_kg: Option<f64>,
+    /// Peak over average drive force. Lower is a fuller, more even drive.
+    pub peak_force_ratio: Option<f64>,
+    /// The change in work from the previous stroke, as a fraction of it.
+    pub work_change: Option<f64>,
+}
+
+impl StrokeTechnique {
+    /// One entry per stroke in `samples`. Samples between strokes repeat the
+    /// last stroke's data, so consecutive samples with the same stroke data
+    /// are taken as the same stroke. Work per kg needs `body_weight_kg`.
+    pub fn from_samples(samples: &[WorkoutSample], body_weight_kg: Option<f64>) -> Vec<Self> {
+        let body_weight_kg = body_weight_kg.filter(|kg| *kg > 0.0);
+        let mut strokes: Vec<Self> = Vec::new();
+        let mut previous = None;
+        for sample in samples {
+            let Some(drive_time_ms) = sample.drive_time_ms else {
+                continue;
+            };
+            let key = (
+                sample.drive_length_cm,
+                drive_time_ms,
+                sample.recovery_time_ms,
+                sample.stroke_distance_cm,
+                sample.peak_drive_force_n,
+                sample.avg_drive_force_n,
+                sample.work_per_stroke_j,
+            );
+            if previous == Some(key) {
+                continue;
+            }
+            previous = Some(key);
+
+            let drive_ms = drive_time_ms as f64;
+            let recovery_ms = sample.recovery_time_ms.map(f64::from);
+            let work = sample.work_per_stroke_j;
+            let previous_work = strokes.last().and_then(|stroke| stroke.work_per_stroke_j);
+            strokes.push(Self {
+                elapsed_time_ms: sample.elapsed_time_ms,
+                drive_length_cm: sample.drive_length_cm,
+                drive_recovery_ratio: recovery_ms
+                    .filter(|_| drive_ms > 0.0)
+                    .map(|recovery| recovery / drive_ms),
+                rhythm: recovery_ms
+                    .filter(|recovery| drive_ms + recovery > 0.0)
+                    .map(|recovery| drive_ms / (drive_ms + recovery)),
+                distance_per_stroke_m: sample.stroke_distance_cm.map(|cm| cm as f64 / 100.0),
+                work_per_stroke_j: work,
+                work_per_kg: work.zip(body_weight_kg).map(|(work, kg)| work as f64 / kg),
+                peak_force_ratio: sample
+                    .peak_drive_force_n
+                    .zip(sample.avg_drive_force_n.filter(|avg| *avg > 0))
+                    .map(|(peak, avg)| peak as f64 / avg as f64),
+                work_change: work
+                    .zip(previous_work.filter(|work| *work > 0))
+                    .map(|(work, previous)| (work as f64 - previous as f64) / previous as f64),
+            });
+        }
+        strokes
+    }
+
+    /// Strokes as a table with a column per field.
+    pub fn to_dataframe(strokes: &[StrokeTechnique]) -> PolarsResult<DataFrame> {
+        let column =
+            |f: fn(&StrokeTechnique) -> Option<f64>| strokes.iter().map(f).collect::<Vec<_>>();
+        df!(
+            "elapsed_time_ms" => strokes.iter().map(|s| s.elapsed_time_ms).collect::<Vec<_>>(),
+            "drive_length_cm" => strokes
+                .iter()
+                .map(|s| s.drive_length_cm.map(u32::from))
+                .collect::<Vec<_>>(),
+            "drive_recovery_ratio" => column(|s| s.drive_recovery_ratio),
+            "rhythm" => column(|s| s.rhythm),
+            "distance_per_stroke_m" => column(|s| s.distance_per_stroke_m),
+            "work_per_stroke_j" => strokes
+                .iter()
+                .map(|s| s.work_per_stroke_j.map(u32::from))
+                .collect::<Vec<_>>(),
+            "work_per_kg" => column(|s| s.work_per_kg),
+            "peak_force_ratio" => column(|s| s.peak_force_ratio),
+            "work_change" => column(|s| s.work_change),
+        )
+    }
+}
+
+/// Averages and stroke-to-stroke variability over a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TechniqueSummary {
+    pub strokes: u32,
+    pub avg_drive_recovery_ratio: Option<f64>,
+    pub avg_rhythm: Option<f64>,
+    pub avg_distance_per_stroke_m: Option<f64>,
+    pub avg_work_per_stroke_j: Option<f64>,
+    pub avg_work_per_kg: Option<f64>,
+    pub avg_peak_force_ratio: Option<f64>,
+    /// Coefficients of variation across strokes. Lower is more consistent.
+    pub drive_length_variation: Option<f64>,
+    pub work_variation: Option<f64>,
+    pub rhythm_variation: Option<f64>,
+}
+
+impl TechniqueSummary {
+    pub fn from_strokes(strokes: &[StrokeTechnique]) -> Self {
+        let values = |f: fn(&StrokeTechnique) -> Option<f64>| -> Vec<f64> {
+            strokes.iter().filter_map(f).collect()
+        };
+        let mean = |values: Vec<f64>| {
+            (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+        };
+        let variation =
+            |values: Vec<f64>| (values.len() >= 2).then(|| coefficient_of_variation(&values));
+        Self {
+            strokes: strokes.len() as u32,
+            avg_drive_recovery_ratio: mean(values(|s| s.drive_recovery_ratio)),
+            avg_rhythm: mean(values(|s| s.rhythm)),
+            avg_distance_per_stroke_m: mean(values(|s| s.distance_per_stroke_m)),
+            avg_work_per_stroke_j: mean(values(|s| s.work_per_stroke_j.map(f64::from))),
+            avg_work_per_kg: mean(values(|s| s.work_per_kg)),
+            avg_peak_force_ratio: mean(values(|s| s.peak_force_ratio)),
+            drive_length_variation: variation(values(|s| s.drive_length_cm.map(f64::from))),
+            work_variation: variation(values(|s| s.work_per_stroke_j.map(f64::from))),
+            rhythm_variation: variation(values(|s| s.rhythm)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stroke(elapsed_time_ms: u32, work_per_stroke_j: u16) -> WorkoutSample {
+        WorkoutSample {
+            elapsed_time_ms,
+            drive_length_cm: Some(140),
+            drive_time_ms: Some(800),
+            recovery_time_ms: Some(1600),
+            stroke_distance_cm: Some(1000),
+            peak_drive_force_n: Some(600),
+            avg_drive_force_n: Some(400),
+            work_per_stroke_j: Some(work_per_stroke_j),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stroke_technique() {
+        let samples = [
+            WorkoutSample::default(),
+            stroke(1000, 500),
+            // A general sample repeating the first stroke.
+            stroke(1500, 500),
+            stroke(3000, 550),
+        ];
+        let strokes = StrokeTechnique::from_samples(&samples, Some(80.0));
+        assert_eq!(strokes.len(), 2);
+        assert_eq!(strokes[0].elapsed_time_ms, 1000);
+        assert_eq!(strokes[0].drive_recovery_ratio, Some(2.0));
+        assert!((strokes[0].rhythm.unwrap() - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(strokes[0].distance_per_stroke_m, Some(10.0));
+        assert_eq!(strokes[0].work_per_kg, Some(6.25));
+        assert_eq!(strokes[0].peak_force_ratio, Some(1.5));
+        assert_eq!(strokes[0].work_change, None);
+        assert!((strokes[1].work_change.unwrap() - 0.1).abs() < 1e-9);
+
+        let summary = TechniqueSummary::from_strokes(&strokes);
+        assert_eq!(summary.strokes, 2);
+        assert_eq!(summary.avg_work_per_stroke_j, Some(525.0));
+        assert_eq!(summary.drive_length_variation, Some(0.0));
+        assert!((summary.work_variation.unwrap() - 25.0 / 525.0).abs() < 1e-9);
+
+        let df = StrokeTechnique::to_dataframe(&strokes).unwrap();
+        assert_eq!(df.height(), 2);
+        assert!(StrokeTechnique::from_samples(&samples, None)[0]
+            .work_per_kg
+            .is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
 
 mod privat {
     use std::io::Read;
@@ -89,7 +90,7 @@ mod privat {
             $(impl crate::parse::Parse for $x {
                 fn parse(cursor: &mut std::io::Cursor<Vec<u8>>) -> Result<Self, crate::parse::ParseError> {
                     let v: u8 = crate::parse::Parse::parse(cursor)?;
-                    Ok(Self::try_from(v).map_err(|_err| crate::parse::ParseError::Variant)?)
+                    Self::try_from(v).map_err(|_err| crate::parse::ParseError::Variant)
                 }
             })*
         };
@@ -128,6 +129,12 @@ mod privat {
 #[derive(Default, Debug)]
 pub struct U24(u32);
 
+impl From<u32> for U24 {
+    fn from(value: u32) -> Self {
+        Self(value & 0x00FF_FFFF)
+    }
+}
+
 impl std::ops::Deref for U24 {
     type Target = u32;
 
@@ -184,7 +191,7 @@ pub struct Force(pub u16);
 #[derive(Default, Debug)]
 pub struct Work(pub u16);
 #[derive(Default, Debug)]
-pub struct Size(pub u8);
+pub struct Size(pub u16);
 #[derive(Default, Debug)]
 pub struct StrokeCount(pub u16);
 #[derive(Default, Debug)]
@@ -249,7 +256,7 @@ pub enum ErgModelType {
     TypeA,
 }
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum ErgMachineType {
     /// Model D, static type (0).
@@ -302,7 +309,7 @@ pub enum ErgMachineType {
     Num,
 }
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum WorkoutType {
     /// JustRow, no splits (0).
@@ -335,7 +342,7 @@ pub enum WorkoutType {
     Num,
 }
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum IntervalType {
     /// Time interval type (0).
@@ -362,7 +369,7 @@ pub enum IntervalType {
     None = 255,
 }
 
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum WorkoutState {
     /// Wait to begin state (0).
@@ -395,6 +402,29 @@ pub enum WorkoutState {
     Rearm,
 }
 
+impl WorkoutState {
+    /// Whether the PM5 is in the rest part of an interval.
+    pub fn is_rest(&self) -> bool {
+        matches!(
+            self,
+            WorkoutState::IntervalRest
+                | WorkoutState::IntervalWorkTimeToRest
+                | WorkoutState::IntervalWorkDistanceToRest
+        )
+    }
+
+    /// Whether the workout on the PM5 has ended and is waiting to be reset.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            WorkoutState::WorkoutEnd
+                | WorkoutState::Terminate
+                | WorkoutState::WorkoutLogged
+                | WorkoutState::Rearm
+        )
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
 #[repr(u8)]
 pub enum RowingState {
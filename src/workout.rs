@@ -1,9 +1,28 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
 use futures::TryStreamExt;
 use polars::prelude::*;
 use serde::{Deserialize, Serialize};
-use time::UtcDateTime;
+use time::{Date, Time, UtcDateTime};
 use uuid::Uuid;
 
+use crate::decoupling::{AerobicDecoupling, DecouplingBasis, DecouplingOptions};
+use crate::drag::{DragFactorReport, DragFactorSummary, DragRecommendation, TrainingFocus};
+use crate::efforts::{BestEfforts, DistanceEffort, DurationEffort};
+use crate::intervals::{detect_intervals, IntervalDetection};
+use crate::load::{daily_load, DailyLoad, LoadMetric, TrainingStress};
+use crate::pace::Ergometer;
+use crate::predict::{RacePrediction, RacePredictor};
+use crate::records::{
+    update_records, workout_records, workout_records_from_dataframe, PersonalRecord, RecordKind,
+};
+use crate::services::RowingData;
+use crate::technique::StrokeTechnique;
+use crate::types::{ErgMachineType, HeartRate, IntervalType, WorkoutState, WorkoutType};
+use crate::zones::{TrainingZones, UserProfile};
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct WorkoutSample {
     pub timestamp: i128,
@@ -20,6 +39,9 @@ pub struct WorkoutSample {
     pub peak_drive_force_n: Option<u16>,
     pub avg_drive_force_n: Option<u16>,
     pub work_per_stroke_j: Option<u16>,
+    pub recovery_time_ms: Option<u32>,
+    pub stroke_distance_cm: Option<u16>,
+    pub drag_factor: Option<u8>,
 }
 
 impl WorkoutSample {
@@ -41,10 +63,7 @@ impl WorkoutSample {
             .iter()
             .map(|s| s.stroke_rate.map(|v| v as u32))
             .collect();
-        let paces: Vec<Option<u32>> = samples
-            .iter()
-            .map(|s| s.pace_ms_per_500m.map(|v| v as u32))
-            .collect();
+        let paces: Vec<Option<u32>> = samples.iter().map(|s| s.pace_ms_per_500m).collect();
         let calories: Vec<Option<u32>> = samples
             .iter()
             .map(|s| s.calories.map(|v| v as u32))
@@ -69,6 +88,15 @@ impl WorkoutSample {
             .iter()
             .map(|s| s.work_per_stroke_j.map(|v| v as u32))
             .collect();
+        let recovery_times: Vec<Option<u32>> = samples.iter().map(|s| s.recovery_time_ms).collect();
+        let stroke_distances: Vec<Option<u32>> = samples
+            .iter()
+            .map(|s| s.stroke_distance_cm.map(|v| v as u32))
+            .collect();
+        let drag_factors: Vec<Option<u32>> = samples
+            .iter()
+            .map(|s| s.drag_factor.map(|v| v as u32))
+            .collect();
 
         // Create series for each column
         let df = DataFrame::new(vec![
@@ -85,13 +113,145 @@ impl WorkoutSample {
             Series::new("peak_drive_force_n".into(), peak_forces).into(),
             Series::new("avg_drive_force_n".into(), avg_forces).into(),
             Series::new("work_per_stroke_j".into(), work_per_strokes).into(),
+            Series::new("recovery_time_ms".into(), recovery_times).into(),
+            Series::new("stroke_distance_cm".into(), stroke_distances).into(),
+            Series::new("drag_factor".into(), drag_factors).into(),
         ])?;
 
         Ok(df)
     }
+
+    /// Convert a DataFrame written by [`WorkoutSample::to_dataframe`] back to samples
+    pub fn from_dataframe(df: &DataFrame) -> PolarsResult<Vec<WorkoutSample>> {
+        let timestamps = df.column("timestamp")?.i64()?;
+        let elapsed_times = df.column("elapsed_time_ms")?.u32()?;
+        let distances = df.column("distance_m")?.u32()?;
+        let heart_rates = df.column("heart_rate_bpm")?.u32()?;
+        let powers = df.column("power_watts")?.u32()?;
+        let stroke_rates = df.column("stroke_rate")?.u32()?;
+        let paces = df.column("pace_ms_per_500m")?.u32()?;
+        let calories = df.column("calories")?.u32()?;
+        let drive_lengths = df.column("drive_length_cm")?.u32()?;
+        let drive_times = df.column("drive_time_ms")?.u32()?;
+        let peak_forces = df.column("peak_drive_force_n")?.u32()?;
+        let avg_forces = df.column("avg_drive_force_n")?.u32()?;
+        let work_per_strokes = df.column("work_per_stroke_j")?.u32()?;
+        let recovery_times = df.column("recovery_time_ms")?.u32()?;
+        let stroke_distances = df.column("stroke_distance_cm")?.u32()?;
+        let drag_factors = df.column("drag_factor")?.u32()?;
+
+        Ok((0..df.height())
+            .map(|i| WorkoutSample {
+                timestamp: timestamps.get(i).unwrap_or_default() as i128,
+                elapsed_time_ms: elapsed_times.get(i).unwrap_or_default(),
+                distance_m: distances.get(i).unwrap_or_default(),
+                heart_rate_bpm: heart_rates.get(i).map(|v| v as u8),
+                power_watts: powers.get(i).map(|v| v as u16),
+                stroke_rate: stroke_rates.get(i).map(|v| v as u8),
+                pace_ms_per_500m: paces.get(i),
+                calories: calories.get(i).map(|v| v as u16),
+                drive_length_cm: drive_lengths.get(i).map(|v| v as u16),
+                drive_time_ms: drive_times.get(i).map(|v| v as u16),
+                peak_drive_force_n: peak_forces.get(i).map(|v| v as u16),
+                avg_drive_force_n: avg_forces.get(i).map(|v| v as u16),
+                work_per_stroke_j: work_per_strokes.get(i).map(|v| v as u16),
+                recovery_time_ms: recovery_times.get(i),
+                stroke_distance_cm: stroke_distances.get(i).map(|v| v as u16),
+                drag_factor: drag_factors.get(i).map(|v| v as u8),
+            })
+            .collect())
+    }
+}
+
+/// A single split or interval as reported by the PM5, falling back to the
+/// recorded samples for any averages the monitor did not send.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct IntervalSummary {
+    pub interval_number: u8,
+    pub interval_type: Option<IntervalType>,
+    pub start_elapsed_ms: u32,
+    pub work_time_ms: u32,
+    pub rest_time_ms: u32,
+    pub work_distance_m: u32,
+    pub rest_distance_m: u32,
+
+    pub avg_pace_ms_per_500m: Option<u32>,
+    pub avg_power_watts: Option<u16>,
+    pub avg_stroke_rate: Option<u8>,
+    pub work_heart_rate_bpm: Option<u8>,
+    pub rest_heart_rate_bpm: Option<u8>,
+    pub avg_drag_factor: Option<u8>,
+}
+
+impl IntervalSummary {
+    pub fn end_elapsed_ms(&self) -> u32 {
+        self.start_elapsed_ms + self.work_time_ms
+    }
+
+    /// Convert intervals to a Polars DataFrame, one row per interval.
+    pub fn to_dataframe(intervals: &[IntervalSummary]) -> PolarsResult<DataFrame> {
+        fn column<T>(
+            name: &str,
+            intervals: &[IntervalSummary],
+            f: impl Fn(&IntervalSummary) -> Option<T>,
+        ) -> Column
+        where
+            Series: NamedFrom<Vec<Option<T>>, [Option<T>]>,
+        {
+            Series::new(name.into(), intervals.iter().map(f).collect::<Vec<_>>()).into()
+        }
+
+        DataFrame::new(vec![
+            column("interval_number", intervals, |i| {
+                Some(i.interval_number as u32)
+            }),
+            column("interval_type", intervals, |i| {
+                i.interval_type.map(|t| format!("{t:?}"))
+            }),
+            column("start_elapsed_ms", intervals, |i| Some(i.start_elapsed_ms)),
+            column("work_time_ms", intervals, |i| Some(i.work_time_ms)),
+            column("rest_time_ms", intervals, |i| Some(i.rest_time_ms)),
+            column("work_distance_m", intervals, |i| Some(i.work_distance_m)),
+            column("rest_distance_m", intervals, |i| Some(i.rest_distance_m)),
+            column("avg_pace_ms_per_500m", intervals, |i| {
+                i.avg_pace_ms_per_500m
+            }),
+            column("avg_power_watts", intervals, |i| {
+                i.avg_power_watts.map(|v| v as u32)
+            }),
+            column("avg_stroke_rate", intervals, |i| {
+                i.avg_stroke_rate.map(|v| v as u32)
+            }),
+            column("work_heart_rate_bpm", intervals, |i| {
+                i.work_heart_rate_bpm.map(|v| v as u32)
+            }),
+            column("rest_heart_rate_bpm", intervals, |i| {
+                i.rest_heart_rate_bpm.map(|v| v as u32)
+            }),
+            column("avg_drag_factor", intervals, |i| {
+                i.avg_drag_factor.map(|v| v as u32)
+            }),
+        ])
+    }
+}
+
+/// A part of a workout exported as one lap: an interval or split with the
+/// rest after it, or the whole workout if the PM5 reported none.
+pub(crate) struct Lap<'a> {
+    pub start_ms: u32,
+    pub end_ms: u32,
+    /// Unix timestamp in nanoseconds.
+    pub start_time: i128,
+    pub distance_m: u32,
+    pub calories: u16,
+    pub avg_power_watts: Option<u16>,
+    pub avg_stroke_rate: Option<u8>,
+    pub avg_heart_rate_bpm: Option<u8>,
+    pub max_heart_rate_bpm: Option<u8>,
+    pub samples: &'a [WorkoutSample],
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct WorkoutSummary {
     pub workout_id: Uuid,
     pub user_id: String,
@@ -100,6 +260,8 @@ pub struct WorkoutSummary {
     pub duration_ms: u32,
     pub total_distance_m: u32,
     pub total_calories: u16,
+    pub machine_type: Option<ErgMachineType>,
+    pub workout_type: Option<WorkoutType>,
 
     pub avg_heart_rate_bpm: Option<u8>,
     pub max_heart_rate_bpm: Option<u8>,
@@ -107,18 +269,335 @@ pub struct WorkoutSummary {
     pub avg_stroke_rate: Option<u8>,
     pub avg_pace_ms_per_500m: Option<u32>,
 
+    pub min_heart_rate_bpm: Option<u8>,
+    pub ending_heart_rate_bpm: Option<u8>,
+    pub recovery_heart_rate_bpm: Option<u8>,
+    pub avg_drag_factor: Option<u8>,
+    pub total_strokes: Option<u16>,
+    /// Average watts per stroke per minute, comparable across pieces rowed at
+    /// different rates.
+    pub stroke_rate_adjusted_watts: Option<f64>,
+
     pub race_id: Option<String>,
     pub race_position: Option<u16>,
+
+    #[serde(default)]
+    pub intervals: Vec<IntervalSummary>,
+}
+
+impl WorkoutSummary {
+    /// The machine the workout was done on, for its units and formulas.
+    pub fn ergometer(&self) -> Ergometer {
+        Ergometer::from_machine_type(self.machine_type)
+    }
+
+    /// Split `samples` into laps for export. Each interval's lap runs until
+    /// the next one starts, so that its time and distance both include the
+    /// rest and every sample falls in a lap.
+    pub(crate) fn laps<'a>(&self, samples: &'a [WorkoutSample]) -> Vec<Lap<'a>> {
+        let last_ms = samples.last().map_or(0, |s| s.elapsed_time_ms);
+        let bounds: Vec<_> = if self.intervals.is_empty() {
+            vec![(
+                samples.first().map_or(0, |s| s.elapsed_time_ms),
+                self.duration_ms.max(last_ms),
+                self.total_distance_m,
+                self.avg_power_watts,
+                self.avg_stroke_rate,
+            )]
+        } else {
+            let next_starts = self.intervals.iter().skip(1).map(|i| i.start_elapsed_ms);
+            self.intervals
+                .iter()
+                .zip(next_starts.map(Some).chain([None]))
+                .map(|(interval, next_start_ms)| {
+                    let end_ms = next_start_ms.unwrap_or_else(|| {
+                        (interval.end_elapsed_ms() + interval.rest_time_ms).max(last_ms)
+                    });
+                    (
+                        interval.start_elapsed_ms,
+                        end_ms,
+                        interval.work_distance_m + interval.rest_distance_m,
+                        interval.avg_power_watts,
+                        interval.avg_stroke_rate,
+                    )
+                })
+                .collect()
+        };
+
+        let last = bounds.len().saturating_sub(1);
+        bounds
+            .into_iter()
+            .enumerate()
+            .map(
+                |(i, (start_ms, end_ms, distance_m, avg_power_watts, avg_stroke_rate))| {
+                    let from = if i == 0 {
+                        0
+                    } else {
+                        samples.partition_point(|s| s.elapsed_time_ms < start_ms)
+                    };
+                    let to = if i == last {
+                        samples.len()
+                    } else {
+                        samples.partition_point(|s| s.elapsed_time_ms < end_ms)
+                    };
+                    let lap_samples = &samples[from..to.max(from)];
+
+                    let calories = if self.intervals.is_empty() {
+                        self.total_calories
+                    } else {
+                        let calories = lap_samples.iter().filter_map(|s| s.calories);
+                        calories.clone().max().unwrap_or(0) - calories.min().unwrap_or(0)
+                    };
+                    Lap {
+                        start_ms,
+                        end_ms,
+                        start_time: lap_samples
+                            .first()
+                            .map_or(self.start_time + start_ms as i128 * 1_000_000, |s| {
+                                s.timestamp
+                            }),
+                        distance_m,
+                        calories,
+                        avg_power_watts,
+                        avg_stroke_rate,
+                        avg_heart_rate_bpm: mean(lap_samples.iter().map(|s| s.heart_rate_bpm)),
+                        max_heart_rate_bpm: lap_samples
+                            .iter()
+                            .filter_map(|s| s.heart_rate_bpm)
+                            .max(),
+                        samples: lap_samples,
+                    }
+                },
+            )
+            .collect()
+    }
+
+    /// Convert summaries to a Polars DataFrame, one row per workout. Intervals
+    /// are left out.
+    pub fn to_dataframe(summaries: &[WorkoutSummary]) -> PolarsResult<DataFrame> {
+        fn column<T>(
+            name: &str,
+            summaries: &[WorkoutSummary],
+            f: impl Fn(&WorkoutSummary) -> Option<T>,
+        ) -> Column
+        where
+            Series: NamedFrom<Vec<Option<T>>, [Option<T>]>,
+        {
+            Series::new(name.into(), summaries.iter().map(f).collect::<Vec<_>>()).into()
+        }
+
+        DataFrame::new(vec![
+            column("workout_id", summaries, |s| Some(s.workout_id.to_string())),
+            column("user_id", summaries, |s| Some(s.user_id.clone())),
+            column("start_time", summaries, |s| Some(s.start_time as i64)),
+            column("end_time", summaries, |s| Some(s.end_time as i64)),
+            column("duration_ms", summaries, |s| Some(s.duration_ms)),
+            column("total_distance_m", summaries, |s| Some(s.total_distance_m)),
+            column("total_calories", summaries, |s| {
+                Some(s.total_calories as u32)
+            }),
+            column("machine_type", summaries, |s| {
+                s.machine_type.map(|m| format!("{m:?}"))
+            }),
+            column("avg_heart_rate_bpm", summaries, |s| {
+                s.avg_heart_rate_bpm.map(|v| v as u32)
+            }),
+            column("max_heart_rate_bpm", summaries, |s| {
+                s.max_heart_rate_bpm.map(|v| v as u32)
+            }),
+            column("avg_power_watts", summaries, |s| {
+                s.avg_power_watts.map(|v| v as u32)
+            }),
+            column("avg_stroke_rate", summaries, |s| {
+                s.avg_stroke_rate.map(|v| v as u32)
+            }),
+            column("avg_pace_ms_per_500m", summaries, |s| {
+                s.avg_pace_ms_per_500m
+            }),
+            column("min_heart_rate_bpm", summaries, |s| {
+                s.min_heart_rate_bpm.map(|v| v as u32)
+            }),
+            column("avg_drag_factor", summaries, |s| {
+                s.avg_drag_factor.map(|v| v as u32)
+            }),
+            column("total_strokes", summaries, |s| {
+                s.total_strokes.map(|v| v as u32)
+            }),
+            column("race_id", summaries, |s| s.race_id.clone()),
+            column("race_position", summaries, |s| {
+                s.race_position.map(|v| v as u32)
+            }),
+        ])
+    }
+}
+
+/// Selects workouts by their summary. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryFilter {
+    pub from: Option<UtcDateTime>,
+    pub to: Option<UtcDateTime>,
+    pub min_distance_m: Option<u32>,
+    pub max_distance_m: Option<u32>,
+    pub machine_type: Option<ErgMachineType>,
+    /// Any of the machine's types. Workouts without a machine type are taken
+    /// as rows.
+    pub ergometer: Option<Ergometer>,
+    pub race_id: Option<String>,
+}
+
+impl SummaryFilter {
+    pub fn matches(&self, summary: &WorkoutSummary) -> bool {
+        self.from
+            .is_none_or(|from| summary.start_time >= from.unix_timestamp_nanos())
+            && self
+                .to
+                .is_none_or(|to| summary.start_time < to.unix_timestamp_nanos())
+            && self
+                .min_distance_m
+                .is_none_or(|min| summary.total_distance_m >= min)
+            && self
+                .max_distance_m
+                .is_none_or(|max| summary.total_distance_m <= max)
+            && self
+                .machine_type
+                .is_none_or(|machine_type| summary.machine_type == Some(machine_type))
+            && self
+                .ergometer
+                .is_none_or(|ergometer| summary.ergometer() == ergometer)
+            && self
+                .race_id
+                .as_ref()
+                .is_none_or(|race_id| summary.race_id.as_ref() == Some(race_id))
+    }
+
+    /// The same filter as an expression over [`WorkoutSummary::to_dataframe`] columns.
+    pub fn expr(&self) -> Expr {
+        let mut expr = lit(true);
+        if let Some(from) = self.from {
+            expr = expr.and(col("start_time").gt_eq(lit(from.unix_timestamp_nanos() as i64)));
+        }
+        if let Some(to) = self.to {
+            expr = expr.and(col("start_time").lt(lit(to.unix_timestamp_nanos() as i64)));
+        }
+        if let Some(min) = self.min_distance_m {
+            expr = expr.and(col("total_distance_m").gt_eq(lit(min)));
+        }
+        if let Some(max) = self.max_distance_m {
+            expr = expr.and(col("total_distance_m").lt_eq(lit(max)));
+        }
+        if let Some(machine_type) = self.machine_type {
+            expr = expr.and(col("machine_type").eq(lit(format!("{machine_type:?}"))));
+        }
+        if let Some(ergometer) = self.ergometer {
+            let mut matches = col("machine_type")
+                .is_null()
+                .and(lit(ergometer == Ergometer::RowErg));
+            for machine_type in ergometer.machine_types() {
+                matches = matches.or(col("machine_type").eq(lit(format!("{machine_type:?}"))));
+            }
+            expr = expr.and(matches);
+        }
+        if let Some(race_id) = &self.race_id {
+            expr = expr.and(col("race_id").eq(lit(race_id.clone())));
+        }
+        expr
+    }
+}
+
+/// The PM5's own end of workout summary, split over two notifications.
+#[derive(Default, Debug, Clone)]
+struct EndOfWorkoutSummary {
+    elapsed_time_ms: Option<u32>,
+    distance_m: Option<u32>,
+    avg_stroke_rate: Option<u8>,
+    ending_heart_rate_bpm: Option<u8>,
+    avg_heart_rate_bpm: Option<u8>,
+    min_heart_rate_bpm: Option<u8>,
+    max_heart_rate_bpm: Option<u8>,
+    recovery_heart_rate_bpm: Option<u8>,
+    avg_drag_factor: Option<u8>,
+    avg_pace_ms_per_500m: Option<u32>,
+    total_calories: Option<u16>,
+    avg_power_watts: Option<u16>,
+}
+
+/// The monitor a workout was recorded on, stored with its samples.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub hardware_revision: Option<String>,
+    pub firmware_revision: Option<String>,
+    pub manufacturer_name: Option<String>,
+    pub machine_type: Option<ErgMachineType>,
 }
 
 pub struct WorkoutRecorder {
     workout_id: Uuid,
     user_id: String,
     start_time: UtcDateTime,
+    /// When the samples of a recovered or imported workout end. A workout
+    /// recorded live ends when it's summarized.
+    end_time: Option<UtcDateTime>,
     last_stroke_sample: WorkoutSample,
     samples: Vec<WorkoutSample>,
+
+    distance_m: u32,
+    workout_state: Option<WorkoutState>,
+    last_rest_ms: Option<u32>,
+    rest_heart_rate_bpm: Option<u8>,
+    intervals: BTreeMap<u8, IntervalSummary>,
+    /// Samples since the last split, kept apart from `samples` so that the
+    /// intervals' averages survive spilling, and those averages per interval.
+    unsplit_samples: Vec<IntervalSample>,
+    interval_means: BTreeMap<u8, IntervalMeans>,
+
+    drag_factor: Option<(u32, u8)>,
+    drag_factor_weighted_sum: u64,
+    drag_factor_time_ms: u64,
+    stroke_count: Option<u16>,
+    end_of_workout: Option<EndOfWorkoutSummary>,
+    device: DeviceInfo,
+    workout_type: Option<WorkoutType>,
+
+    paused: bool,
+    resuming: bool,
+    elapsed_offset_ms: u32,
+    distance_offset_m: u32,
+    last_pm5_elapsed_ms: Option<u32>,
+    completed: Vec<WorkoutRecorder>,
+
+    sample_count: usize,
+    last_position: Option<(u32, u32)>,
+    spilled_parts: u32,
+}
+
+/// The values of a sample that [`WorkoutRecorder::intervals`] averages.
+#[derive(Debug, Clone, Copy)]
+struct IntervalSample {
+    elapsed_time_ms: u32,
+    power_watts: Option<u16>,
+    stroke_rate: Option<u8>,
+    heart_rate_bpm: Option<u8>,
+    drag_factor: Option<u8>,
+}
+
+/// Averages of an interval's work period, for when the PM5 doesn't report
+/// them.
+#[derive(Debug, Clone, Copy, Default)]
+struct IntervalMeans {
+    avg_power_watts: Option<u16>,
+    avg_stroke_rate: Option<u8>,
+    work_heart_rate_bpm: Option<u8>,
+    avg_drag_factor: Option<u8>,
 }
 
+/// How far the PM5 elapsed time may run backwards before it's treated as the
+/// monitor having been reset for a new workout. Stroke and status
+/// notifications are timestamped independently and can arrive slightly out
+/// of order.
+const ELAPSED_RESET_TOLERANCE_MS: u32 = 1000;
+
 impl WorkoutRecorder {
     pub fn new(user_id: String) -> Self {
         Self {
@@ -126,14 +605,467 @@ impl WorkoutRecorder {
             user_id,
             last_stroke_sample: Default::default(),
             start_time: UtcDateTime::now(),
+            end_time: None,
             samples: Vec::new(),
+            distance_m: 0,
+            workout_state: None,
+            last_rest_ms: None,
+            rest_heart_rate_bpm: None,
+            intervals: BTreeMap::new(),
+            unsplit_samples: Vec::new(),
+            interval_means: BTreeMap::new(),
+            drag_factor: None,
+            drag_factor_weighted_sum: 0,
+            drag_factor_time_ms: 0,
+            stroke_count: None,
+            end_of_workout: None,
+            device: DeviceInfo::default(),
+            workout_type: None,
+            paused: false,
+            resuming: false,
+            elapsed_offset_ms: 0,
+            distance_offset_m: 0,
+            last_pm5_elapsed_ms: None,
+            completed: Vec::new(),
+            sample_count: 0,
+            last_position: None,
+            spilled_parts: 0,
+        }
+    }
+
+    /// A recorder for a workout whose samples were already spilled to storage.
+    fn recovered(user_id: String, workout_id: Uuid, spilled: &SpilledSamples) -> Self {
+        let mut recorder = Self::new(user_id);
+        recorder.workout_id = workout_id;
+        if let Some(first) = &spilled.first {
+            recorder.start_time = UtcDateTime::from_unix_timestamp_nanos(first.timestamp)
+                .unwrap_or(recorder.start_time);
+        }
+        if let Some(last) = &spilled.last {
+            recorder.end_time = UtcDateTime::from_unix_timestamp_nanos(last.timestamp).ok();
         }
+        recorder.sample_count = spilled.count;
+        recorder.last_position = spilled
+            .last
+            .as_ref()
+            .map(|s| (s.elapsed_time_ms, s.distance_m));
+        recorder.spilled_parts = spilled.parts;
+        if let Some(device) = spilled.device.clone() {
+            recorder.set_device(device);
+        }
+        recorder
+    }
+
+    /// A recorder holding samples recorded elsewhere, e.g. imported from a file.
+    pub fn from_samples(user_id: String, samples: Vec<WorkoutSample>) -> Self {
+        let spilled = SpilledSamples {
+            first: samples.first().cloned(),
+            last: samples.last().cloned(),
+            count: samples.len(),
+            ..Default::default()
+        };
+        let mut recorder = Self::recovered(user_id, Uuid::now_v7(), &spilled);
+        recorder.samples = samples;
+        recorder
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// Number of samples held in memory, not yet spilled to storage.
+    pub fn pending_samples(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Take the samples held in memory, leaving the rest of the recording intact.
+    pub fn drain_samples(&mut self) -> Vec<WorkoutSample> {
+        std::mem::take(&mut self.samples)
     }
 
     pub fn workout_id(&self) -> Uuid {
         self.workout_id
     }
 
+    pub fn workout_state(&self) -> Option<WorkoutState> {
+        self.workout_state
+    }
+
+    pub fn machine_type(&self) -> Option<ErgMachineType> {
+        self.device.machine_type
+    }
+
+    pub fn ergometer(&self) -> Ergometer {
+        Ergometer::from_machine_type(self.device.machine_type)
+    }
+
+    pub fn set_machine_type(&mut self, machine_type: ErgMachineType) {
+        self.device.machine_type = Some(machine_type);
+    }
+
+    pub fn device(&self) -> &DeviceInfo {
+        &self.device
+    }
+
+    /// Set the monitor details read from the device information service.
+    /// A machine type already seen in rowing data is kept if `device` has none.
+    pub fn set_device(&mut self, device: DeviceInfo) {
+        let machine_type = device.machine_type.or(self.device.machine_type);
+        self.device = DeviceInfo {
+            machine_type,
+            ..device
+        };
+    }
+
+    /// Feed a decoded notification from the rowing service into the recorder.
+    ///
+    /// The PM5 reports times in hundredths of a second and distances in
+    /// tenths of a meter, these are converted to the units of [`WorkoutSample`].
+    pub fn record(&mut self, data: &RowingData) {
+        match data {
+            RowingData::GeneralStatus {
+                elapsed_time,
+                distance,
+                workout_type,
+                workout_state,
+                drag_factor,
+                ..
+            } => {
+                self.distance_m = *distance.0 / 10;
+                self.workout_type = Some(*workout_type);
+                self.set_workout_state(*workout_state);
+                if self.is_recording() {
+                    self.set_drag_factor(*elapsed_time.0 * 10, drag_factor.0);
+                }
+            }
+            RowingData::AdditionalStatusOne {
+                elapsed_time,
+                stroke_rate,
+                heart_rate,
+                current_pace,
+                rest_time,
+                machine_type,
+                ..
+            } => {
+                self.set_machine_type(*machine_type);
+                let heart_rate_bpm = heart_rate_bpm(heart_rate);
+                if self.workout_state.is_some_and(|state| state.is_rest()) {
+                    self.rest_heart_rate_bpm = heart_rate_bpm.or(self.rest_heart_rate_bpm);
+                    // The rest time counts up through an undefined rest, so the
+                    // last one seen is how long the rest lasted.
+                    if *rest_time.0 > 0 {
+                        self.last_rest_ms = Some(*rest_time.0 * 10);
+                    }
+                }
+                self.add_general_sample(
+                    *elapsed_time.0 * 10,
+                    self.distance_m,
+                    heart_rate_bpm,
+                    (stroke_rate.0 > 0).then_some(stroke_rate.0),
+                    (current_pace.0 > 0).then_some(current_pace.0 as u32 * 10),
+                );
+            }
+            RowingData::StrokeData {
+                elapsed_time,
+                distance,
+                drive_length,
+                drive_time,
+                stroke_recovery,
+                stroke_distance,
+                peak_drive_force,
+                avg_drive_force,
+                work_per_stroke,
+                stroke_count,
+            } => {
+                self.stroke_count = self.stroke_count.max(Some(stroke_count.0));
+                self.set_stroke_data(
+                    *elapsed_time.0 * 10,
+                    *distance.0 / 10,
+                    drive_length.0 as u16,
+                    drive_time.0 as u16 * 10,
+                    tenth_pounds_to_newtons(peak_drive_force.0),
+                    tenth_pounds_to_newtons(avg_drive_force.0),
+                    work_per_stroke.0 / 10,
+                    self.last_stroke_sample.power_watts,
+                    self.last_stroke_sample.calories,
+                );
+                self.set_stroke_recovery(stroke_recovery.0 as u32 * 10, stroke_distance.0);
+            }
+            RowingData::AdditionalStatusTwo { total_calories, .. } => {
+                self.last_stroke_sample.calories = Some(total_calories.0);
+            }
+            RowingData::AdditionalStrokeData {
+                stroke_power,
+                stroke_count,
+                ..
+            } => {
+                self.stroke_count = self.stroke_count.max(Some(stroke_count.0));
+                self.last_stroke_sample.power_watts = Some(stroke_power.0);
+            }
+            RowingData::SplitIntervalData {
+                elapsed_time,
+                split_interval_time,
+                split_interval_distance,
+                interval_rest_time,
+                interval_rest_distance,
+                split_interval_type,
+                split_interval_number,
+                ..
+            } => {
+                // Split time is reported in tenths of a second, rest time in seconds.
+                let work_time_ms = *split_interval_time.0 * 100;
+                let rest_time_ms = match interval_rest_time.0 {
+                    0 => self.last_rest_ms.take().unwrap_or(0),
+                    rest => rest as u32 * 1000,
+                };
+                let rest_heart_rate_bpm = self.rest_heart_rate_bpm.take();
+                let end_elapsed_ms = (*elapsed_time.0 * 10).saturating_sub(self.elapsed_offset_ms);
+                let start_elapsed_ms = end_elapsed_ms.saturating_sub(work_time_ms);
+                self.average_split(split_interval_number.0, start_elapsed_ms, end_elapsed_ms);
+
+                let interval = self.interval_mut(split_interval_number.0);
+                interval.interval_type = Some(*split_interval_type);
+                interval.start_elapsed_ms = start_elapsed_ms;
+                interval.work_time_ms = work_time_ms;
+                interval.rest_time_ms = rest_time_ms;
+                interval.work_distance_m = *split_interval_distance.0;
+                interval.rest_distance_m = interval_rest_distance.0 as u32;
+                interval.rest_heart_rate_bpm = interval.rest_heart_rate_bpm.or(rest_heart_rate_bpm);
+            }
+            RowingData::AdditionalSplitIntervalData {
+                split_interval_avg_stroke_rate,
+                split_interval_work_heartrate,
+                split_interval_rest_heartrate,
+                split_interval_avg_pace,
+                split_interval_power,
+                split_avg_drag_factor,
+                split_interval_number,
+                erg_machine_type,
+                ..
+            } => {
+                self.set_machine_type(*erg_machine_type);
+                let interval = self.interval_mut(split_interval_number.0);
+                interval.avg_stroke_rate = (split_interval_avg_stroke_rate.0 > 0)
+                    .then_some(split_interval_avg_stroke_rate.0);
+                interval.work_heart_rate_bpm = heart_rate_bpm(split_interval_work_heartrate);
+                interval.rest_heart_rate_bpm =
+                    heart_rate_bpm(split_interval_rest_heartrate).or(interval.rest_heart_rate_bpm);
+                // Interval pace is reported in tenths of a second.
+                interval.avg_pace_ms_per_500m = (split_interval_avg_pace.0 > 0)
+                    .then_some(split_interval_avg_pace.0 as u32 * 100);
+                interval.avg_power_watts =
+                    (split_interval_power.0 > 0).then_some(split_interval_power.0);
+                interval.avg_drag_factor =
+                    (split_avg_drag_factor.0 > 0).then_some(split_avg_drag_factor.0);
+            }
+            RowingData::EndOfWorkoutSummaryData {
+                elapsed_time,
+                distance,
+                avg_stroke_rate,
+                ending_heartrate,
+                avg_heartrate,
+                min_heartrate,
+                max_heartrate,
+                drag_factor_avg,
+                recover_heartrate,
+                avg_pace,
+                ..
+            } => {
+                let end_of_workout = self.end_of_workout.get_or_insert_with(Default::default);
+                end_of_workout.elapsed_time_ms = Some(*elapsed_time.0 * 10);
+                end_of_workout.distance_m = Some(*distance.0 / 10);
+                end_of_workout.avg_stroke_rate =
+                    (avg_stroke_rate.0 > 0).then_some(avg_stroke_rate.0);
+                end_of_workout.ending_heart_rate_bpm = heart_rate_bpm(ending_heartrate);
+                end_of_workout.avg_heart_rate_bpm = heart_rate_bpm(avg_heartrate);
+                end_of_workout.min_heart_rate_bpm = heart_rate_bpm(min_heartrate);
+                end_of_workout.max_heart_rate_bpm = heart_rate_bpm(max_heartrate);
+                end_of_workout.recovery_heart_rate_bpm = heart_rate_bpm(recover_heartrate);
+                end_of_workout.avg_drag_factor =
+                    (drag_factor_avg.0 > 0).then_some(drag_factor_avg.0);
+                // Average pace is reported in tenths of a second.
+                end_of_workout.avg_pace_ms_per_500m =
+                    (avg_pace.0 > 0).then_some(avg_pace.0 as u32 * 100);
+            }
+            RowingData::AdditionalEndOfWorkoutSummaryData {
+                total_calories,
+                watts,
+                ..
+            } => {
+                let end_of_workout = self.end_of_workout.get_or_insert_with(Default::default);
+                end_of_workout.total_calories = Some(total_calories.0);
+                end_of_workout.avg_power_watts = (watts.0 > 0).then_some(watts.0);
+            }
+            _ => {}
+        }
+    }
+
+    /// Accumulate the drag factor weighted by how long it was in effect.
+    pub fn set_drag_factor(&mut self, elapsed_time_ms: u32, drag_factor: u8) {
+        if let Some((since_ms, previous)) = self.drag_factor {
+            let held_ms = elapsed_time_ms.saturating_sub(since_ms) as u64;
+            self.drag_factor_weighted_sum += held_ms * previous as u64;
+            self.drag_factor_time_ms += held_ms;
+        }
+        self.drag_factor = (drag_factor > 0).then_some((elapsed_time_ms, drag_factor));
+    }
+
+    fn avg_drag_factor(&self) -> Option<u8> {
+        if self.drag_factor_time_ms == 0 {
+            return self.drag_factor.map(|(_, drag_factor)| drag_factor);
+        }
+        Some((self.drag_factor_weighted_sum as f64 / self.drag_factor_time_ms as f64).round() as u8)
+    }
+
+    /// Track the PM5 workout state. Rest periods are timed by the rest time
+    /// the PM5 reports while resting, so that intervals with an undefined rest
+    /// still get a rest time.
+    ///
+    /// Once the PM5 has finished a workout, samples are ignored until it
+    /// starts the next one, which is recorded as a separate workout.
+    pub fn set_workout_state(&mut self, state: WorkoutState) {
+        if self.workout_state.is_some_and(|state| state.is_finished()) && !state.is_finished() {
+            self.finish_workout();
+        }
+
+        let was_rest = self.workout_state.is_some_and(|state| state.is_rest());
+        if !was_rest && state.is_rest() {
+            self.last_rest_ms = None;
+        }
+        self.workout_state = Some(state);
+    }
+
+    /// Stop recording samples until [`WorkoutRecorder::resume`] is called.
+    ///
+    /// Time and distance covered while paused are left out of the workout.
+    pub fn pause(&mut self) {
+        if self.paused {
+            return;
+        }
+        if let Some(elapsed_time_ms) = self.last_pm5_elapsed_ms {
+            self.set_drag_factor(elapsed_time_ms, 0);
+        }
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.resuming = true;
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn is_recording(&self) -> bool {
+        !self.paused && !self.workout_state.is_some_and(|state| state.is_finished())
+    }
+
+    /// End the current workout and start recording a new one with a fresh
+    /// workout id. Does nothing if no samples have been recorded yet.
+    pub fn finish_workout(&mut self) {
+        if self.sample_count == 0 {
+            return;
+        }
+        let mut finished = std::mem::replace(self, WorkoutRecorder::new(self.user_id.clone()));
+        self.completed = std::mem::take(&mut finished.completed);
+        self.workout_state = finished.workout_state;
+        self.device = finished.device.clone();
+        self.paused = finished.paused;
+        self.completed.push(finished);
+    }
+
+    /// Workouts that have ended since the last call, oldest first.
+    pub fn take_completed(&mut self) -> Vec<WorkoutRecorder> {
+        std::mem::take(&mut self.completed)
+    }
+
+    /// Map the PM5 elapsed time and distance onto this workout's timeline, or
+    /// `None` if the sample shouldn't be recorded.
+    fn timeline_position(&mut self, elapsed_time_ms: u32, distance_m: u32) -> Option<(u32, u32)> {
+        if !self.is_recording() {
+            return None;
+        }
+        if self
+            .last_pm5_elapsed_ms
+            .is_some_and(|last| elapsed_time_ms + ELAPSED_RESET_TOLERANCE_MS < last)
+        {
+            self.finish_workout();
+        }
+        if self.resuming {
+            let (last_elapsed_ms, last_distance_m) = self.last_position.unwrap_or_default();
+            self.elapsed_offset_ms = elapsed_time_ms.saturating_sub(last_elapsed_ms);
+            self.distance_offset_m = distance_m.saturating_sub(last_distance_m);
+            self.resuming = false;
+        }
+        self.last_pm5_elapsed_ms = Some(elapsed_time_ms);
+        Some((
+            elapsed_time_ms.saturating_sub(self.elapsed_offset_ms),
+            distance_m.saturating_sub(self.distance_offset_m),
+        ))
+    }
+
+    fn interval_mut(&mut self, interval_number: u8) -> &mut IntervalSummary {
+        self.intervals
+            .entry(interval_number)
+            .or_insert_with(|| IntervalSummary {
+                interval_number,
+                ..Default::default()
+            })
+    }
+
+    /// Average the samples of the work period that just ended, and forget
+    /// those up to its end.
+    fn average_split(&mut self, interval_number: u8, start_elapsed_ms: u32, end_elapsed_ms: u32) {
+        let work: Vec<&IntervalSample> = self
+            .unsplit_samples
+            .iter()
+            .filter(|s| s.elapsed_time_ms > start_elapsed_ms && s.elapsed_time_ms <= end_elapsed_ms)
+            .collect();
+        if !work.is_empty() {
+            let means = IntervalMeans {
+                avg_power_watts: mean(work.iter().map(|s| s.power_watts)),
+                avg_stroke_rate: mean(work.iter().map(|s| s.stroke_rate)),
+                work_heart_rate_bpm: mean(work.iter().map(|s| s.heart_rate_bpm)),
+                avg_drag_factor: mean(work.iter().map(|s| s.drag_factor)),
+            };
+            self.interval_means.insert(interval_number, means);
+        }
+        self.unsplit_samples
+            .retain(|s| s.elapsed_time_ms > end_elapsed_ms);
+    }
+
+    /// Splits and intervals seen so far, with any averages the PM5 did not
+    /// report computed from the samples recorded during the work period,
+    /// including those already spilled.
+    pub fn intervals(&self) -> Vec<IntervalSummary> {
+        self.intervals
+            .values()
+            .map(|interval| {
+                let mut interval = interval.clone();
+                let means = self
+                    .interval_means
+                    .get(&interval.interval_number)
+                    .copied()
+                    .unwrap_or_default();
+                interval.avg_power_watts = interval.avg_power_watts.or(means.avg_power_watts);
+                interval.avg_stroke_rate = interval.avg_stroke_rate.or(means.avg_stroke_rate);
+                interval.work_heart_rate_bpm =
+                    interval.work_heart_rate_bpm.or(means.work_heart_rate_bpm);
+                interval.avg_drag_factor = interval.avg_drag_factor.or(means.avg_drag_factor);
+                interval.avg_pace_ms_per_500m = interval.avg_pace_ms_per_500m.or_else(|| {
+                    (interval.work_distance_m > 0).then(|| {
+                        (interval.work_time_ms as u64 * 500 / interval.work_distance_m as u64)
+                            as u32
+                    })
+                });
+                interval
+            })
+            .collect()
+    }
+
     pub fn add_general_sample(
         &mut self,
         elapsed_time_ms: u32,
@@ -142,17 +1074,35 @@ impl WorkoutRecorder {
         stroke_rate: Option<u8>,
         pace_ms_per_500m: Option<u32>,
     ) {
-        self.samples.push(WorkoutSample {
+        let Some((elapsed_time_ms, distance_m)) =
+            self.timeline_position(elapsed_time_ms, distance_m)
+        else {
+            return;
+        };
+        let sample = WorkoutSample {
             timestamp: UtcDateTime::now().unix_timestamp_nanos(),
             elapsed_time_ms,
             distance_m,
             heart_rate_bpm,
             stroke_rate,
             pace_ms_per_500m,
+            drag_factor: self.drag_factor.map(|(_, drag_factor)| drag_factor),
             ..self.last_stroke_sample.clone()
+        };
+        self.unsplit_samples.push(IntervalSample {
+            elapsed_time_ms,
+            power_watts: sample.power_watts,
+            stroke_rate,
+            heart_rate_bpm,
+            drag_factor: sample.drag_factor,
         });
+        self.samples.push(sample);
+        self.sample_count += 1;
+        self.last_position = Some((elapsed_time_ms, distance_m));
+        self.end_time = None;
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn set_stroke_data(
         &mut self,
         elapsed_time_ms: u32,
@@ -165,6 +1115,11 @@ impl WorkoutRecorder {
         power_watts: Option<u16>,
         calories: Option<u16>,
     ) {
+        let Some((elapsed_time_ms, distance_m)) =
+            self.timeline_position(elapsed_time_ms, distance_m)
+        else {
+            return;
+        };
         self.last_stroke_sample = WorkoutSample {
             timestamp: UtcDateTime::now().unix_timestamp_nanos(),
             elapsed_time_ms,
@@ -179,26 +1134,71 @@ impl WorkoutRecorder {
             peak_drive_force_n: Some(peak_drive_force_n),
             avg_drive_force_n: Some(avg_drive_force_n),
             work_per_stroke_j: Some(work_per_stroke_j),
+            recovery_time_ms: None,
+            stroke_distance_cm: None,
+            drag_factor: None,
         };
     }
 
+    /// Add the recovery time and distance of the stroke last given to
+    /// [`Self::set_stroke_data`].
+    pub fn set_stroke_recovery(&mut self, recovery_time_ms: u32, stroke_distance_cm: u16) {
+        self.last_stroke_sample.recovery_time_ms = Some(recovery_time_ms);
+        self.last_stroke_sample.stroke_distance_cm = Some(stroke_distance_cm);
+    }
+
     pub fn to_dataframe(&self) -> PolarsResult<DataFrame> {
         WorkoutSample::to_dataframe(&self.samples)
     }
 
+    /// Export the samples held in memory as a FIT activity.
+    pub fn to_fit(&self, summary: &WorkoutSummary) -> Vec<u8> {
+        crate::fit::encode(summary, &self.samples)
+    }
+
+    /// Export the samples held in memory as Garmin TCX.
+    pub fn to_tcx(&self, summary: &WorkoutSummary) -> String {
+        crate::tcx::to_tcx(summary, &self.samples, &self.device)
+    }
+
     pub fn to_lazyframe(&self) -> PolarsResult<LazyFrame> {
         Ok(self.to_dataframe()?.lazy())
     }
 
+    /// Summarize the recorded samples.
+    ///
+    /// Averages are weighted by the time until the next sample so that bursts
+    /// of notifications don't skew them. When the PM5 has sent its end of
+    /// workout summary its values take precedence over the computed ones.
     pub fn generate_summary(&self, race_id: Option<String>) -> PolarsResult<WorkoutSummary> {
-        let lf = self.to_lazyframe()?;
+        self.summarize(self.to_lazyframe()?, race_id)
+    }
+
+    /// Summarize the samples in `lf`, which needs at least the
+    /// [`SUMMARY_COLUMNS`].
+    fn summarize(&self, lf: LazyFrame, race_id: Option<String>) -> PolarsResult<WorkoutSummary> {
+        let delta_ms = col("elapsed_time_ms").cast(DataType::Int64).shift(lit(-1))
+            - col("elapsed_time_ms").cast(DataType::Int64);
+        let lf = lf.with_column(
+            when(delta_ms.clone().gt(lit(0)))
+                .then(delta_ms)
+                .otherwise(lit(0))
+                .cast(DataType::Float64)
+                .alias("weight_ms"),
+        );
 
         let agg_df = lf
             .select([
-                col("heart_rate_bpm").mean().alias("avg_hr"),
+                time_weighted_mean("heart_rate_bpm").alias("avg_hr"),
                 col("heart_rate_bpm").max().alias("max_hr"),
-                col("power_watts").mean().alias("avg_power"),
-                col("stroke_rate").mean().alias("avg_stroke_rate"),
+                col("heart_rate_bpm").min().alias("min_hr"),
+                col("heart_rate_bpm").drop_nulls().last().alias("ending_hr"),
+                time_weighted_mean("power_watts").alias("avg_power"),
+                time_weighted_mean("stroke_rate").alias("avg_stroke_rate"),
+                col("calories").max().alias("total_calories"),
+                col("distance_m").max().alias("total_distance"),
+                col("elapsed_time_ms").max().alias("duration"),
+                time_weighted_mean("drag_factor").alias("avg_drag_factor"),
             ])
             .collect()?;
 
@@ -206,105 +1206,1695 @@ impl WorkoutRecorder {
             .get(0)
             .ok_or_else(|| PolarsError::ComputeError("No data to aggregate".into()))?;
 
-        let avg_hr = row[0].try_extract::<f64>().ok().map(|v| v as u8);
-        let max_hr = row[1].try_extract::<f64>().ok().map(|v| v as u8);
-        let avg_power = row[2].try_extract::<f64>().ok().map(|v| v as u16);
-        let avg_stroke_rate = row[3].try_extract::<f64>().ok().map(|v| v as u8);
-
-        let last_sample = self
-            .samples
-            .last()
+        let extract = |i: usize| row[i].try_extract::<f64>().ok().map(f64::round);
+        let avg_hr = extract(0).map(|v| v as u8);
+        let max_hr = extract(1).map(|v| v as u8);
+        let min_hr = extract(2).map(|v| v as u8);
+        let ending_hr = extract(3).map(|v| v as u8);
+        let avg_power = extract(4).map(|v| v as u16);
+        let avg_stroke_rate = extract(5).map(|v| v as u8);
+        let total_calories = extract(6).map(|v| v as u16);
+        let total_distance_m = extract(7).map(|v| v as u32).unwrap_or(0);
+        let duration_ms = extract(8)
+            .map(|v| v as u32)
             .ok_or_else(|| PolarsError::ComputeError("No samples recorded".into()))?;
+        let avg_drag_factor = extract(9).map(|v| v as u8);
+
+        let end = self.end_of_workout.clone().unwrap_or_default();
+        let duration_ms = end.elapsed_time_ms.unwrap_or(duration_ms);
+        let total_distance_m = end.distance_m.unwrap_or(total_distance_m);
+        let avg_power_watts = end.avg_power_watts.or(avg_power);
+        let avg_stroke_rate = end.avg_stroke_rate.or(avg_stroke_rate);
+        let avg_pace_ms_per_500m = end.avg_pace_ms_per_500m.or_else(|| {
+            (total_distance_m > 0)
+                .then(|| (duration_ms as u64 * 500 / total_distance_m as u64) as u32)
+        });
+        let stroke_rate_adjusted_watts = avg_power_watts
+            .zip(avg_stroke_rate)
+            .filter(|(_, rate)| *rate > 0)
+            .map(|(power, rate)| power as f64 / rate as f64);
 
         Ok(WorkoutSummary {
             workout_id: self.workout_id,
             user_id: self.user_id.clone(),
             start_time: self.start_time.unix_timestamp_nanos(),
-            end_time: UtcDateTime::now().unix_timestamp_nanos(),
-            duration_ms: last_sample.elapsed_time_ms,
-            total_distance_m: last_sample.distance_m,
-            total_calories: last_sample.calories.unwrap_or(0),
-            avg_heart_rate_bpm: avg_hr,
-            max_heart_rate_bpm: max_hr,
-            avg_power_watts: avg_power,
+            end_time: self
+                .end_time
+                .unwrap_or_else(UtcDateTime::now)
+                .unix_timestamp_nanos(),
+            duration_ms,
+            total_distance_m,
+            total_calories: end.total_calories.or(total_calories).unwrap_or(0),
+            machine_type: self.device.machine_type,
+            workout_type: self.workout_type,
+            avg_heart_rate_bpm: end.avg_heart_rate_bpm.or(avg_hr),
+            max_heart_rate_bpm: end.max_heart_rate_bpm.or(max_hr),
+            avg_power_watts,
             avg_stroke_rate,
-            avg_pace_ms_per_500m: None,
+            avg_pace_ms_per_500m,
+            min_heart_rate_bpm: end.min_heart_rate_bpm.or(min_hr),
+            ending_heart_rate_bpm: end.ending_heart_rate_bpm.or(ending_hr),
+            recovery_heart_rate_bpm: end.recovery_heart_rate_bpm,
+            // A recovered recorder has no drag factor history, only samples.
+            avg_drag_factor: end
+                .avg_drag_factor
+                .or(self.avg_drag_factor())
+                .or(avg_drag_factor),
+            total_strokes: self.stroke_count,
+            stroke_rate_adjusted_watts,
             race_id,
             race_position: None,
+            intervals: self.intervals(),
         })
     }
 }
 
+/// The sample columns a workout's summary and records are computed from.
+const SUMMARY_COLUMNS: [&str; 7] = [
+    "elapsed_time_ms",
+    "distance_m",
+    "heart_rate_bpm",
+    "power_watts",
+    "stroke_rate",
+    "calories",
+    "drag_factor",
+];
+
+/// Mean of `column` weighted by the `weight_ms` column, ignoring nulls. Falls
+/// back to the plain mean when there's no elapsed time to weight by.
+fn time_weighted_mean(column: &str) -> Expr {
+    let value = col(column).cast(DataType::Float64);
+    let weight = when(value.clone().is_not_null())
+        .then(col("weight_ms"))
+        .otherwise(lit(0.0));
+    when(weight.clone().sum().gt(lit(0.0)))
+        .then((value.clone() * col("weight_ms")).sum() / weight.sum())
+        .otherwise(value.mean())
+}
+
+/// The PM5 reports a missing heart rate belt as 0 or 255.
+fn heart_rate_bpm(heart_rate: &HeartRate) -> Option<u8> {
+    (heart_rate.0 != 0 && heart_rate.0 != u8::MAX).then_some(heart_rate.0)
+}
+
+fn tenth_pounds_to_newtons(force: u16) -> u16 {
+    (force as f64 * 0.1 * 4.448_222).round() as u16
+}
+
+pub(crate) fn mean<T>(values: impl Iterator<Item = Option<T>>) -> Option<T>
+where
+    T: Into<f64> + TryFrom<u32>,
+{
+    let (sum, count) = values
+        .flatten()
+        .fold((0.0, 0u32), |(sum, count), v| (sum + v.into(), count + 1));
+    if count == 0 {
+        return None;
+    }
+    T::try_from((sum / count as f64).round() as u32).ok()
+}
+
+#[derive(Clone)]
 pub struct WorkoutStorage {
     operator: opendal::Operator,
+    /// Set for disk storage so Parquet files can be scanned in place.
+    local_root: Option<PathBuf>,
+    /// One lock per user, held while a file shared by the user's workouts is
+    /// read, changed and written back, so concurrent saves don't lose each
+    /// other's changes. Shared by clones, but not between processes.
+    user_locks: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+}
+
+/// A workout that was saved, and the personal records it set.
+#[derive(Debug, Clone)]
+pub struct SavedWorkout {
+    pub summary: WorkoutSummary,
+    pub new_records: Vec<PersonalRecord>,
+}
+
+/// Connection settings for an S3-compatible object store such as AWS S3,
+/// MinIO or Cloudflare R2.
+#[cfg(feature = "s3")]
+#[derive(Debug, Clone, Default)]
+pub struct S3Config {
+    pub bucket: String,
+    /// Prefix inside the bucket that workouts are stored under.
+    pub root: Option<String>,
+    pub region: Option<String>,
+    /// Custom endpoint, required for anything but AWS itself.
+    pub endpoint: Option<String>,
+    /// When unset, credentials are loaded from the environment.
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
 }
 
 impl WorkoutStorage {
+    /// Store workouts in any opendal backend.
+    pub fn from_operator(operator: opendal::Operator) -> Self {
+        Self {
+            operator,
+            local_root: None,
+            user_locks: Arc::default(),
+        }
+    }
+
+    /// Store workouts on disk under `root`, relative to the working directory
+    /// unless it's an absolute path.
     pub async fn new_disk(root: &str) -> anyhow::Result<Self> {
-        let builder = opendal::services::Fs::default().root(&format!("./{root}"));
+        let root = if std::path::Path::new(root).is_absolute() {
+            root.to_owned()
+        } else {
+            format!("./{root}")
+        };
+        // Files are written in here and then renamed into place, so a crash
+        // never leaves a half-written file behind.
+        let builder = opendal::services::Fs::default()
+            .root(&root)
+            .atomic_write_dir(&format!("{root}/.tmp"));
+        let operator = opendal::Operator::new(builder)?.finish();
+        Ok(Self {
+            local_root: Some(PathBuf::from(root)),
+            ..Self::from_operator(operator)
+        })
+    }
+
+    /// Keep workouts in memory, mostly useful for tests.
+    pub fn new_memory() -> anyhow::Result<Self> {
+        let operator = opendal::Operator::new(opendal::services::Memory::default())?.finish();
+        Ok(Self::from_operator(operator))
+    }
+
+    #[cfg(feature = "s3")]
+    pub fn new_s3(config: S3Config) -> anyhow::Result<Self> {
+        let mut builder = opendal::services::S3::default().bucket(&config.bucket);
+        if let Some(root) = &config.root {
+            builder = builder.root(root);
+        }
+        if let Some(region) = &config.region {
+            builder = builder.region(region);
+        }
+        if let Some(endpoint) = &config.endpoint {
+            builder = builder.endpoint(endpoint);
+        }
+        if let (Some(access_key_id), Some(secret_access_key)) =
+            (&config.access_key_id, &config.secret_access_key)
+        {
+            builder = builder
+                .access_key_id(access_key_id)
+                .secret_access_key(secret_access_key)
+                .disable_config_load();
+        }
         let operator = opendal::Operator::new(builder)?.finish();
-        Ok(Self { operator })
+        Ok(Self::from_operator(operator))
+    }
+
+    pub fn operator(&self) -> &opendal::Operator {
+        &self.operator
+    }
+
+    /// Wait for the user's lock; see `user_locks`.
+    async fn lock_user(&self, user_id: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = self
+            .user_locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(user_id.to_owned())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
     }
 
+    /// Save a workout's samples and summary. The summary is written last and
+    /// commits the save: samples without a summary are left over from an
+    /// interrupted save and are cleaned up by [`Self::repair`].
+    ///
+    /// Returns the personal records the workout set.
     pub async fn save_workout(
         &self,
         recorder: &WorkoutRecorder,
         summary: &WorkoutSummary,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Vec<PersonalRecord>> {
         let df = recorder.to_dataframe()?;
 
         let path = format!(
             "workouts/{}/{}.parquet",
             summary.user_id, summary.workout_id
         );
+        self.operator
+            .write(&path, encode_samples(df, recorder.device())?)
+            .await?;
+        self.commit_workout(summary, workout_records(summary, &recorder.samples))
+            .await
+    }
 
-        let mut buffer = Vec::new();
-        ParquetWriter::new(&mut buffer)
-            .with_compression(ParquetCompression::Snappy)
-            .finish(&mut df.clone())?;
-
-        self.operator.write(&path, buffer).await?;
-
+    /// Write the summary of a workout whose samples were written, and keep
+    /// any records among `candidates`.
+    async fn commit_workout(
+        &self,
+        summary: &WorkoutSummary,
+        candidates: Vec<PersonalRecord>,
+    ) -> anyhow::Result<Vec<PersonalRecord>> {
         let summary_path = format!("summaries/{}/{}.json", summary.user_id, summary.workout_id);
         let summary_json = serde_json::to_vec(summary)?;
         self.operator.write(&summary_path, summary_json).await?;
 
+        let _lock = self.lock_user(&summary.user_id).await;
+        self.update_summary_index(summary).await?;
+        let mut records = self.personal_records(&summary.user_id).await?;
+        let new = update_records(&mut records, candidates);
+        if !new.is_empty() {
+            self.write_personal_records(&summary.user_id, &records)
+                .await?;
+        }
+        Ok(new)
+    }
+
+    /// The user's personal records, for each machine type.
+    pub async fn personal_records(&self, user_id: &str) -> anyhow::Result<Vec<PersonalRecord>> {
+        let path = format!("users/{}/records.json", user_id);
+        match self.operator.read(&path).await {
+            Ok(data) => Ok(serde_json::from_slice(&data.to_bytes())?),
+            Err(err) if err.kind() == opendal::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn write_personal_records(
+        &self,
+        user_id: &str,
+        records: &[PersonalRecord],
+    ) -> anyhow::Result<()> {
+        let path = format!("users/{}/records.json", user_id);
+        self.operator
+            .write(&path, serde_json::to_vec(records)?)
+            .await?;
         Ok(())
     }
 
-    pub async fn load_workout_lazy(
+    pub async fn load_summary(
         &self,
         user_id: &str,
-        workout_id: uuid::Uuid,
-    ) -> anyhow::Result<LazyFrame> {
-        let path = format!("workouts/{}/{}.parquet", user_id, workout_id);
+        workout_id: Uuid,
+    ) -> anyhow::Result<WorkoutSummary> {
+        let path = format!("summaries/{}/{}.json", user_id, workout_id);
         let data = self.operator.read(&path).await?;
-        let bytes: Vec<_> = data.into_iter().flat_map(|bytes| bytes.to_vec()).collect();
-
-        let df = ParquetReader::new(std::io::Cursor::new(bytes)).finish()?;
-        Ok(df.lazy())
+        Ok(serde_json::from_slice(&data.to_bytes())?)
     }
 
-    pub async fn scan_user_workouts(&self, user_id: &str) -> anyhow::Result<Vec<String>> {
-        let prefix = format!("workouts/{}/", user_id);
-        let mut paths = Vec::new();
+    /// Summaries of a user's workouts matching `filter`, oldest first.
+    ///
+    /// The workouts are picked from the user's summary index, so only the
+    /// matching summaries are read. Unreadable ones are skipped.
+    pub async fn list_summaries(
+        &self,
+        user_id: &str,
+        filter: &SummaryFilter,
+    ) -> anyhow::Result<Vec<WorkoutSummary>> {
+        let workouts = self
+            .read_user_summary_index(user_id)
+            .await?
+            .lazy()
+            .filter(filter.expr())
+            .sort(["start_time"], Default::default())
+            .select([col("workout_id")])
+            .collect()?;
 
-        let mut lister = self.operator.lister(&prefix).await?;
-        while let Some(entry) = lister.try_next().await? {
-            if entry.path().ends_with(".parquet") {
-                paths.push(entry.path().to_owned());
+        let mut summaries = Vec::with_capacity(workouts.height());
+        for workout_id in workouts.column("workout_id")?.str()?.into_no_null_iter() {
+            let path = format!("summaries/{}/{}.json", user_id, workout_id);
+            let data = match self.operator.read(&path).await {
+                Ok(data) => data,
+                Err(err) if err.kind() == opendal::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if let Ok(summary) = serde_json::from_slice(&data.to_bytes()) {
+                summaries.push(summary);
             }
         }
+        Ok(summaries)
+    }
 
-        Ok(paths)
+    /// The summaries under `prefix`. Unreadable ones are skipped, and left
+    /// for [`Self::verify`] to report.
+    async fn read_summaries(&self, prefix: &str) -> anyhow::Result<Vec<WorkoutSummary>> {
+        let entries = self.operator.list_with(prefix).recursive(true).await?;
+        let mut summaries = Vec::new();
+        for entry in entries {
+            if entry.path().ends_with(".json") {
+                let data = self.operator.read(entry.path()).await?;
+                if let Ok(summary) = serde_json::from_slice(&data.to_bytes()) {
+                    summaries.push(summary);
+                }
+            }
+        }
+        Ok(summaries)
     }
-}
 
-/// Analytics utilities using LazyFrame
-pub struct WorkoutAnalytics;
+    /// Summaries of all users' workouts, one row per workout as laid out by
+    /// [`WorkoutSummary::to_dataframe`]. Filter with [`SummaryFilter::expr`].
+    ///
+    /// The index is kept in one file per user, each changed only under the
+    /// user's lock, so saves for different users never touch the same file.
+    /// As with [`Self::scan_workouts_lazy`], the files are only read when the
+    /// frame is collected on disk storage, so collect it outside the async
+    /// runtime.
+    pub async fn scan_summary_index(&self) -> anyhow::Result<LazyFrame> {
+        let entries = self.list_summary_index().await?;
+        let paths = entries
+            .iter()
+            .map(|entry| entry.path())
+            .filter(|path| parse_summary_index_path(path).is_some());
+        let sources = match &self.local_root {
+            Some(root) => ScanSources::Paths(
+                paths
+                    .map(|path| PlPath::Local(root.join(path).into()))
+                    .collect(),
+            ),
+            None => {
+                let mut buffers = Vec::new();
+                for path in paths {
+                    let bytes = self.operator.read(path).await?.to_bytes();
+                    buffers.push(polars::polars_utils::mmap::MemSlice::from_bytes(bytes));
+                }
+                ScanSources::Buffers(buffers.into())
+            }
+        };
+        if sources.is_empty() {
+            return Ok(WorkoutSummary::to_dataframe(&[])?.lazy());
+        }
+        let args = ScanArgsParquet {
+            hive_options: polars::io::HiveOptions::new_disabled(),
+            ..Default::default()
+        };
+        Ok(LazyFrame::scan_parquet_sources(sources, args)?)
+    }
 
-impl WorkoutAnalytics {
-    pub fn delta_time(lf: LazyFrame) -> LazyFrame {
+    /// Check the store for interrupted saves and unreadable files without
+    /// changing anything.
+    pub async fn verify(&self) -> anyhow::Result<StorageReport> {
+        let mut report = StorageReport::default();
+
+        let mut summaries = std::collections::HashSet::new();
+        for entry in self
+            .operator
+            .list_with("summaries/")
+            .recursive(true)
+            .await?
+        {
+            let Some((user_id, workout_id)) =
+                parse_workout_path(entry.path(), "summaries/", ".json")
+            else {
+                continue;
+            };
+            let data = self.operator.read(entry.path()).await?;
+            match serde_json::from_slice::<WorkoutSummary>(&data.to_bytes()) {
+                Ok(_) => {
+                    summaries.insert(workout_id);
+                }
+                Err(_) => report.unreadable_summaries.push((user_id, workout_id)),
+            }
+        }
+
+        let mut samples = std::collections::HashSet::new();
+        for entry in self.operator.list_with("workouts/").recursive(true).await? {
+            let Some((user_id, workout_id)) =
+                parse_workout_path(entry.path(), "workouts/", ".parquet")
+            else {
+                continue;
+            };
+            let data = self.operator.read(entry.path()).await?;
+            if decode_samples(data).is_err() {
+                report.unreadable_samples.push((user_id, workout_id));
+                continue;
+            }
+            samples.insert(workout_id);
+            if !summaries.contains(&workout_id)
+                && !report
+                    .unreadable_summaries
+                    .iter()
+                    .any(|(_, id)| *id == workout_id)
+            {
+                report.orphaned_samples.push((user_id, workout_id));
+            }
+        }
+        report.unrecoverable_summaries = report
+            .unreadable_summaries
+            .iter()
+            .filter(|(_, workout_id)| !samples.contains(workout_id))
+            .cloned()
+            .collect();
+
+        report.stale_index = match self.read_summary_index().await {
+            Ok(index) => {
+                let indexed: std::collections::HashSet<Uuid> = index
+                    .column("workout_id")?
+                    .str()?
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| Uuid::parse_str(id).ok())
+                    .collect();
+                indexed != summaries
+            }
+            Err(_) => true,
+        };
+        Ok(report)
+    }
+
+    /// Fix what [`Self::verify`] finds: summaries are regenerated from
+    /// orphaned samples, unreadable files are removed, and the summary index
+    /// is rebuilt if it's out of date. Unreadable summaries without readable
+    /// samples to regenerate them from are left as they are. Returns what was
+    /// found.
+    pub async fn repair(&self) -> anyhow::Result<StorageReport> {
+        let report = self.verify().await?;
+
+        for workout in &report.unreadable_summaries {
+            if report.unrecoverable_summaries.contains(workout) {
+                continue;
+            }
+            let (user_id, workout_id) = workout;
+            self.operator
+                .delete(&format!("summaries/{}/{}.json", user_id, workout_id))
+                .await?;
+            self.regenerate_summary(user_id, *workout_id).await?;
+        }
+        for (user_id, workout_id) in &report.unreadable_samples {
+            self.operator
+                .delete(&format!("workouts/{}/{}.parquet", user_id, workout_id))
+                .await?;
+        }
+        for (user_id, workout_id) in &report.orphaned_samples {
+            self.regenerate_summary(user_id, *workout_id).await?;
+        }
+
+        if !report.is_consistent() {
+            self.rebuild_summary_index().await?;
+        }
+        Ok(report)
+    }
+
+    /// Save a summary computed from a workout's stored samples, along with
+    /// any records they set. Anything only the original recording knew, such
+    /// as the race, is lost.
+    async fn regenerate_summary(&self, user_id: &str, workout_id: Uuid) -> anyhow::Result<()> {
+        let data = self
+            .operator
+            .read(&format!("workouts/{}/{}.parquet", user_id, workout_id))
+            .await?;
+        let (df, metadata) = decode_samples(data)?;
+        let samples = WorkoutSample::from_dataframe(&df)?;
+        let mut recorder = WorkoutRecorder::from_samples(user_id.to_owned(), samples);
+        recorder.workout_id = workout_id;
+        if let Some(device) = metadata.device {
+            recorder.set_device(device);
+        }
+
+        let summary = recorder.generate_summary(None)?;
+        self.commit_workout(&summary, workout_records(&summary, &recorder.samples))
+            .await?;
+        Ok(())
+    }
+
+    /// Recreate the summary index from the individual summaries, e.g. after
+    /// they were written by an older version.
+    pub async fn rebuild_summary_index(&self) -> anyhow::Result<()> {
+        let mut user_ids = std::collections::BTreeSet::new();
+        for entry in self.operator.list("summaries/").await? {
+            if let Some(user_id) = entry.path().strip_prefix("summaries/") {
+                user_ids.insert(user_id.trim_end_matches('/').to_owned());
+            }
+        }
+        for entry in self.list_summary_index().await? {
+            if let Some(user_id) = parse_summary_index_path(entry.path()) {
+                user_ids.insert(user_id.to_owned());
+            }
+        }
+        user_ids.remove("");
+
+        for user_id in user_ids {
+            let _lock = self.lock_user(&user_id).await;
+            let mut summaries = self
+                .read_summaries(&format!("summaries/{}/", user_id))
+                .await?;
+            if summaries.is_empty() {
+                self.operator.delete(&summary_index_path(&user_id)).await?;
+                continue;
+            }
+            summaries.sort_by_key(|summary| summary.start_time);
+            let index = WorkoutSummary::to_dataframe(&summaries)?;
+            self.write_summary_index(&user_id, index).await?;
+        }
+        Ok(())
+    }
+
+    /// All users' index files, stacked.
+    async fn read_summary_index(&self) -> anyhow::Result<DataFrame> {
+        let mut index = WorkoutSummary::to_dataframe(&[])?;
+        for entry in self.list_summary_index().await? {
+            if let Some(user_id) = parse_summary_index_path(entry.path()) {
+                index.vstack_mut(&self.read_user_summary_index(user_id).await?)?;
+            }
+        }
+        Ok(index)
+    }
+
+    async fn list_summary_index(&self) -> anyhow::Result<Vec<opendal::Entry>> {
+        Ok(self.operator.list(SUMMARY_INDEX_DIR).await?)
+    }
+
+    async fn read_user_summary_index(&self, user_id: &str) -> anyhow::Result<DataFrame> {
+        let path = summary_index_path(user_id);
+        match self.operator.read(&path).await {
+            Ok(data) => Ok(ParquetReader::new(std::io::Cursor::new(data.to_bytes())).finish()?),
+            Err(err) if err.kind() == opendal::ErrorKind::NotFound => {
+                Ok(WorkoutSummary::to_dataframe(&[])?)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Insert or replace `summary` in its user's index. The caller holds the
+    /// user's lock.
+    async fn update_summary_index(&self, summary: &WorkoutSummary) -> anyhow::Result<()> {
+        let id = summary.workout_id.to_string();
+        let updated = WorkoutSummary::to_dataframe(std::slice::from_ref(summary))?;
+        let index = self.read_user_summary_index(&summary.user_id).await?;
+        let keep: BooleanChunked = index
+            .column("workout_id")?
+            .str()?
+            .into_iter()
+            .map(|workout_id| workout_id != Some(id.as_str()))
+            .collect();
+        self.write_summary_index(&summary.user_id, index.filter(&keep)?.vstack(&updated)?)
+            .await
+    }
+
+    /// Remove a workout from its user's index. The caller holds the user's
+    /// lock.
+    async fn remove_from_summary_index(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<()> {
+        let index = self
+            .read_user_summary_index(user_id)
+            .await?
+            .lazy()
+            .filter(col("workout_id").neq(lit(workout_id.to_string())))
+            .collect()?;
+        self.write_summary_index(user_id, index).await
+    }
+
+    async fn write_summary_index(&self, user_id: &str, index: DataFrame) -> anyhow::Result<()> {
+        self.operator
+            .write(&summary_index_path(user_id), encode_parquet(index)?)
+            .await?;
+        Ok(())
+    }
+
+    /// Change a stored summary, e.g. to set `race_position` once a race is
+    /// over.
+    pub async fn update_summary(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+        update: impl FnOnce(&mut WorkoutSummary),
+    ) -> anyhow::Result<WorkoutSummary> {
+        let _lock = self.lock_user(user_id).await;
+        let mut summary = self.load_summary(user_id, workout_id).await?;
+        update(&mut summary);
+        anyhow::ensure!(
+            summary.user_id == user_id && summary.workout_id == workout_id,
+            "a summary can't be moved to another workout"
+        );
+
+        let summary_path = format!("summaries/{}/{}.json", user_id, workout_id);
+        self.operator
+            .write(&summary_path, serde_json::to_vec(&summary)?)
+            .await?;
+        self.update_summary_index(&summary).await?;
+        Ok(summary)
+    }
+
+    pub async fn save_profile(&self, profile: &UserProfile) -> anyhow::Result<()> {
+        let path = format!("users/{}/profile.json", profile.user_id);
+        self.operator
+            .write(&path, serde_json::to_vec(profile)?)
+            .await?;
+        Ok(())
+    }
+
+    /// The user's profile, or a default one if none was saved.
+    pub async fn load_profile(&self, user_id: &str) -> anyhow::Result<UserProfile> {
+        let path = format!("users/{}/profile.json", user_id);
+        match self.operator.read(&path).await {
+            Ok(data) => Ok(serde_json::from_slice(&data.to_bytes())?),
+            Err(err) if err.kind() == opendal::ErrorKind::NotFound => Ok(UserProfile {
+                user_id: user_id.to_owned(),
+                ..Default::default()
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Daily training load from the user's first workout through `to`, with
+    /// each workout's stress computed from its samples and the user's
+    /// profile. Workouts without the `metric` count as no load, as do those
+    /// without samples (see [`Self::load_kept_samples`]).
+    ///
+    /// Scores depend on the profile's current FTP and heart rates, so they
+    /// aren't stored: every call reads and scores the samples of every
+    /// workout through `to`. Keep the result rather than calling this for
+    /// each view of it.
+    pub async fn training_load(
+        &self,
+        user_id: &str,
+        metric: LoadMetric,
+        to: Date,
+    ) -> anyhow::Result<Vec<DailyLoad>> {
+        let profile = self.load_profile(user_id).await?;
+        let filter = SummaryFilter {
+            to: to
+                .next_day()
+                .map(|day| UtcDateTime::new(day, Time::MIDNIGHT)),
+            ..Default::default()
+        };
+        let mut workouts = Vec::new();
+        for summary in self.list_summaries(user_id, &filter).await? {
+            let stress = match self.load_kept_samples(user_id, summary.workout_id).await? {
+                Some(samples) => TrainingStress::from_samples(&samples, &profile),
+                None => TrainingStress::default(),
+            };
+            let date = UtcDateTime::from_unix_timestamp_nanos(summary.start_time)?.date();
+            workouts.push((date, stress.metric(metric).unwrap_or(0.0)));
+        }
+        Ok(daily_load(&workouts, to))
+    }
+
+    /// Race predictions from the best efforts of the user's workouts matching
+    /// `filter`, such as the last few months'. Set the filter's ergometer so
+    /// that efforts on different machines aren't mixed; without one the
+    /// workouts are taken as rows. See [`Self::load_kept_samples`] for
+    /// workouts without samples.
+    pub async fn race_predictions(
+        &self,
+        user_id: &str,
+        filter: &SummaryFilter,
+    ) -> anyhow::Result<Vec<RacePrediction>> {
+        let mut efforts = BestEfforts::default();
+        for summary in self.list_summaries(user_id, filter).await? {
+            efforts.merge(
+                match self.load_kept_samples(user_id, summary.workout_id).await? {
+                    Some(samples) => BestEfforts::from_samples(&samples),
+                    None => summary_efforts(&summary),
+                },
+            );
+        }
+        let ergometer = filter
+            .ergometer
+            .unwrap_or_else(|| Ergometer::from_machine_type(filter.machine_type));
+        Ok(RacePredictor::from_efforts(&efforts, ergometer).predict_races())
+    }
+
+    /// The drag factor of each of the user's workouts matching `filter`
+    /// that recorded one, against the range recommended for the user's body
+    /// weight, the workout's machine and `focus`. See
+    /// [`Self::load_kept_samples`] for workouts without samples.
+    pub async fn drag_factor_report(
+        &self,
+        user_id: &str,
+        filter: &SummaryFilter,
+        focus: TrainingFocus,
+    ) -> anyhow::Result<Vec<DragFactorReport>> {
+        let profile = self.load_profile(user_id).await?;
+        let mut reports = Vec::new();
+        for summary in self.list_summaries(user_id, filter).await? {
+            let drag = match self.load_kept_samples(user_id, summary.workout_id).await? {
+                Some(samples) => DragFactorSummary::from_samples(&samples),
+                None => summary.avg_drag_factor.map(DragFactorSummary::from_average),
+            };
+            let Some(drag) = drag else {
+                continue;
+            };
+            let ergometer = summary.ergometer();
+            let recommendation = DragRecommendation::new(ergometer, profile.body_weight_kg, focus);
+            reports.push(DragFactorReport {
+                workout_id: summary.workout_id,
+                start_time: summary.start_time,
+                ergometer,
+                deviations: drag.deviations(&recommendation),
+                summary: drag,
+                recommendation,
+            });
+        }
+        Ok(reports)
+    }
+
+    /// Remove a workout's samples, including unfinished parts, and its summary.
+    pub async fn delete_workout(&self, user_id: &str, workout_id: Uuid) -> anyhow::Result<()> {
+        let _lock = self.lock_user(user_id).await;
+        self.operator
+            .delete(&format!("workouts/{}/{}.parquet", user_id, workout_id))
+            .await?;
+        self.operator
+            .remove_all(&spilled_parts_dir(user_id, workout_id))
+            .await?;
+        self.operator
+            .delete(&format!("summaries/{}/{}.json", user_id, workout_id))
+            .await?;
+        self.remove_from_summary_index(user_id, workout_id).await?;
+
+        // Records set by the workout go with it, replaced by the best of the
+        // remaining workouts on the same machine.
+        let (removed, mut records): (Vec<_>, Vec<_>) = self
+            .personal_records(user_id)
+            .await?
+            .into_iter()
+            .partition(|record| record.workout_id == workout_id);
+        if removed.is_empty() {
+            return Ok(());
+        }
+        for summary in self
+            .list_summaries(user_id, &SummaryFilter::default())
+            .await?
+        {
+            if !removed
+                .iter()
+                .any(|r| r.machine_type == summary.machine_type)
+            {
+                continue;
+            }
+            let samples = self
+                .load_kept_samples(user_id, summary.workout_id)
+                .await?
+                .unwrap_or_default();
+            let candidates = workout_records(&summary, &samples)
+                .into_iter()
+                .filter(|candidate| removed.iter().any(|r| r.is_same_record(candidate)))
+                .collect();
+            update_records(&mut records, candidates);
+        }
+        self.write_personal_records(user_id, &records).await
+    }
+
+    /// Remove everything stored for a user, e.g. on an erasure request.
+    pub async fn delete_user(&self, user_id: &str) -> anyhow::Result<()> {
+        let _lock = self.lock_user(user_id).await;
+        self.operator
+            .remove_all(&format!("workouts/{}/", user_id))
+            .await?;
+        self.operator
+            .remove_all(&format!("summaries/{}/", user_id))
+            .await?;
+        self.operator
+            .remove_all(&format!("users/{}/", user_id))
+            .await?;
+        self.operator.delete(&summary_index_path(user_id)).await?;
+        Ok(())
+    }
+
+    /// Drop or downsample the samples of workouts that started longer than
+    /// `policy.max_age` ago. Summaries are kept. Returns how many workouts
+    /// were changed.
+    pub async fn apply_retention(&self, policy: &RetentionPolicy) -> anyhow::Result<usize> {
+        let cutoff = UtcDateTime::now() - policy.max_age;
+        let expired = self
+            .read_summary_index()
+            .await?
+            .lazy()
+            .filter(col("start_time").lt(lit(cutoff.unix_timestamp_nanos() as i64)))
+            .select([col("user_id"), col("workout_id")])
+            .collect()?;
+
+        let mut changed = 0;
+        let user_ids = expired.column("user_id")?.str()?;
+        let workout_ids = expired.column("workout_id")?.str()?;
+        for (user_id, workout_id) in user_ids
+            .into_no_null_iter()
+            .zip(workout_ids.into_no_null_iter())
+        {
+            let path = format!("workouts/{}/{}.parquet", user_id, workout_id);
+            if !self.operator.exists(&path).await? {
+                continue;
+            }
+            match policy.action {
+                RetentionAction::DropSamples => {
+                    self.operator.delete(&path).await?;
+                    changed += 1;
+                }
+                RetentionAction::Downsample { interval } => {
+                    let data = self.operator.read(&path).await?;
+                    let (df, metadata) = decode_samples(data)?;
+                    let samples = WorkoutSample::from_dataframe(&df)?;
+                    let downsampled = downsample(&samples, interval);
+                    if downsampled.len() < samples.len() {
+                        let df = WorkoutSample::to_dataframe(&downsampled)?;
+                        let device = metadata.device.unwrap_or_default();
+                        self.operator
+                            .write(&path, encode_samples(df, &device)?)
+                            .await?;
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Write the samples the recorder holds in memory as the next part of its
+    /// workout, so they survive a crash and don't have to be kept around.
+    pub async fn spill_samples(&self, recorder: &mut WorkoutRecorder) -> anyhow::Result<()> {
+        if recorder.pending_samples() == 0 {
+            return Ok(());
+        }
+        let df = recorder.to_dataframe()?;
+        let path = format!(
+            "{}{:06}.parquet",
+            spilled_parts_dir(recorder.user_id(), recorder.workout_id()),
+            recorder.spilled_parts
+        );
+
+        let mut writer = self.operator.writer(&path).await?;
+        writer.write(encode_samples(df, recorder.device())?).await?;
+        writer.close().await?;
+
+        recorder.drain_samples();
+        recorder.spilled_parts += 1;
+        Ok(())
+    }
+
+    /// Merge the spilled parts of a workout with the samples still in memory,
+    /// save it like any other workout and remove the parts.
+    ///
+    /// The samples in memory are spilled first, and the parts are then copied
+    /// into the workout's file one row group at a time. Only the columns the
+    /// summary and records are computed from are held for the whole workout.
+    ///
+    /// The parts are removed once the workout's file is written, before its
+    /// summary is, so that a workout is never both saved and unfinished. If
+    /// saving stops in between, [`Self::repair`] regenerates the summary.
+    pub async fn finish_spilled_workout(
+        &self,
+        recorder: &mut WorkoutRecorder,
+        race_id: Option<String>,
+    ) -> anyhow::Result<SavedWorkout> {
+        self.spill_samples(recorder).await?;
+        let parts_dir = spilled_parts_dir(recorder.user_id(), recorder.workout_id());
+        let parts = self.spilled_parts(&parts_dir).await?;
+        anyhow::ensure!(!parts.is_empty(), "no samples recorded");
+
+        let path = format!(
+            "workouts/{}/{}.parquet",
+            recorder.user_id(),
+            recorder.workout_id()
+        );
+        let encoded = EncodedBytes::default();
+        let mut file = self.operator.writer(&path).await?;
+        let mut batches: Option<polars::io::parquet::write::BatchedWriter<EncodedBytes>> = None;
+        let mut columns: Option<DataFrame> = None;
+        for part in parts {
+            let (mut df, _) = decode_samples(self.operator.read(&part).await?)?;
+            df.align_chunks_par();
+            let batches = match &mut batches {
+                Some(batches) => batches,
+                None => batches.insert(
+                    ParquetWriter::new(encoded.clone())
+                        .with_compression(ParquetCompression::Snappy)
+                        .with_key_value_metadata(Some(sample_metadata(recorder.device())?))
+                        .batched(df.schema())?,
+                ),
+            };
+            batches.write_batch(&df)?;
+            file.write(encoded.take()).await?;
+
+            let part_columns = df.select(SUMMARY_COLUMNS)?;
+            match &mut columns {
+                Some(columns) => {
+                    columns.vstack_mut(&part_columns)?;
+                }
+                None => columns = Some(part_columns),
+            }
+        }
+        if let Some(batches) = batches {
+            batches.finish()?;
+        }
+        file.write(encoded.take()).await?;
+        file.close().await?;
+        self.operator.remove_all(&parts_dir).await?;
+        recorder.spilled_parts = 0;
+
+        let columns = columns.unwrap_or_default();
+        let summary = recorder.summarize(columns.clone().lazy(), race_id)?;
+        let candidates = workout_records_from_dataframe(&summary, &columns)?;
+        let new_records = self.commit_workout(&summary, candidates).await?;
+        Ok(SavedWorkout {
+            summary,
+            new_records,
+        })
+    }
+
+    /// Workouts with spilled parts that were never finished, e.g. because the
+    /// app crashed mid-session.
+    pub async fn unfinished_workouts(&self, user_id: &str) -> anyhow::Result<Vec<Uuid>> {
+        let entries = self
+            .operator
+            .list(&format!("workouts/{}/", user_id))
+            .await?;
+        Ok(entries
+            .iter()
+            .filter_map(|entry| entry.name().strip_suffix(".parts/"))
+            .filter_map(|id| Uuid::parse_str(id).ok())
+            .collect())
+    }
+
+    /// Rebuild a recorder from the spilled parts of an unfinished workout. It
+    /// can keep recording, or be passed to [`WorkoutStorage::finish_spilled_workout`].
+    ///
+    /// Only the samples are spilled, so the recovered workout has no
+    /// intervals and no stroke count, unless the PM5 reports them after
+    /// recovery.
+    pub async fn recover_workout(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<WorkoutRecorder> {
+        let parts_dir = spilled_parts_dir(user_id, workout_id);
+        let spilled = self.read_spilled_parts(&parts_dir).await?;
+        if spilled.parts == 0 {
+            anyhow::bail!("no spilled samples for workout {workout_id}");
+        }
+        Ok(WorkoutRecorder::recovered(
+            user_id.to_owned(),
+            workout_id,
+            &spilled,
+        ))
+    }
+
+    async fn spilled_parts(&self, parts_dir: &str) -> anyhow::Result<Vec<String>> {
+        let mut parts: Vec<String> = self
+            .operator
+            .list(parts_dir)
+            .await?
+            .into_iter()
+            .filter(|entry| entry.path().ends_with(".parquet"))
+            .map(|entry| entry.path().to_owned())
+            .collect();
+        parts.sort();
+        Ok(parts)
+    }
+
+    /// Read the parts one at a time, keeping only what resuming needs.
+    async fn read_spilled_parts(&self, parts_dir: &str) -> anyhow::Result<SpilledSamples> {
+        let mut spilled = SpilledSamples::default();
+        for part in self.spilled_parts(parts_dir).await? {
+            let data = self.operator.read(&part).await?;
+            let (df, metadata) = decode_samples(data)?;
+            let samples = WorkoutSample::from_dataframe(&df)?;
+            spilled.first = spilled.first.or_else(|| samples.first().cloned());
+            spilled.last = samples.last().cloned().or(spilled.last);
+            spilled.count += samples.len();
+            spilled.parts += 1;
+            spilled.device = spilled.device.or(metadata.device);
+        }
+        Ok(spilled)
+    }
+
+    pub async fn load_workout_lazy(
+        &self,
+        user_id: &str,
+        workout_id: uuid::Uuid,
+    ) -> anyhow::Result<LazyFrame> {
+        self.scan_samples(&format!("workouts/{}/{}.parquet", user_id, workout_id))
+            .await?
+            .ok_or_else(|| samples_dropped(workout_id))
+    }
+
+    /// A stored workout's samples, upgraded to the current schema.
+    pub async fn load_samples(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<Vec<WorkoutSample>> {
+        let path = format!("workouts/{}/{}.parquet", user_id, workout_id);
+        let (df, _) = decode_samples(self.operator.read(&path).await?)?;
+        Ok(WorkoutSample::from_dataframe(&df)?)
+    }
+
+    /// Like [`Self::load_samples`], but `None` for a workout whose samples
+    /// were dropped by a retention policy or a repair and only its summary
+    /// kept.
+    ///
+    /// The analyses over many workouts then fall back to the summary:
+    /// [`Self::training_load`] counts no load, [`Self::race_predictions`]
+    /// counts the workout as a whole if it's of a standard distance or time,
+    /// and [`Self::drag_factor_report`] judges its average drag factor.
+    async fn load_kept_samples(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<Option<Vec<WorkoutSample>>> {
+        let path = format!("workouts/{}/{}.parquet", user_id, workout_id);
+        let data = match self.operator.read(&path).await {
+            Ok(data) => data,
+            Err(err) if err.kind() == opendal::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let (df, _) = decode_samples(data)?;
+        Ok(Some(WorkoutSample::from_dataframe(&df)?))
+    }
+
+    /// The samples of a workout being exported, which can't be done without
+    /// them.
+    async fn load_exported_samples(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<Vec<WorkoutSample>> {
+        self.load_kept_samples(user_id, workout_id)
+            .await?
+            .ok_or_else(|| samples_dropped(workout_id))
+    }
+
+    /// Export a stored workout as a FIT activity.
+    pub async fn export_fit(&self, user_id: &str, workout_id: Uuid) -> anyhow::Result<Vec<u8>> {
+        let summary = self.load_summary(user_id, workout_id).await?;
+        let samples = self.load_exported_samples(user_id, workout_id).await?;
+        Ok(crate::fit::encode(&summary, &samples))
+    }
+
+    /// Import a FIT activity, e.g. from another rowing app, as a new workout.
+    pub async fn import_fit(&self, user_id: &str, data: &[u8]) -> anyhow::Result<SavedWorkout> {
+        let activity = crate::fit::decode(data)?;
+        let mut recorder = WorkoutRecorder::from_samples(user_id.to_owned(), activity.samples);
+        if let Some(machine_type) = activity.machine_type {
+            recorder.set_machine_type(machine_type);
+        }
+
+        let mut summary = recorder.generate_summary(None)?;
+        summary.start_time = activity.start_time;
+        if let Some(calories) = activity.total_calories {
+            summary.total_calories = calories;
+        }
+        if let Some(strokes) = activity.total_strokes {
+            summary.total_strokes = u16::try_from(strokes).ok();
+        }
+        // A single lap is just the whole workout.
+        if activity.laps.len() > 1 {
+            summary.intervals = activity.laps;
+        }
+        let new_records = self.save_workout(&recorder, &summary).await?;
+        Ok(SavedWorkout {
+            summary,
+            new_records,
+        })
+    }
+
+    /// Import a Logbook season export as new workouts, one per row.
+    ///
+    /// `strokes` holds the stroke data exports by Logbook id. Rows without
+    /// stroke data are stored with their totals only.
+    pub async fn import_logbook_csv(
+        &self,
+        user_id: &str,
+        logbook: &[u8],
+        strokes: &HashMap<u64, Vec<u8>>,
+    ) -> anyhow::Result<Vec<SavedWorkout>> {
+        let mut saved = Vec::new();
+        for row in crate::logbook_csv::read_logbook_csv(logbook)? {
+            let summary = row.to_summary(user_id)?;
+            saved.push(match row.log_id.and_then(|id| strokes.get(&id)) {
+                Some(data) => self.import_strokes(summary, data).await?,
+                None => {
+                    let recorder = WorkoutRecorder::from_samples(user_id.to_owned(), Vec::new());
+                    let new_records = self.save_workout(&recorder, &summary).await?;
+                    SavedWorkout {
+                        summary,
+                        new_records,
+                    }
+                }
+            });
+        }
+        Ok(saved)
+    }
+
+    /// Import a stroke data export, e.g. from ErgData, as a new workout on
+    /// `ergometer` that started at `start_time` (Unix nanoseconds).
+    pub async fn import_stroke_csv(
+        &self,
+        user_id: &str,
+        ergometer: Ergometer,
+        start_time: i128,
+        data: &[u8],
+    ) -> anyhow::Result<SavedWorkout> {
+        let samples = crate::logbook_csv::read_stroke_csv(data, ergometer, start_time)?;
+        let mut recorder = WorkoutRecorder::from_samples(user_id.to_owned(), samples);
+        recorder.set_machine_type(ergometer.machine_type());
+        let summary = recorder.generate_summary(None)?;
+        let new_records = self.save_workout(&recorder, &summary).await?;
+        Ok(SavedWorkout {
+            summary,
+            new_records,
+        })
+    }
+
+    /// Save stroke data under a summary read from the Logbook, whose totals
+    /// take precedence over the ones computed from the strokes.
+    async fn import_strokes(
+        &self,
+        mut summary: WorkoutSummary,
+        data: &[u8],
+    ) -> anyhow::Result<SavedWorkout> {
+        let samples =
+            crate::logbook_csv::read_stroke_csv(data, summary.ergometer(), summary.start_time)?;
+        let mut recorder = WorkoutRecorder::from_samples(summary.user_id.clone(), samples);
+        if let Some(machine_type) = summary.machine_type {
+            recorder.set_machine_type(machine_type);
+        }
+        let computed = recorder.generate_summary(None)?;
+        summary.avg_heart_rate_bpm = summary.avg_heart_rate_bpm.or(computed.avg_heart_rate_bpm);
+        summary.max_heart_rate_bpm = computed.max_heart_rate_bpm;
+        summary.min_heart_rate_bpm = computed.min_heart_rate_bpm;
+        summary.avg_power_watts = summary.avg_power_watts.or(computed.avg_power_watts);
+        let new_records = self.save_workout(&recorder, &summary).await?;
+        Ok(SavedWorkout {
+            summary,
+            new_records,
+        })
+    }
+
+    /// Export a stored workout as Garmin TCX.
+    pub async fn export_tcx(&self, user_id: &str, workout_id: Uuid) -> anyhow::Result<String> {
+        let summary = self.load_summary(user_id, workout_id).await?;
+        let samples = self.load_exported_samples(user_id, workout_id).await?;
+        let device = self
+            .load_workout_metadata(user_id, workout_id)
+            .await?
+            .device
+            .unwrap_or_default();
+        Ok(crate::tcx::to_tcx(&summary, &samples, &device))
+    }
+
+    /// Export a stored workout as a Concept2 Online Logbook result.
+    pub async fn export_logbook(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<crate::logbook::LogbookResult> {
+        let summary = self.load_summary(user_id, workout_id).await?;
+        let samples = self.load_exported_samples(user_id, workout_id).await?;
+        Ok(crate::logbook::LogbookResult::from_workout(
+            &summary, &samples,
+        ))
+    }
+
+    /// The schema version and device a workout's samples were stored with.
+    pub async fn load_workout_metadata(
+        &self,
+        user_id: &str,
+        workout_id: Uuid,
+    ) -> anyhow::Result<SampleFileMetadata> {
+        let path = format!("workouts/{}/{}.parquet", user_id, workout_id);
+        match &self.local_root {
+            Some(root) => read_sample_metadata(std::fs::File::open(root.join(path))?),
+            None => {
+                let data = self.operator.read(&path).await?;
+                read_sample_metadata(std::io::Cursor::new(data.to_bytes()))
+            }
+        }
+    }
+
+    /// Scan the samples of all of a user's saved workouts matching `filter`
+    /// as one frame, with `workout_id` and `start_time` columns added.
+    /// Workouts whose samples were dropped are left out. Files
+    /// are only read when the frame is collected on disk storage; other
+    /// backends fetch the bytes up front but still decode lazily. Like
+    /// [`Self::load_workout_lazy`], collect it outside the async runtime, e.g.
+    /// in `tokio::task::block_in_place`.
+    pub async fn scan_workouts_lazy(
+        &self,
+        user_id: &str,
+        filter: &SummaryFilter,
+    ) -> anyhow::Result<LazyFrame> {
+        let workouts = self
+            .read_user_summary_index(user_id)
+            .await?
+            .lazy()
+            .filter(filter.expr())
+            .sort(["start_time"], Default::default())
+            .select([col("workout_id"), col("start_time")])
+            .collect()?;
+
+        let mut frames = Vec::with_capacity(workouts.height());
+        let ids = workouts.column("workout_id")?.str()?;
+        let start_times = workouts.column("start_time")?.i64()?;
+        for (workout_id, start_time) in ids.into_no_null_iter().zip(start_times.into_no_null_iter())
+        {
+            let Some(lf) = self
+                .scan_samples(&format!("workouts/{}/{}.parquet", user_id, workout_id))
+                .await?
+            else {
+                continue;
+            };
+            frames.push(lf.with_columns([
+                lit(workout_id).alias("workout_id"),
+                lit(start_time).alias("start_time"),
+            ]));
+        }
+
+        if frames.is_empty() {
+            let empty = WorkoutSample::to_dataframe(&[])?.lazy().with_columns([
+                lit(NULL).cast(DataType::String).alias("workout_id"),
+                lit(NULL).cast(DataType::Int64).alias("start_time"),
+            ]);
+            return Ok(empty);
+        }
+        Ok(concat(frames, UnionArgs::default())?)
+    }
+
+    /// Scan a sample file, upgrading it in memory if it was written with an
+    /// older schema version. `None` if there is none, as for
+    /// [`Self::load_kept_samples`].
+    async fn scan_samples(&self, path: &str) -> anyhow::Result<Option<LazyFrame>> {
+        let sources = match &self.local_root {
+            Some(root) => {
+                let path = root.join(path);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                let metadata = read_sample_metadata(std::fs::File::open(&path)?)?;
+                if metadata.schema_version != SAMPLE_SCHEMA_VERSION {
+                    let df = ParquetReader::new(std::fs::File::open(&path)?).finish()?;
+                    return Ok(Some(migrate_samples(df, metadata.schema_version)?.lazy()));
+                }
+                ScanSources::Paths([PlPath::Local(path.into())].into_iter().collect())
+            }
+            None => {
+                let data = match self.operator.read(path).await {
+                    Ok(data) => data,
+                    Err(err) if err.kind() == opendal::ErrorKind::NotFound => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                };
+                let bytes = data.to_bytes();
+                let metadata = read_sample_metadata(std::io::Cursor::new(bytes.clone()))?;
+                if metadata.schema_version != SAMPLE_SCHEMA_VERSION {
+                    return Ok(Some(decode_samples(data)?.0.lazy()));
+                }
+                ScanSources::Buffers(
+                    [polars::polars_utils::mmap::MemSlice::from_bytes(bytes)].into(),
+                )
+            }
+        };
+        let args = ScanArgsParquet {
+            // Workouts aren't hive-partitioned, and in-memory buffers can't be.
+            hive_options: polars::io::HiveOptions::new_disabled(),
+            ..Default::default()
+        };
+        Ok(Some(LazyFrame::scan_parquet_sources(sources, args)?))
+    }
+
+    pub async fn scan_user_workouts(&self, user_id: &str) -> anyhow::Result<Vec<String>> {
+        let prefix = format!("workouts/{}/", user_id);
+        let mut paths = Vec::new();
+
+        let mut lister = self.operator.lister(&prefix).await?;
+        while let Some(entry) = lister.try_next().await? {
+            if entry.path().ends_with(".parquet") {
+                paths.push(entry.path().to_owned());
+            }
+        }
+
+        Ok(paths)
+    }
+}
+
+fn encode_parquet(df: DataFrame) -> PolarsResult<Vec<u8>> {
+    write_parquet(df, None)
+}
+
+fn write_parquet(mut df: DataFrame, metadata: Option<KeyValueMetadata>) -> PolarsResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    ParquetWriter::new(&mut buffer)
+        .with_compression(ParquetCompression::Snappy)
+        .with_key_value_metadata(metadata)
+        .finish(&mut df)?;
+    Ok(buffer)
+}
+
+/// Version of the sample columns written by [`WorkoutSample::to_dataframe`].
+/// Bump it whenever they change, with the upgrade from the previous version
+/// added to [`SAMPLE_MIGRATIONS`].
+pub const SAMPLE_SCHEMA_VERSION: u32 = 3;
+
+/// `SAMPLE_MIGRATIONS[i]` upgrades files of version `i + 1` to `i + 2`.
+const SAMPLE_MIGRATIONS: &[&[SchemaChange]] = &[
+    // 2: stroke recovery time and distance.
+    &[
+        SchemaChange::AddColumn {
+            name: "recovery_time_ms",
+            dtype: DataType::UInt32,
+        },
+        SchemaChange::AddColumn {
+            name: "stroke_distance_cm",
+            dtype: DataType::UInt32,
+        },
+    ],
+    // 3: drag factor.
+    &[SchemaChange::AddColumn {
+        name: "drag_factor",
+        dtype: DataType::UInt32,
+    }],
+];
+const _: () = assert!(SAMPLE_MIGRATIONS.len() as u32 == SAMPLE_SCHEMA_VERSION - 1);
+
+const SCHEMA_VERSION_KEY: &str = "pm5.sample_schema_version";
+const DEVICE_KEY: &str = "pm5.device";
+
+/// A change to the sample columns from one schema version to the next.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaChange {
+    /// A column older files don't have, filled with nulls.
+    AddColumn { name: &'static str, dtype: DataType },
+    RenameColumn {
+        from: &'static str,
+        to: &'static str,
+    },
+    /// Multiply an unsigned integer column by `factor` to convert its unit.
+    ScaleColumn { name: &'static str, factor: f64 },
+}
+
+impl SchemaChange {
+    fn apply(&self, lf: LazyFrame) -> LazyFrame {
+        match self {
+            SchemaChange::AddColumn { name, dtype } => {
+                lf.with_column(lit(NULL).cast(dtype.clone()).alias(*name))
+            }
+            SchemaChange::RenameColumn { from, to } => lf.rename([*from], [*to], true),
+            SchemaChange::ScaleColumn { name, factor } => lf.with_column(
+                // Sample values are never negative, so adding a half and
+                // truncating rounds to the nearest integer.
+                (col(*name).cast(DataType::Float64) * lit(*factor) + lit(0.5))
+                    .cast(DataType::UInt32)
+                    .alias(*name),
+            ),
+        }
+    }
+}
+
+/// How a sample file was written, from its Parquet key-value metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleFileMetadata {
+    /// Files written before the schema was versioned count as version 1.
+    pub schema_version: u32,
+    pub device: Option<DeviceInfo>,
+}
+
+fn encode_samples(df: DataFrame, device: &DeviceInfo) -> anyhow::Result<Vec<u8>> {
+    Ok(write_parquet(df, Some(sample_metadata(device)?))?)
+}
+
+fn sample_metadata(device: &DeviceInfo) -> anyhow::Result<KeyValueMetadata> {
+    Ok(KeyValueMetadata::from_static(vec![
+        (
+            SCHEMA_VERSION_KEY.to_owned(),
+            SAMPLE_SCHEMA_VERSION.to_string(),
+        ),
+        (DEVICE_KEY.to_owned(), serde_json::to_string(device)?),
+    ]))
+}
+
+/// Where a Parquet `BatchedWriter` encodes a file into, taken from after each
+/// batch so that only one batch is held in memory at a time.
+#[derive(Clone, Default)]
+struct EncodedBytes(Arc<Mutex<Vec<u8>>>);
+
+impl EncodedBytes {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(
+            &mut self
+                .0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+}
+
+impl std::io::Write for EncodedBytes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn read_sample_metadata<R: polars::io::mmap::MmapBytesReader>(
+    reader: R,
+) -> anyhow::Result<SampleFileMetadata> {
+    let mut reader = ParquetReader::new(reader);
+    let mut metadata = SampleFileMetadata {
+        schema_version: 1,
+        device: None,
+    };
+    for kv in reader.get_metadata()?.key_value_metadata().iter().flatten() {
+        match (kv.key.as_str(), &kv.value) {
+            (SCHEMA_VERSION_KEY, Some(value)) => metadata.schema_version = value.parse()?,
+            (DEVICE_KEY, Some(value)) => metadata.device = Some(serde_json::from_str(value)?),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+/// Read a sample file and upgrade it to [`SAMPLE_SCHEMA_VERSION`].
+fn decode_samples(data: opendal::Buffer) -> anyhow::Result<(DataFrame, SampleFileMetadata)> {
+    let data = data.to_bytes();
+    let metadata = read_sample_metadata(std::io::Cursor::new(data.clone()))?;
+    let df = ParquetReader::new(std::io::Cursor::new(data)).finish()?;
+    Ok((migrate_samples(df, metadata.schema_version)?, metadata))
+}
+
+fn migrate_samples(df: DataFrame, version: u32) -> anyhow::Result<DataFrame> {
+    if version == 0 || version > SAMPLE_SCHEMA_VERSION {
+        anyhow::bail!("unsupported sample schema version {version}");
+    }
+    let lf = SAMPLE_MIGRATIONS[version as usize - 1..]
+        .iter()
+        .flat_map(|changes| changes.iter())
+        .fold(df.lazy(), |lf, change| change.apply(lf));
+    Ok(lf.collect()?)
+}
+
+const SUMMARY_INDEX_DIR: &str = "index/summaries/";
+
+fn summary_index_path(user_id: &str) -> String {
+    format!("{SUMMARY_INDEX_DIR}{user_id}.parquet")
+}
+
+/// The user whose index file `path` is.
+fn parse_summary_index_path(path: &str) -> Option<&str> {
+    path.strip_prefix(SUMMARY_INDEX_DIR)?
+        .strip_suffix(".parquet")
+}
+
+/// What [`WorkoutStorage::verify`] found, as `(user_id, workout_id)` pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StorageReport {
+    /// Samples without a summary, left over from an interrupted save.
+    pub orphaned_samples: Vec<(String, Uuid)>,
+    pub unreadable_samples: Vec<(String, Uuid)>,
+    pub unreadable_summaries: Vec<(String, Uuid)>,
+    /// Unreadable summaries whose samples are gone or unreadable too, so
+    /// they can't be regenerated.
+    pub unrecoverable_summaries: Vec<(String, Uuid)>,
+    /// The summary index doesn't list exactly the stored summaries.
+    pub stale_index: bool,
+}
+
+impl StorageReport {
+    pub fn is_consistent(&self) -> bool {
+        self.orphaned_samples.is_empty()
+            && self.unreadable_samples.is_empty()
+            && self.unreadable_summaries.is_empty()
+            && !self.stale_index
+    }
+}
+
+/// Split `{prefix}{user_id}/{workout_id}{suffix}` into its ids.
+fn parse_workout_path(path: &str, prefix: &str, suffix: &str) -> Option<(String, Uuid)> {
+    let (user_id, file) = path.strip_prefix(prefix)?.split_once('/')?;
+    let workout_id = Uuid::parse_str(file.strip_suffix(suffix)?).ok()?;
+    Some((user_id.to_owned(), workout_id))
+}
+
+fn samples_dropped(workout_id: Uuid) -> anyhow::Error {
+    anyhow::anyhow!("the samples of workout {workout_id} were dropped by retention")
+}
+
+/// What a recorder resuming a spilled workout needs to know of its parts.
+#[derive(Debug, Clone, Default)]
+struct SpilledSamples {
+    first: Option<WorkoutSample>,
+    last: Option<WorkoutSample>,
+    count: usize,
+    parts: u32,
+    device: Option<DeviceInfo>,
+}
+
+fn spilled_parts_dir(user_id: &str, workout_id: Uuid) -> String {
+    format!("workouts/{}/{}.parts/", user_id, workout_id)
+}
+
+/// What [`WorkoutStorage::apply_retention`] does with old raw samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionAction {
+    /// Delete the samples, leaving only the summary.
+    DropSamples,
+    /// Keep one sample per `interval` of elapsed time.
+    Downsample { interval: std::time::Duration },
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    /// Workouts that started longer ago than this are affected.
+    pub max_age: std::time::Duration,
+    pub action: RetentionAction,
+}
+
+/// The efforts a workout makes as a whole, for workouts stored without
+/// samples: those of its records that need none.
+fn summary_efforts(summary: &WorkoutSummary) -> BestEfforts {
+    let mut efforts = BestEfforts::default();
+    for record in workout_records(summary, &[]) {
+        match record.kind {
+            RecordKind::FastestDistance { distance_m } => efforts.distances.push(DistanceEffort {
+                distance_m,
+                time_ms: record.value as u32,
+                start_ms: 0,
+                workout_id: None,
+            }),
+            RecordKind::MostDistance { duration_s } => efforts.durations.push(DurationEffort {
+                duration_s,
+                distance_m: record.value as u32,
+                start_ms: 0,
+                workout_id: None,
+            }),
+            RecordKind::PeakStrokePower | RecordKind::PeakMinutePower => {}
+        }
+    }
+    efforts
+}
+
+/// Keep the first sample in each `interval` of elapsed time, and the last
+/// sample so the workout still ends where it did.
+fn downsample(samples: &[WorkoutSample], interval: std::time::Duration) -> Vec<WorkoutSample> {
+    let interval_ms = (interval.as_millis() as u32).max(1);
+    let mut kept: Vec<WorkoutSample> = Vec::new();
+    for (i, sample) in samples.iter().enumerate() {
+        let bucket = sample.elapsed_time_ms / interval_ms;
+        let is_last = i == samples.len() - 1;
+        if is_last
+            || kept
+                .last()
+                .is_none_or(|last| last.elapsed_time_ms / interval_ms != bucket)
+        {
+            kept.push(sample.clone());
+        }
+    }
+    kept
+}
+
+/// When a [`StreamingRecorder`] spills samples to storage.
+#[derive(Debug, Clone)]
+pub struct SpillPolicy {
+    pub max_samples: usize,
+    pub max_interval: std::time::Duration,
+}
+
+impl Default for SpillPolicy {
+    fn default() -> Self {
+        Self {
+            max_samples: 1000,
+            max_interval: std::time::Duration::from_secs(60),
+        }
+    }
+}
+
+/// Records a session while keeping memory bounded, spilling samples to
+/// storage as Parquet parts according to a [`SpillPolicy`].
+pub struct StreamingRecorder {
+    recorder: WorkoutRecorder,
+    storage: WorkoutStorage,
+    policy: SpillPolicy,
+    last_spill: std::time::Instant,
+}
+
+impl StreamingRecorder {
+    pub fn new(recorder: WorkoutRecorder, storage: WorkoutStorage, policy: SpillPolicy) -> Self {
+        Self {
+            recorder,
+            storage,
+            policy,
+            last_spill: std::time::Instant::now(),
+        }
+    }
+
+    pub fn recorder(&self) -> &WorkoutRecorder {
+        &self.recorder
+    }
+
+    pub fn recorder_mut(&mut self) -> &mut WorkoutRecorder {
+        &mut self.recorder
+    }
+
+    /// Record a notification, saving any workouts it completed and spilling
+    /// samples if due. Returns the saved workouts.
+    pub async fn record(&mut self, data: &RowingData) -> anyhow::Result<Vec<SavedWorkout>> {
+        self.recorder.record(data);
+
+        let mut saved = Vec::new();
+        for mut completed in self.recorder.take_completed() {
+            saved.push(
+                self.storage
+                    .finish_spilled_workout(&mut completed, None)
+                    .await?,
+            );
+        }
+        self.spill_if_due().await?;
+        Ok(saved)
+    }
+
+    pub async fn spill_if_due(&mut self) -> anyhow::Result<()> {
+        if self.recorder.pending_samples() >= self.policy.max_samples
+            || self.last_spill.elapsed() >= self.policy.max_interval
+        {
+            self.spill().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn spill(&mut self) -> anyhow::Result<()> {
+        self.storage.spill_samples(&mut self.recorder).await?;
+        self.last_spill = std::time::Instant::now();
+        Ok(())
+    }
+
+    pub async fn finish(mut self, race_id: Option<String>) -> anyhow::Result<SavedWorkout> {
+        self.storage
+            .finish_spilled_workout(&mut self.recorder, race_id)
+            .await
+    }
+}
+
+/// Analytics utilities using LazyFrame
+pub struct WorkoutAnalytics;
+
+impl WorkoutAnalytics {
+    pub fn delta_time(lf: LazyFrame) -> LazyFrame {
         lf.with_column(
             (col("elapsed_time_ms") - col("elapsed_time_ms").shift(lit(1))).alias("duration_ms"),
         )
@@ -316,44 +2906,1498 @@ impl WorkoutAnalytics {
         )
     }
 
-    pub fn rolling_power_avg(lf: LazyFrame, window_size: usize) -> LazyFrame {
-        lf.with_column(
-            col("power_watts")
-                .cast(DataType::Float64)
-                .rolling_mean(RollingOptionsFixedWindow {
-                    window_size,
-                    min_periods: 1,
+    pub fn rolling_power_avg(lf: LazyFrame, window_size: usize) -> LazyFrame {
+        lf.with_column(
+            col("power_watts")
+                .cast(DataType::Float64)
+                .rolling_mean(RollingOptionsFixedWindow {
+                    window_size,
+                    min_periods: 1,
+                    ..Default::default()
+                })
+                .alias("rolling_avg_power"),
+        )
+    }
+
+    pub fn rolling_heartrate_avg(lf: LazyFrame, window_size: usize) -> LazyFrame {
+        lf.with_column(
+            col("heart_rate_bpm")
+                .cast(DataType::Float64)
+                .rolling_mean(RollingOptionsFixedWindow {
+                    window_size,
+                    min_periods: 1,
+                    ..Default::default()
+                })
+                .alias("rolling_avg_heartrate"),
+        )
+    }
+
+    /// Number each sample's power zone as `power_zone`, null where the power
+    /// or the user's power zones are unknown.
+    pub fn power_zones(lf: LazyFrame, zones: &TrainingZones) -> LazyFrame {
+        let bounds = zones.power.as_ref().map(|zones| &zones.bounds_watts[..]);
+        lf.with_column(zone_expr("power_watts", bounds).alias("power_zone"))
+    }
+
+    /// Number each sample's heart rate zone as `hr_zone`, null where the heart
+    /// rate or the user's heart rate zones are unknown.
+    pub fn hr_zones(lf: LazyFrame, zones: &TrainingZones) -> LazyFrame {
+        let bounds = zones.heart_rate.as_ref().map(|zones| &zones.bounds_bpm[..]);
+        lf.with_column(zone_expr("heart_rate_bpm", bounds).alias("hr_zone"))
+    }
+
+    /// Maximal mean power and fastest times over standard durations and
+    /// distances. `lf` may hold many workouts, with a `workout_id` column.
+    pub fn best_efforts(lf: LazyFrame) -> PolarsResult<BestEfforts> {
+        let mut lf = lf;
+        let mut columns = vec![
+            col("elapsed_time_ms"),
+            col("distance_m"),
+            col("power_watts"),
+        ];
+        if lf.collect_schema()?.contains("workout_id") {
+            columns.push(col("workout_id"));
+        }
+        BestEfforts::from_dataframe(&lf.select(columns).collect()?)
+    }
+
+    /// Technique metrics for each stroke of a single workout. See
+    /// [`crate::technique::TechniqueSummary::from_strokes`] for the session's averages and
+    /// [`StrokeTechnique::to_dataframe`] for them as a table.
+    pub fn stroke_technique(
+        lf: LazyFrame,
+        body_weight_kg: Option<f64>,
+    ) -> PolarsResult<Vec<StrokeTechnique>> {
+        let samples = WorkoutSample::from_dataframe(&lf.collect()?)?;
+        Ok(StrokeTechnique::from_samples(&samples, body_weight_kg))
+    }
+
+    /// The drag factor over a single workout, if it recorded one.
+    pub fn drag_factor(lf: LazyFrame) -> PolarsResult<Option<DragFactorSummary>> {
+        let samples = WorkoutSample::from_dataframe(&lf.collect()?)?;
+        Ok(DragFactorSummary::from_samples(&samples))
+    }
+
+    /// Compare output per heart beat between the halves of a steady session.
+    pub fn aerobic_decoupling(
+        lf: LazyFrame,
+        options: &DecouplingOptions,
+    ) -> PolarsResult<Option<AerobicDecoupling>> {
+        let output = match options.basis {
+            DecouplingBasis::Power => "power_watts",
+            DecouplingBasis::Pace => "pace_ms_per_500m",
+        };
+        let df = lf
+            .select([col("elapsed_time_ms"), col("heart_rate_bpm"), col(output)])
+            .collect()?;
+        AerobicDecoupling::from_dataframe(&df, options)
+    }
+
+    /// Find work and rest intervals in the samples of a single workout, for
+    /// workouts the PM5 reported none for. See [`IntervalSummary::to_dataframe`]
+    /// for them as a table.
+    pub fn detect_intervals(
+        lf: LazyFrame,
+        options: &IntervalDetection,
+    ) -> PolarsResult<Vec<IntervalSummary>> {
+        let samples = WorkoutSample::from_dataframe(&lf.collect()?)?;
+        Ok(detect_intervals(&samples, options))
+    }
+
+    /// Time spent in each zone of `zone_column`, as `time_in_zone_ms`. Each
+    /// sample counts for the time since the previous one.
+    pub fn time_in_zone(lf: LazyFrame, zone_column: &str) -> LazyFrame {
+        Self::delta_time(lf)
+            .filter(col(zone_column).is_not_null())
+            .group_by([col(zone_column)])
+            .agg([col("duration_ms")
+                .cast(DataType::UInt64)
+                .sum()
+                .alias("time_in_zone_ms")])
+            .sort([zone_column], SortMultipleOptions::default())
+    }
+}
+
+/// The zone number of `column`: one more than the number of bounds at or
+/// below the value.
+fn zone_expr<T: Copy + Into<u32>>(column: &str, bounds: Option<&[T]>) -> Expr {
+    let Some(bounds) = bounds else {
+        return lit(NULL).cast(DataType::UInt32);
+    };
+    bounds.iter().fold(lit(1u32), |zone, bound| {
+        zone + col(column)
+            .gt_eq(lit((*bound).into()))
+            .cast(DataType::UInt32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use crate::predict::PredictionModel;
+    use crate::records::RecordKind;
+    use crate::types::*;
+
+    use super::*;
+
+    fn split_interval(elapsed_ms: u32, work_ms: u32, distance_m: u32, number: u8) -> RowingData {
+        RowingData::SplitIntervalData {
+            elapsed_time: Time((elapsed_ms / 10).into()),
+            distance: Distance((distance_m * 10).into()),
+            split_interval_time: Time((work_ms / 100).into()),
+            split_interval_distance: Distance(distance_m.into()),
+            interval_rest_time: RestTime(60),
+            interval_rest_distance: RestDistance(12),
+            split_interval_type: IntervalType::Dist,
+            split_interval_number: IntervalCount(number),
+        }
+    }
+
+    #[test]
+    fn test_intervals_fall_back_to_samples() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_workout_state(WorkoutState::IntervalWorkDistance);
+        let work = Rowing {
+            meters_per_s: 5,
+            heart_rate_bpm: Some(140),
+            stroke_rate: Some(24),
+            pace_ms_per_500m: Some(100_000),
+            ..Default::default()
+        };
+        row(&mut recorder, 1..=50, work);
+        // Samples spilled mid-interval still count.
+        recorder.drain_samples();
+        let work = Rowing {
+            heart_rate_bpm: Some(160),
+            ..work
+        };
+        row(&mut recorder, 51..=100, work);
+        recorder.set_workout_state(WorkoutState::IntervalRest);
+        recorder.add_general_sample(101_000, 500, Some(120), None, None);
+        recorder.record(&split_interval(100_000, 100_000, 500, 1));
+
+        let intervals = recorder.intervals();
+        assert_eq!(intervals.len(), 1);
+        let interval = &intervals[0];
+        assert_eq!(interval.start_elapsed_ms, 0);
+        assert_eq!(interval.work_time_ms, 100_000);
+        assert_eq!(interval.rest_time_ms, 60_000);
+        assert_eq!(interval.work_distance_m, 500);
+        assert_eq!(interval.rest_distance_m, 12);
+        assert_eq!(interval.avg_pace_ms_per_500m, Some(100_000));
+        assert_eq!(interval.avg_power_watts, Some(200));
+        assert_eq!(interval.avg_stroke_rate, Some(24));
+        assert_eq!(interval.work_heart_rate_bpm, Some(150));
+        assert_eq!(interval.rest_heart_rate_bpm, None);
+    }
+
+    #[test]
+    fn test_slow_stroke_recovery() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        // An 8 s recovery, reported in hundredths of a second.
+        recorder.record(&RowingData::StrokeData {
+            elapsed_time: Time(1000.into()),
+            distance: Distance(500.into()),
+            drive_length: DriveLength(140),
+            drive_time: DriveTime(90),
+            stroke_recovery: StrokeRecoveryTime(800),
+            stroke_distance: StrokeDistance(1200),
+            peak_drive_force: Force(1500),
+            avg_drive_force: Force(1000),
+            work_per_stroke: Work(4000),
+            stroke_count: StrokeCount(5),
+        });
+        recorder.add_general_sample(10_000, 50, None, Some(6), None);
+        assert_eq!(recorder.samples[0].recovery_time_ms, Some(8000));
+        let strokes = StrokeTechnique::from_samples(&recorder.samples, None);
+        assert!((strokes[0].drive_recovery_ratio.unwrap() - 8.0 / 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_intervals_prefer_pm5_averages() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_workout_state(WorkoutState::IntervalRest);
+        recorder.record(&RowingData::AdditionalStatusOne {
+            elapsed_time: Time(6000.into()),
+            speed: Speed(0),
+            stroke_rate: StrokeRate(0),
+            heart_rate: HeartRate(130),
+            current_pace: Pace(0),
+            average_pace: Pace(0),
+            rest_distance: RestDistance(0),
+            rest_time: Time(0.into()),
+            machine_type: ErgMachineType::StaticSki,
+        });
+        recorder.record(&split_interval(60_000, 60_000, 300, 1));
+        recorder.record(&RowingData::AdditionalSplitIntervalData {
+            elapsed_time: Time(6000.into()),
+            split_interval_avg_stroke_rate: StrokeRate(30),
+            split_interval_work_heartrate: HeartRate(170),
+            split_interval_rest_heartrate: HeartRate(u8::MAX),
+            split_interval_avg_pace: Pace(1000),
+            split_interval_total_calories: Calories(20),
+            split_interval_avg_calories: Calories(1000),
+            split_interval_speed: Speed(5000),
+            split_interval_power: Power(350),
+            split_avg_drag_factor: DragFactor(90),
+            split_interval_number: IntervalCount(1),
+            erg_machine_type: ErgMachineType::StaticSki,
+        });
+
+        let summary = recorder.generate_summary(None).unwrap();
+        let interval = &summary.intervals[0];
+        assert_eq!(interval.avg_pace_ms_per_500m, Some(100_000));
+        assert_eq!(interval.avg_power_watts, Some(350));
+        assert_eq!(interval.avg_stroke_rate, Some(30));
+        assert_eq!(interval.work_heart_rate_bpm, Some(170));
+        assert_eq!(interval.rest_heart_rate_bpm, Some(130));
+    }
+
+    #[test]
+    fn test_undefined_rest_uses_pm5_rest_time() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_workout_state(WorkoutState::IntervalRest);
+        for rest_time in [1000, 2000, 4550] {
+            recorder.record(&RowingData::AdditionalStatusOne {
+                elapsed_time: Time(6000.into()),
+                speed: Speed(0),
+                stroke_rate: StrokeRate(0),
+                heart_rate: HeartRate(130),
+                current_pace: Pace(0),
+                average_pace: Pace(0),
+                rest_distance: RestDistance(0),
+                rest_time: Time(rest_time.into()),
+                machine_type: ErgMachineType::StaticD,
+            });
+        }
+        recorder.set_workout_state(WorkoutState::IntervalWorkDistance);
+        let mut split = split_interval(60_000, 60_000, 300, 1);
+        if let RowingData::SplitIntervalData {
+            interval_rest_time, ..
+        } = &mut split
+        {
+            *interval_rest_time = RestTime(0);
+        }
+        recorder.record(&split);
+
+        let summary = recorder.generate_summary(None).unwrap();
+        assert_eq!(summary.intervals[0].rest_time_ms, 45_500);
+    }
+
+    #[test]
+    fn test_summary_is_time_weighted() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        for (elapsed_ms, power, calories) in [
+            (0, 100, 0),
+            (3000, 300, 1),
+            (3001, 300, 1),
+            (3002, 300, 2),
+            (4000, 0, 1),
+        ] {
+            let distance_m = elapsed_ms / 200;
+            recorder.set_stroke_data(
+                elapsed_ms,
+                distance_m,
+                140,
+                800,
+                450,
+                380,
+                85,
+                Some(power),
+                Some(calories),
+            );
+            recorder.add_general_sample(elapsed_ms, distance_m, Some(140), Some(20), None);
+        }
+
+        let summary = recorder.generate_summary(None).unwrap();
+        assert_eq!(summary.avg_power_watts, Some(150));
+        assert_eq!(summary.duration_ms, 4000);
+        assert_eq!(summary.total_distance_m, 20);
+        assert_eq!(summary.total_calories, 2);
+        assert_eq!(summary.avg_pace_ms_per_500m, Some(100_000));
+        assert_eq!(summary.stroke_rate_adjusted_watts, Some(7.5));
+    }
+
+    #[test]
+    fn test_summary_prefers_end_of_workout_data() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.add_general_sample(1000, 5, Some(150), Some(24), None);
+        recorder.add_general_sample(2000, 10, Some(160), Some(24), None);
+        recorder.record(&RowingData::EndOfWorkoutSummaryData {
+            log_entry_date: LogEntryDate(0),
+            log_entry_time: LogEntryTime(0),
+            elapsed_time: Time(200.into()),
+            distance: Distance(110.into()),
+            avg_stroke_rate: StrokeRate(25),
+            ending_heartrate: HeartRate(161),
+            avg_heartrate: HeartRate(155),
+            min_heartrate: HeartRate(140),
+            max_heartrate: HeartRate(165),
+            drag_factor_avg: DragFactor(120),
+            recover_heartrate: HeartRate(110),
+            workout_type: WorkoutType::JustrowSplits,
+            avg_pace: Pace(900),
+        });
+
+        let summary = recorder.generate_summary(None).unwrap();
+        assert_eq!(summary.total_distance_m, 11);
+        assert_eq!(summary.avg_stroke_rate, Some(25));
+        assert_eq!(summary.avg_heart_rate_bpm, Some(155));
+        assert_eq!(summary.min_heart_rate_bpm, Some(140));
+        assert_eq!(summary.ending_heart_rate_bpm, Some(161));
+        assert_eq!(summary.recovery_heart_rate_bpm, Some(110));
+        assert_eq!(summary.avg_drag_factor, Some(120));
+        assert_eq!(summary.avg_pace_ms_per_500m, Some(90_000));
+    }
+
+    #[test]
+    fn test_workout_boundaries_split_recording() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_workout_state(WorkoutState::WorkoutRow);
+        for i in 0..10 {
+            recorder.add_general_sample(i * 1000, i * 4, Some(120), Some(18), None);
+        }
+        recorder.set_workout_state(WorkoutState::WorkoutEnd);
+        recorder.add_general_sample(9000, 36, Some(118), None, None);
+        recorder.set_workout_state(WorkoutState::Rearm);
+        recorder.set_workout_state(WorkoutState::WaitToBegin);
+        recorder.set_workout_state(WorkoutState::WorkoutRow);
+        for i in 0..5 {
+            recorder.add_general_sample(i * 1000, i * 5, Some(150), Some(28), None);
+        }
+        // The PM5 resets its elapsed time without a state change in between.
+        recorder.add_general_sample(0, 0, Some(150), Some(28), None);
+
+        let completed = recorder.take_completed();
+        assert_eq!(completed.len(), 2);
+        assert_eq!(completed[0].samples.len(), 10);
+        assert_eq!(completed[1].samples.len(), 5);
+        assert_ne!(completed[0].workout_id(), completed[1].workout_id());
+        assert_ne!(completed[1].workout_id(), recorder.workout_id());
+        assert_eq!(recorder.samples.len(), 1);
+        assert!(recorder.take_completed().is_empty());
+    }
+
+    #[test]
+    fn test_paused_time_is_excluded() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        let rowing = Rowing {
+            meters_per_s: 5,
+            heart_rate_bpm: Some(150),
+            stroke_rate: Some(24),
+            ..Default::default()
+        };
+        row(&mut recorder, 0..=5, rowing);
+        recorder.pause();
+        let paused = Rowing {
+            power_watts: 20,
+            heart_rate_bpm: Some(100),
+            stroke_rate: Some(10),
+            ..rowing
+        };
+        row(&mut recorder, 6..=15, paused);
+        recorder.resume();
+        row(&mut recorder, 16..=21, rowing);
+
+        let summary = recorder.generate_summary(None).unwrap();
+        assert_eq!(summary.duration_ms, 10_000);
+        assert_eq!(summary.total_distance_m, 50);
+        assert_eq!(summary.avg_power_watts, Some(200));
+        assert_eq!(summary.min_heart_rate_bpm, Some(150));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spilled_workout_can_be_recovered() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let policy = SpillPolicy {
+            max_samples: 10,
+            ..Default::default()
+        };
+        let mut streaming =
+            StreamingRecorder::new(WorkoutRecorder::new("user".into()), storage.clone(), policy);
+        streaming.recorder_mut().set_drag_factor(0, 120);
+        for i in 0..35 {
+            streaming
+                .recorder_mut()
+                .add_general_sample(i * 1000, i * 5, Some(150), Some(24), None);
+            streaming.spill_if_due().await.unwrap();
+        }
+        assert_eq!(streaming.recorder().pending_samples(), 5);
+
+        // Simulate a crash, losing the samples that weren't spilled yet.
+        let workout_id = streaming.recorder().workout_id();
+        drop(streaming);
+
+        assert_eq!(
+            storage.unfinished_workouts("user").await.unwrap(),
+            vec![workout_id]
+        );
+        let mut recovered = storage.recover_workout("user", workout_id).await.unwrap();
+        recovered.add_general_sample(35_000, 175, Some(150), Some(24), None);
+        let summary = storage
+            .finish_spilled_workout(&mut recovered, None)
+            .await
+            .unwrap()
+            .summary;
+
+        assert_eq!(summary.workout_id, workout_id);
+        assert_eq!(summary.total_distance_m, 175);
+        // The drag factor is taken from the spilled samples.
+        assert_eq!(summary.avg_drag_factor, Some(120));
+        assert!(summary.intervals.is_empty());
+        assert!(storage
+            .unfinished_workouts("user")
+            .await
+            .unwrap()
+            .is_empty());
+        let df = collect(storage.load_workout_lazy("user", workout_id).await.unwrap());
+        assert_eq!(df.height(), 31);
+    }
+
+    #[tokio::test]
+    async fn test_spilled_workout_matches_unspilled() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let mut recorder = WorkoutRecorder::new("a".into());
+        for i in 0..=120 {
+            let rowing = Rowing {
+                power_watts: 150 + (i % 40) as u16,
+                heart_rate_bpm: Some(140 + (i % 20) as u8),
+                stroke_rate: Some(24),
+                ..Default::default()
+            };
+            row(&mut recorder, i..=i, rowing);
+        }
+        let summary = recorder.generate_summary(None).unwrap();
+        let records = storage.save_workout(&recorder, &summary).await.unwrap();
+
+        let mut spilled = WorkoutRecorder::new("b".into());
+        for part in recorder.samples.chunks(25) {
+            spilled.samples.extend_from_slice(part);
+            storage.spill_samples(&mut spilled).await.unwrap();
+        }
+        let saved = storage
+            .finish_spilled_workout(&mut spilled, None)
+            .await
+            .unwrap();
+        let spilled_summary = saved.summary;
+
+        let totals = |s: &WorkoutSummary| {
+            (
+                s.duration_ms,
+                s.total_distance_m,
+                s.avg_power_watts,
+                s.avg_heart_rate_bpm,
+                s.max_heart_rate_bpm,
+                s.avg_stroke_rate,
+            )
+        };
+        assert_eq!(totals(&spilled_summary), totals(&summary));
+        let values = |records: &[PersonalRecord]| {
+            records
+                .iter()
+                .map(|r| (r.kind, r.value, r.offset_ms))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(&saved.new_records), values(&records));
+        let spilled_records = storage.personal_records("b").await.unwrap();
+        assert_eq!(values(&spilled_records), values(&records));
+        let samples = storage
+            .load_samples("b", spilled_summary.workout_id)
+            .await
+            .unwrap();
+        let df = WorkoutSample::to_dataframe(&samples).unwrap();
+        assert!(df.equals_missing(&recorder.to_dataframe().unwrap()));
+    }
+
+    /// Scans of stored Parquet block on Polars' own runtime.
+    fn collect(lf: LazyFrame) -> DataFrame {
+        tokio::task::block_in_place(|| lf.collect().unwrap())
+    }
+
+    /// Drop the samples of every stored workout, returning how many there were.
+    async fn drop_all_samples(storage: &WorkoutStorage) -> usize {
+        let drop = RetentionPolicy {
+            max_age: std::time::Duration::ZERO,
+            action: RetentionAction::DropSamples,
+        };
+        storage.apply_retention(&drop).await.unwrap()
+    }
+
+    async fn assert_round_trip(storage: &WorkoutStorage) {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        for i in 0..10 {
+            recorder.add_general_sample(i * 1000, i * 5, Some(150), Some(24), None);
+        }
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+
+        let paths = storage.scan_user_workouts("user").await.unwrap();
+        assert!(paths.contains(&format!("workouts/user/{}.parquet", summary.workout_id)));
+        let df = collect(
+            storage
+                .load_workout_lazy("user", summary.workout_id)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(df.height(), 10);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_memory_storage() {
+        assert_round_trip(&WorkoutStorage::new_memory().unwrap()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_list_summaries() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let mut ids = Vec::new();
+        for (user_id, distance, machine_type) in [
+            ("a", 2000, ErgMachineType::StaticD),
+            ("a", 5000, ErgMachineType::StaticD),
+            ("a", 5000, ErgMachineType::Bike),
+            ("b", 5000, ErgMachineType::StaticD),
+        ] {
+            let mut recorder = WorkoutRecorder::new(user_id.into());
+            recorder.set_machine_type(machine_type);
+            recorder.add_general_sample(0, 0, None, None, None);
+            recorder.add_general_sample(600_000, distance, None, None, None);
+            let summary = recorder.generate_summary(None).unwrap();
+            storage.save_workout(&recorder, &summary).await.unwrap();
+            ids.push(summary.workout_id);
+        }
+
+        let filter = SummaryFilter {
+            min_distance_m: Some(4000),
+            machine_type: Some(ErgMachineType::StaticD),
+            ..Default::default()
+        };
+        let summaries = storage.list_summaries("a", &filter).await.unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].workout_id, ids[1]);
+        let loaded = storage.load_summary("a", ids[1]).await.unwrap();
+        assert_eq!(loaded.machine_type, Some(ErgMachineType::StaticD));
+
+        let index = collect(
+            storage
+                .scan_summary_index()
+                .await
+                .unwrap()
+                .filter(filter.expr()),
+        );
+        assert_eq!(index.height(), 2);
+
+        let filter = SummaryFilter {
+            ergometer: Some(Ergometer::BikeErg),
+            ..Default::default()
+        };
+        let summaries = storage.list_summaries("a", &filter).await.unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].workout_id, ids[2]);
+        let filter = SummaryFilter {
+            ergometer: Some(Ergometer::RowErg),
+            ..Default::default()
+        };
+        let index = collect(
+            storage
+                .scan_summary_index()
+                .await
+                .unwrap()
+                .filter(filter.expr()),
+        );
+        assert_eq!(index.height(), 3);
+
+        storage
+            .operator()
+            .remove_all(SUMMARY_INDEX_DIR)
+            .await
+            .unwrap();
+        storage.rebuild_summary_index().await.unwrap();
+        let index = collect(storage.scan_summary_index().await.unwrap());
+        assert_eq!(index.height(), 4);
+    }
+
+    async fn assert_scans_workouts(storage: &WorkoutStorage) {
+        for distance in [2000, 5000] {
+            let mut recorder = WorkoutRecorder::new("user".into());
+            for i in 0..=10 {
+                recorder.add_general_sample(i * 60_000, i * distance / 10, None, None, None);
+            }
+            let summary = recorder.generate_summary(None).unwrap();
+            storage.save_workout(&recorder, &summary).await.unwrap();
+        }
+
+        let all = collect(
+            storage
+                .scan_workouts_lazy("user", &SummaryFilter::default())
+                .await
+                .unwrap()
+                .group_by([col("workout_id")])
+                .agg([col("distance_m").max(), col("start_time").first()]),
+        );
+        assert_eq!(all.height(), 2);
+
+        let filter = SummaryFilter {
+            min_distance_m: Some(4000),
+            ..Default::default()
+        };
+        let long = collect(storage.scan_workouts_lazy("user", &filter).await.unwrap());
+        assert_eq!(long.height(), 11);
+        let index = collect(
+            storage
+                .scan_summary_index()
+                .await
+                .unwrap()
+                .filter(filter.expr()),
+        );
+        assert_eq!(index.height(), 1);
+        let listed = storage.list_summaries("user", &filter).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].total_distance_m, 5000);
+        assert_eq!(
+            long.column("distance_m").unwrap().u32().unwrap().max(),
+            Some(5000)
+        );
+
+        let lf = storage
+            .scan_workouts_lazy("user", &SummaryFilter::default())
+            .await
+            .unwrap();
+        let efforts = tokio::task::block_in_place(|| WorkoutAnalytics::best_efforts(lf)).unwrap();
+        let two_k = efforts.distances.iter().find(|e| e.distance_m == 2000);
+        assert_eq!(two_k.map(|e| e.time_ms), Some(240_000));
+
+        let none = collect(
+            storage
+                .scan_workouts_lazy("nobody", &SummaryFilter::default())
+                .await
+                .unwrap(),
+        );
+        assert_eq!(none.height(), 0);
+        assert!(none.column("workout_id").is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_scan_workouts_lazy() {
+        assert_scans_workouts(&WorkoutStorage::new_memory().unwrap()).await;
+
+        let root = std::env::temp_dir().join(format!("pm5-{}", Uuid::now_v7()));
+        let storage = WorkoutStorage::new_disk(root.to_str().unwrap())
+            .await
+            .unwrap();
+        assert_scans_workouts(&storage).await;
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    /// What [`row`] records each second. Tests set the values that matter to
+    /// them.
+    #[derive(Debug, Clone, Copy)]
+    struct Rowing {
+        meters_per_s: u32,
+        power_watts: u16,
+        heart_rate_bpm: Option<u8>,
+        stroke_rate: Option<u8>,
+        pace_ms_per_500m: Option<u32>,
+    }
+
+    impl Default for Rowing {
+        fn default() -> Self {
+            Self {
+                meters_per_s: 4,
+                power_watts: 200,
+                heart_rate_bpm: None,
+                stroke_rate: None,
+                pace_ms_per_500m: None,
+            }
+        }
+    }
+
+    /// Record a stroke and a general sample for each second in `seconds`.
+    fn row(recorder: &mut WorkoutRecorder, seconds: RangeInclusive<u32>, rowing: Rowing) {
+        for second in seconds {
+            let (elapsed_ms, distance_m) = (second * 1000, second * rowing.meters_per_s);
+            let power = Some(rowing.power_watts);
+            recorder.set_stroke_data(elapsed_ms, distance_m, 140, 800, 450, 380, 85, power, None);
+            recorder.add_general_sample(
+                elapsed_ms,
+                distance_m,
+                rowing.heart_rate_bpm,
+                rowing.stroke_rate,
+                rowing.pace_ms_per_500m,
+            );
+        }
+    }
+
+    async fn save_sample_workout(storage: &WorkoutStorage, user_id: &str) -> WorkoutSummary {
+        let mut recorder = WorkoutRecorder::new(user_id.into());
+        for i in 0..=60 {
+            recorder.add_general_sample(i * 1000, i * 4, None, None, None);
+        }
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+        summary
+    }
+
+    #[tokio::test]
+    async fn test_fit_export_and_import() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let exported = save_sample_workout(&storage, "a").await;
+        let data = storage.export_fit("a", exported.workout_id).await.unwrap();
+
+        let imported = storage.import_fit("b", &data).await.unwrap();
+        assert!(!imported.new_records.is_empty());
+        let imported = imported.summary;
+        assert_ne!(imported.workout_id, exported.workout_id);
+        assert_eq!(imported.total_distance_m, exported.total_distance_m);
+        assert_eq!(imported.duration_ms, exported.duration_ms);
+        let samples = storage
+            .load_samples("b", imported.workout_id)
+            .await
+            .unwrap();
+        assert_eq!(samples.len(), 61);
+        assert_eq!(imported.end_time, samples.last().unwrap().timestamp);
+    }
+
+    #[tokio::test]
+    async fn test_stroke_csv_import() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let strokes = "\
+Number,Time (seconds),Distance (meters),Pace (seconds),Watts,Cal/Hr,Stroke Rate,Heart Rate
+1,2.0,8.5,1:57.6,226,1078,30,140
+2,4.0,17.0,1:57.6,226,1078,30,142
+3,6.0,25.5,1:50.0,276,1250,31,144
+";
+        let start_time = 1_709_363_700_000_000_000;
+        let saved = storage
+            .import_stroke_csv("user", Ergometer::RowErg, start_time, strokes.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(saved.summary.start_time, start_time + 2_000_000_000);
+        assert_eq!(saved.summary.end_time, start_time + 6_000_000_000);
+        assert_eq!(saved.summary.total_distance_m, 26);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_logbook_csv_import() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let logbook = "\
+Log ID,Date,Work Time (Formatted),Work Time (Seconds),Work Distance,Stroke Rate/Cadence,Pace,Avg Watts,Total Cal,Avg Heart Rate,Type
+11,2024-03-02 07:15:00,0:08.0,8.0,34,30,1:57.6,226,2,0,RowErg
+12,2024-03-04 18:00:00,7:30.0,450.0,2000,28,1:52.5,246,143,150,SkiErg
+";
+        let strokes = "\
+Number,Time (seconds),Distance (meters),Pace (seconds),Watts,Cal/Hr,Stroke Rate,Heart Rate
+1,2.0,8.5,1:57.6,226,1078,30,140
+2,4.0,17.0,1:57.6,226,1078,30,142
+3,6.0,25.5,1:50.0,276,1250,31,144
+4,8.0,34.0,1:50.0,276,1250,31,146
+";
+        let strokes = HashMap::from([(11, strokes.as_bytes().to_vec())]);
+        let saved = storage
+            .import_logbook_csv("user", logbook.as_bytes(), &strokes)
+            .await
+            .unwrap();
+        let summaries: Vec<_> = saved.iter().map(|saved| &saved.summary).collect();
+        assert_eq!(summaries.len(), 2);
+        // The 2000 m is a record on the SkiErg.
+        assert!(saved[1]
+            .new_records
+            .iter()
+            .any(|r| r.kind == RecordKind::FastestDistance { distance_m: 2000 }));
+        assert_eq!(summaries[0].max_heart_rate_bpm, Some(146));
+        assert_eq!(summaries[1].total_distance_m, 2000);
+
+        let lf = storage
+            .load_workout_lazy("user", summaries[0].workout_id)
+            .await
+            .unwrap();
+        let df = collect(WorkoutAnalytics::rolling_power_avg(lf, 2));
+        let avg = df.column("rolling_avg_power").unwrap().f64().unwrap();
+        assert_eq!(avg.get(3), Some(276.0));
+        let listed = storage
+            .list_summaries("user", &SummaryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_update_and_delete() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let first = save_sample_workout(&storage, "a").await;
+        let second = save_sample_workout(&storage, "a").await;
+        save_sample_workout(&storage, "b").await;
+
+        let updated = storage
+            .update_summary("a", first.workout_id, |summary| {
+                summary.race_position = Some(2)
+            })
+            .await
+            .unwrap();
+        assert_eq!(updated.race_position, Some(2));
+        let index = collect(storage.scan_summary_index().await.unwrap());
+        assert_eq!(index.height(), 3);
+
+        storage.delete_workout("a", first.workout_id).await.unwrap();
+        let summaries = storage
+            .list_summaries("a", &SummaryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].workout_id, second.workout_id);
+        assert!(storage.load_summary("a", first.workout_id).await.is_err());
+
+        storage.delete_user("a").await.unwrap();
+        assert!(storage.scan_user_workouts("a").await.unwrap().is_empty());
+        assert!(storage
+            .list_summaries("a", &SummaryFilter::default())
+            .await
+            .unwrap()
+            .is_empty());
+        let index = collect(storage.scan_summary_index().await.unwrap());
+        assert_eq!(index.height(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_and_repair() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let saved = save_sample_workout(&storage, "user").await;
+        let broken_summary = save_sample_workout(&storage, "user").await;
+        let broken_samples = save_sample_workout(&storage, "user").await;
+        assert!(storage.verify().await.unwrap().is_consistent());
+
+        // A save interrupted after writing the samples.
+        let mut orphan = WorkoutRecorder::new("user".into());
+        for i in 0..=60 {
+            orphan.add_general_sample(i * 1000, i * 5, None, None, None);
+        }
+        let orphan_path = format!("workouts/user/{}.parquet", orphan.workout_id());
+        let df = orphan.to_dataframe().unwrap();
+        let data = encode_samples(df, orphan.device()).unwrap();
+        storage.operator().write(&orphan_path, data).await.unwrap();
+
+        let summary_path = format!("summaries/user/{}.json", broken_summary.workout_id);
+        let samples_path = format!("workouts/user/{}.parquet", broken_samples.workout_id);
+        let operator = storage.operator();
+        operator
+            .write(&summary_path, "{\"workout_id\":")
+            .await
+            .unwrap();
+        operator.write(&samples_path, "PAR1").await.unwrap();
+
+        let report = storage.repair().await.unwrap();
+        let user = |id| ("user".to_owned(), id);
+        assert_eq!(report.orphaned_samples, vec![user(orphan.workout_id())]);
+        assert_eq!(
+            report.unreadable_summaries,
+            vec![user(broken_summary.workout_id)]
+        );
+        assert_eq!(
+            report.unreadable_samples,
+            vec![user(broken_samples.workout_id)]
+        );
+        assert!(report.stale_index);
+        assert!(storage.verify().await.unwrap().is_consistent());
+
+        let regenerated = storage
+            .load_summary("user", orphan.workout_id())
+            .await
+            .unwrap();
+        assert_eq!(regenerated.total_distance_m, 300);
+        // Its records count like any other workout's.
+        let records = storage.personal_records("user").await.unwrap();
+        assert!(records.iter().any(|r| r.workout_id == orphan.workout_id()
+            && r.kind == RecordKind::MostDistance { duration_s: 60 }));
+        let regenerated = storage
+            .load_summary("user", broken_summary.workout_id)
+            .await
+            .unwrap();
+        assert_eq!(regenerated.total_distance_m, 240);
+        // The summary outlives its unreadable samples, as after retention.
+        assert!(!storage.operator().exists(&samples_path).await.unwrap());
+        assert!(storage
+            .load_summary("user", broken_samples.workout_id)
+            .await
+            .is_ok());
+        assert!(storage.load_summary("user", saved.workout_id).await.is_ok());
+
+        // A broken summary without samples can't be regenerated, and is kept.
+        let lost = Uuid::now_v7();
+        let lost_path = format!("summaries/user/{lost}.json");
+        operator.write(&lost_path, "{").await.unwrap();
+        let report = storage.repair().await.unwrap();
+        assert_eq!(report.unrecoverable_summaries, vec![user(lost)]);
+        assert!(storage.operator().exists(&lost_path).await.unwrap());
+    }
+
+    #[test]
+    fn test_training_zones() {
+        let mut recorder = WorkoutRecorder::new("user".into());
+        for (i, (power_watts, heart_rate_bpm)) in [
+            (100, None),
+            (160, Some(120)),
+            (230, Some(150)),
+            (230, Some(175)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let i = i as u32;
+            let rowing = Rowing {
+                power_watts,
+                heart_rate_bpm,
+                stroke_rate: Some(24),
+                ..Default::default()
+            };
+            row(&mut recorder, i..=i, rowing);
+        }
+        let zones = TrainingZones {
+            power: Some(crate::zones::PowerZones::from_ftp(200)),
+            heart_rate: Some(crate::zones::HeartRateZones::from_max_hr(190)),
+        };
+
+        let lf = recorder.to_lazyframe().unwrap();
+        let lf = WorkoutAnalytics::hr_zones(WorkoutAnalytics::power_zones(lf, &zones), &zones);
+        let df = lf.clone().collect().unwrap();
+        let power: Vec<_> = df
+            .column("power_zone")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .iter()
+            .collect();
+        assert_eq!(power, [Some(1), Some(3), Some(5), Some(5)]);
+        let hr: Vec<_> = df
+            .column("hr_zone")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .iter()
+            .collect();
+        assert_eq!(hr, [None, Some(2), Some(3), Some(5)]);
+
+        let time = WorkoutAnalytics::time_in_zone(lf, "power_zone")
+            .collect()
+            .unwrap();
+        let zone: Vec<_> = time
+            .column("power_zone")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .iter()
+            .collect();
+        let ms: Vec<_> = time
+            .column("time_in_zone_ms")
+            .unwrap()
+            .u64()
+            .unwrap()
+            .iter()
+            .collect();
+        assert_eq!(zone, [Some(1), Some(3), Some(5)]);
+        assert_eq!(ms, [Some(0), Some(1000), Some(2000)]);
+
+        let lf =
+            WorkoutAnalytics::power_zones(recorder.to_lazyframe().unwrap(), &Default::default());
+        let df = lf.collect().unwrap();
+        assert_eq!(df.column("power_zone").unwrap().null_count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_personal_records() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let mut recorder = WorkoutRecorder::new("a".into());
+        for i in 0..=60 {
+            let power_watts = 150 + i as u16;
+            row(
+                &mut recorder,
+                i..=i,
+                Rowing {
+                    power_watts,
                     ..Default::default()
+                },
+            );
+        }
+        let summary = recorder.generate_summary(None).unwrap();
+        let new = storage.save_workout(&recorder, &summary).await.unwrap();
+        let kinds: Vec<_> = new.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                RecordKind::MostDistance { duration_s: 60 },
+                RecordKind::FastestDistance { distance_m: 100 },
+                RecordKind::PeakMinutePower,
+                RecordKind::PeakStrokePower,
+            ]
+        );
+        let peak = &new[3];
+        assert_eq!((peak.value, peak.offset_ms), (210.0, 60_000));
+
+        // The same distances and times without power set nothing new.
+        let again = save_sample_workout(&storage, "a").await;
+        assert_eq!(storage.personal_records("a").await.unwrap(), new);
+
+        // Deleting the workout brings back the next best records.
+        storage
+            .delete_workout("a", summary.workout_id)
+            .await
+            .unwrap();
+        let records = storage.personal_records("a").await.unwrap();
+        let kinds: Vec<_> = records.iter().map(|r| r.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                RecordKind::MostDistance { duration_s: 60 },
+                RecordKind::FastestDistance { distance_m: 100 },
+            ]
+        );
+        assert!(records.iter().all(|r| r.workout_id == again.workout_id));
+
+        storage.delete_workout("a", again.workout_id).await.unwrap();
+        assert!(storage.personal_records("a").await.unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_personal_records() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        // Saves of the user's workouts wait for each other's records.
+        let lock = storage.lock_user("a").await;
+        let saves: Vec<_> = (0..8u16)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    let mut recorder = WorkoutRecorder::new("a".into());
+                    let rowing = Rowing {
+                        power_watts: 100 + i * 10,
+                        ..Default::default()
+                    };
+                    row(&mut recorder, 0..=60, rowing);
+                    let summary = recorder.generate_summary(None).unwrap();
+                    storage.save_workout(&recorder, &summary).await.unwrap();
                 })
-                .alias("rolling_avg_power"),
-        )
+            })
+            .collect();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(saves.iter().all(|save| !save.is_finished()));
+        assert!(storage.personal_records("a").await.unwrap().is_empty());
+
+        drop(lock);
+        for save in saves {
+            save.await.unwrap();
+        }
+        // Whatever order the saves finished in, the best one holds the record.
+        let records = storage.personal_records("a").await.unwrap();
+        let peak = records
+            .iter()
+            .find(|r| r.kind == RecordKind::PeakStrokePower)
+            .unwrap();
+        assert_eq!(peak.value, 170.0);
     }
 
-    pub fn rolling_heartrate_avg(lf: LazyFrame, window_size: usize) -> LazyFrame {
-        lf.with_column(
-            col("heart_rate_bpm")
-                .cast(DataType::Float64)
-                .rolling_mean(RollingOptionsFixedWindow {
-                    window_size,
-                    min_periods: 1,
-                    ..Default::default()
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_concurrent_summary_index() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let saves: Vec<_> = (0..8)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    let user_id = format!("user-{}", i % 4);
+                    save_sample_workout(&storage, &user_id).await.workout_id
                 })
-                .alias("rolling_avg_heartrate"),
-        )
+            })
+            .collect();
+        let mut ids = Vec::new();
+        for save in saves {
+            ids.push(save.await.unwrap().to_string());
+        }
+
+        // Each user's workouts are indexed in a file of their own.
+        for i in 0..4 {
+            let path = summary_index_path(&format!("user-{i}"));
+            assert!(storage.operator().exists(&path).await.unwrap());
+        }
+        let index = collect(storage.scan_summary_index().await.unwrap());
+        let mut indexed: Vec<_> = index
+            .column("workout_id")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .map(str::to_owned)
+            .collect();
+        indexed.sort();
+        ids.sort();
+        assert_eq!(indexed, ids);
+        assert!(storage.verify().await.unwrap().is_consistent());
+
+        storage.delete_user("user-0").await.unwrap();
+        let path = summary_index_path("user-0");
+        assert!(!storage.operator().exists(&path).await.unwrap());
+        let index = collect(storage.scan_summary_index().await.unwrap());
+        assert_eq!(index.height(), 6);
     }
 
-    pub fn power_zones(lf: LazyFrame) -> LazyFrame {
-        lf.with_column(
-            when(col("power_watts").lt(lit(150)))
-                .then(lit("Zone 1: Recovery"))
-                .when(col("power_watts").lt(lit(200)))
-                .then(lit("Zone 2: Endurance"))
-                .when(col("power_watts").lt(lit(250)))
-                .then(lit("Zone 3: Tempo"))
-                .when(col("power_watts").lt(lit(300)))
-                .then(lit("Zone 4: Threshold"))
-                .otherwise(lit("Zone 5: VO2 Max"))
-                .alias("power_zone"),
+    #[tokio::test]
+    async fn test_training_load() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        storage
+            .save_profile(&UserProfile {
+                user_id: "a".into(),
+                ftp_watts: Some(200),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let mut recorder = WorkoutRecorder::new("a".into());
+        row(&mut recorder, 0..=360, Rowing::default());
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+
+        let start = UtcDateTime::from_unix_timestamp_nanos(summary.start_time)
+            .unwrap()
+            .date();
+        let to = start.next_day().unwrap();
+        let days = storage
+            .training_load("a", LoadMetric::Tss, to)
+            .await
+            .unwrap();
+        assert_eq!(days.len(), 2);
+        assert!((days[0].load - 10.0).abs() < 1e-9);
+        assert_eq!(days[1].load, 0.0);
+
+        let days = storage
+            .training_load("a", LoadMetric::Trimp, to)
+            .await
+            .unwrap();
+        assert_eq!(days[0].load, 0.0);
+
+        // A workout whose samples were dropped counts as no load.
+        assert_eq!(drop_all_samples(&storage).await, 1);
+        let days = storage
+            .training_load("a", LoadMetric::Tss, to)
+            .await
+            .unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].load, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_race_predictions() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let mut recorder = WorkoutRecorder::new("a".into());
+        row(&mut recorder, 0..=360, Rowing::default());
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+
+        let predictions = storage
+            .race_predictions("a", &SummaryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            predictions.len(),
+            3 * crate::predict::RACE_DISTANCES_M.len()
+        );
+        // 1000m in 4:10.0, so 2:10.0/500m for 2000m by Paul's Law.
+        assert_eq!(predictions[0].model, PredictionModel::PaulsLaw);
+        assert_eq!(predictions[0].distance_m, 2000);
+        assert_eq!(predictions[0].time_ms, 520_000);
+        // A steady 200 W throughout makes a critical power of 200 W.
+        assert!((predictions[2].avg_power_watts - 200.0).abs() < 0.5);
+
+        // Without samples, a 2000m workout still counts as a whole.
+        let mut recorder = WorkoutRecorder::new("a".into());
+        row(&mut recorder, 0..=500, Rowing::default());
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+        assert_eq!(drop_all_samples(&storage).await, 2);
+        let predictions = storage
+            .race_predictions("a", &SummaryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            predictions.len(),
+            2 * crate::predict::RACE_DISTANCES_M.len()
+        );
+        assert_eq!(predictions[0].distance_m, 2000);
+        assert_eq!(predictions[0].time_ms, 500_000);
+    }
+
+    #[tokio::test]
+    async fn test_drag_factor_report() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let profile = UserProfile {
+            body_weight_kg: Some(75),
+            ..storage.load_profile("a").await.unwrap()
+        };
+        storage.save_profile(&profile).await.unwrap();
+        let mut recorder = WorkoutRecorder::new("a".into());
+        for i in 0..=120 {
+            if i == 0 || i == 60 {
+                recorder.set_drag_factor(i * 1000, if i == 0 { 135 } else { 150 });
+            }
+            recorder.add_general_sample(i * 1000, i * 4, None, None, None);
+        }
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+
+        let samples = storage.load_samples("a", summary.workout_id).await.unwrap();
+        assert_eq!(samples[0].drag_factor, Some(135));
+        assert_eq!(samples[120].drag_factor, Some(150));
+
+        let reports = storage
+            .drag_factor_report("a", &SummaryFilter::default(), TrainingFocus::Threshold)
+            .await
+            .unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].summary.drift, 15);
+        assert!((reports[0].summary.avg - 142.5).abs() < 1e-9);
+        assert_eq!(
+            reports[0].recommendation,
+            DragRecommendation { min: 112, max: 128 }
+        );
+        assert_eq!(
+            reports[0].deviations,
+            vec![
+                crate::drag::DragDeviation::TooHigh,
+                crate::drag::DragDeviation::Drifted
+            ]
+        );
+
+        // Without samples, only the summary's average is known.
+        assert_eq!(drop_all_samples(&storage).await, 1);
+        let reports = storage
+            .drag_factor_report("a", &SummaryFilter::default(), TrainingFocus::Threshold)
+            .await
+            .unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].summary,
+            DragFactorSummary::from_average(summary.avg_drag_factor.unwrap())
+        );
+        assert_eq!(
+            reports[0].deviations,
+            vec![crate::drag::DragDeviation::TooHigh]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_profile() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let profile = storage.load_profile("a").await.unwrap();
+        assert_eq!(profile.user_id, "a");
+        assert_eq!(profile.zones, TrainingZones::default());
+
+        let profile = UserProfile {
+            zones: TrainingZones {
+                heart_rate: Some(crate::zones::HeartRateZones::from_lthr(170)),
+                ..Default::default()
+            },
+            ..profile
+        };
+        storage.save_profile(&profile).await.unwrap();
+        assert_eq!(storage.load_profile("a").await.unwrap(), profile);
+
+        storage.delete_user("a").await.unwrap();
+        assert_eq!(
+            storage.load_profile("a").await.unwrap().zones,
+            TrainingZones::default()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_retention() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let summary = save_sample_workout(&storage, "user").await;
+        let path = format!("workouts/user/{}.parquet", summary.workout_id);
+        let read_samples = || async {
+            let data = storage.operator().read(&path).await.unwrap();
+            let df = ParquetReader::new(std::io::Cursor::new(data.to_bytes()))
+                .finish()
+                .unwrap();
+            WorkoutSample::from_dataframe(&df).unwrap()
+        };
+
+        let recent = RetentionPolicy {
+            max_age: std::time::Duration::from_secs(86_400),
+            action: RetentionAction::DropSamples,
+        };
+        assert_eq!(storage.apply_retention(&recent).await.unwrap(), 0);
+
+        let downsample = RetentionPolicy {
+            max_age: std::time::Duration::ZERO,
+            action: RetentionAction::Downsample {
+                interval: std::time::Duration::from_secs(10),
+            },
+        };
+        assert_eq!(storage.apply_retention(&downsample).await.unwrap(), 1);
+        let samples = read_samples().await;
+        assert_eq!(samples.len(), 7);
+        assert_eq!(samples.last().unwrap().distance_m, 240);
+        assert_eq!(storage.apply_retention(&downsample).await.unwrap(), 0);
+
+        assert_eq!(drop_all_samples(&storage).await, 1);
+        assert!(!storage.operator().exists(&path).await.unwrap());
+        assert!(storage
+            .load_summary("user", summary.workout_id)
+            .await
+            .is_ok());
+
+        // Scans leave the workout out, and exports say why they can't.
+        let lf = storage
+            .scan_workouts_lazy("user", &SummaryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(collect(lf).height(), 0);
+        let err = storage
+            .export_tcx("user", summary.workout_id)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("dropped by retention"));
+    }
+
+    fn golden_samples() -> Vec<WorkoutSample> {
+        vec![
+            WorkoutSample {
+                timestamp: 1_700_000_000_000_000_000,
+                elapsed_time_ms: 0,
+                distance_m: 0,
+                heart_rate_bpm: Some(95),
+                ..Default::default()
+            },
+            WorkoutSample {
+                timestamp: 1_700_000_001_500_000_000,
+                elapsed_time_ms: 1500,
+                distance_m: 6,
+                heart_rate_bpm: Some(101),
+                power_watts: Some(212),
+                stroke_rate: Some(26),
+                pace_ms_per_500m: Some(118_400),
+                calories: Some(1),
+                drive_length_cm: Some(142),
+                drive_time_ms: Some(790),
+                peak_drive_force_n: Some(812),
+                avg_drive_force_n: Some(455),
+                work_per_stroke_j: Some(610),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn golden_device() -> DeviceInfo {
+        DeviceInfo {
+            model_number: Some("PM5".into()),
+            serial_number: Some("430000001".into()),
+            hardware_revision: Some("634".into()),
+            firmware_revision: Some("210".into()),
+            manufacturer_name: Some("Concept2".into()),
+            machine_type: Some(ErgMachineType::StaticD),
+        }
+    }
+
+    fn golden_file(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/data")
+            .join(name)
+    }
+
+    #[test]
+    #[ignore = "writes tests/data, run once after bumping SAMPLE_SCHEMA_VERSION"]
+    fn write_golden_sample_file() {
+        let df = WorkoutSample::to_dataframe(&golden_samples()).unwrap();
+        let path = golden_file(&format!("samples_v{SAMPLE_SCHEMA_VERSION}.parquet"));
+        std::fs::write(path, encode_samples(df, &golden_device()).unwrap()).unwrap();
+    }
+
+    fn assert_golden_file(name: &str, schema_version: u32, device: Option<DeviceInfo>) {
+        let data = opendal::Buffer::from(std::fs::read(golden_file(name)).unwrap());
+        let (df, metadata) = decode_samples(data).unwrap();
+        assert_eq!(metadata.schema_version, schema_version);
+        assert_eq!(metadata.device, device);
+
+        let expected = WorkoutSample::to_dataframe(&golden_samples()).unwrap();
+        assert!(df.equals_missing(&expected), "{name} decoded to {df}");
+    }
+
+    #[test]
+    fn test_golden_sample_files() {
+        // Written before sample files carried any metadata.
+        assert_golden_file("samples_unversioned.parquet", 1, None);
+        assert_golden_file("samples_v1.parquet", 1, Some(golden_device()));
+        assert_golden_file("samples_v2.parquet", 2, Some(golden_device()));
+        assert_golden_file("samples_v3.parquet", 3, Some(golden_device()));
+    }
+
+    #[test]
+    fn test_schema_changes() {
+        let df = df!(
+            "distance_dm" => [0u32, 55, 104],
+            "power_watts" => [None, Some(200u32), Some(210)],
+        )
+        .unwrap();
+        let lf = [
+            SchemaChange::RenameColumn {
+                from: "distance_dm",
+                to: "distance_m",
+            },
+            SchemaChange::ScaleColumn {
+                name: "distance_m",
+                factor: 0.1,
+            },
+            SchemaChange::AddColumn {
+                name: "drag_factor",
+                dtype: DataType::UInt32,
+            },
+        ]
+        .iter()
+        .fold(df.lazy(), |lf, change| change.apply(lf));
+        let expected = df!(
+            "distance_m" => [0u32, 6, 10],
+            "power_watts" => [None, Some(200u32), Some(210)],
+            "drag_factor" => [None::<u32>, None, None],
         )
+        .unwrap();
+        assert!(lf.collect().unwrap().equals_missing(&expected));
+
+        let df = WorkoutSample::to_dataframe(&golden_samples()).unwrap();
+        assert!(migrate_samples(df, SAMPLE_SCHEMA_VERSION + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_device_is_stored_with_samples() {
+        let storage = WorkoutStorage::new_memory().unwrap();
+        let mut recorder = WorkoutRecorder::new("user".into());
+        recorder.set_machine_type(ErgMachineType::StaticD);
+        recorder.set_device(DeviceInfo {
+            machine_type: None,
+            ..golden_device()
+        });
+        assert_eq!(recorder.device(), &golden_device());
+        recorder.add_general_sample(0, 0, None, None, None);
+        let summary = recorder.generate_summary(None).unwrap();
+        storage.save_workout(&recorder, &summary).await.unwrap();
+
+        let metadata = storage
+            .load_workout_metadata("user", summary.workout_id)
+            .await
+            .unwrap();
+        assert_eq!(metadata.schema_version, SAMPLE_SCHEMA_VERSION);
+        assert_eq!(metadata.device, Some(golden_device()));
+    }
+
+    #[cfg(feature = "s3")]
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "needs an S3-compatible server, e.g. `minio server` with PM5_S3_ENDPOINT set"]
+    async fn test_s3_storage() {
+        let env = |key: &str| std::env::var(key).ok();
+        let storage = WorkoutStorage::new_s3(S3Config {
+            bucket: env("PM5_S3_BUCKET").unwrap_or_else(|| "pm5".into()),
+            root: Some(format!("/test/{}", Uuid::now_v7())),
+            region: env("PM5_S3_REGION").or_else(|| Some("us-east-1".into())),
+            endpoint: env("PM5_S3_ENDPOINT"),
+            access_key_id: env("PM5_S3_ACCESS_KEY_ID"),
+            secret_access_key: env("PM5_S3_SECRET_ACCESS_KEY"),
+        })
+        .unwrap();
+        assert_round_trip(&storage).await;
+        storage.operator().remove_all("/").await.unwrap();
     }
 }
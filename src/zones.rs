@@ -0,0 +1,158 @@
+//! Per-user power and heart rate training zones.
+//!
+//! Zones are numbered from 1 and split by ascending lower bounds: a value at
+//! or above the first bound is in zone 2, at or above the second in zone 3,
+//! and so on.
+
+use serde::{Deserialize, Serialize};
+
+use crate::pace::Ergometer;
+
+/// Upper bounds of zones 1–4 of five, as percentages of FTP.
+const FTP_POWER_ZONES: [u32; 4] = [55, 75, 90, 105];
+/// UT2, UT1, AT, TR and AN bands as percentages of 2k power.
+const TWO_K_POWER_ZONES: [u32; 4] = [55, 70, 80, 105];
+/// Percentages of max HR, or of heart rate reserve for Karvonen zones.
+const MAX_HR_ZONES: [u32; 4] = [60, 70, 80, 90];
+/// Percentages of lactate threshold heart rate.
+const LTHR_ZONES: [u32; 4] = [85, 90, 95, 100];
+
+/// Power zones as the lower bounds of zones 2 and up, in watts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PowerZones {
+    pub bounds_watts: Vec<u16>,
+}
+
+impl PowerZones {
+    /// Five zones from functional threshold power.
+    pub fn from_ftp(ftp_watts: u16) -> Self {
+        Self {
+            bounds_watts: percentages(ftp_watts as u32, &FTP_POWER_ZONES),
+        }
+    }
+
+    /// Five rowing training bands from a 2k test pace.
+    pub fn from_2k_pace(pace_ms_per_500m: u32) -> Self {
+        let watts = Ergometer::RowErg.watts_from_pace(pace_ms_per_500m).round() as u32;
+        Self {
+            bounds_watts: percentages(watts, &TWO_K_POWER_ZONES),
+        }
+    }
+
+    pub fn zone(&self, watts: u16) -> u32 {
+        zone(&self.bounds_watts, watts)
+    }
+}
+
+/// Heart rate zones as the lower bounds of zones 2 and up, in bpm.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeartRateZones {
+    pub bounds_bpm: Vec<u8>,
+}
+
+impl HeartRateZones {
+    /// Five zones from maximum heart rate.
+    pub fn from_max_hr(max_bpm: u8) -> Self {
+        Self {
+            bounds_bpm: percentages(max_bpm as u32, &MAX_HR_ZONES),
+        }
+    }
+
+    /// Five Karvonen zones from heart rate reserve, the difference between
+    /// maximum and resting heart rate.
+    pub fn from_heart_rate_reserve(max_bpm: u8, resting_bpm: u8) -> Self {
+        let reserve = max_bpm.saturating_sub(resting_bpm) as u32;
+        Self {
+            bounds_bpm: percentages(reserve, &MAX_HR_ZONES)
+                .into_iter()
+                .map(|bpm: u8| bpm.saturating_add(resting_bpm))
+                .collect(),
+        }
+    }
+
+    /// Five zones from lactate threshold heart rate.
+    pub fn from_lthr(lthr_bpm: u8) -> Self {
+        Self {
+            bounds_bpm: percentages(lthr_bpm as u32, &LTHR_ZONES),
+        }
+    }
+
+    pub fn zone(&self, bpm: u8) -> u32 {
+        zone(&self.bounds_bpm, bpm)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrainingZones {
+    pub power: Option<PowerZones>,
+    pub heart_rate: Option<HeartRateZones>,
+}
+
+/// Settings stored per user, at `users/{user_id}/profile.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub user_id: String,
+    #[serde(default)]
+    pub zones: TrainingZones,
+    /// Functional threshold power, for training stress.
+    #[serde(default)]
+    pub ftp_watts: Option<u16>,
+    /// Maximum and resting heart rate, for TRIMP.
+    #[serde(default)]
+    pub max_heart_rate_bpm: Option<u8>,
+    #[serde(default)]
+    pub resting_heart_rate_bpm: Option<u8>,
+    /// For work per stroke per kilogram.
+    #[serde(default)]
+    pub body_weight_kg: Option<u16>,
+}
+
+fn percentages<T: TryFrom<u32>>(value: u32, percentages: &[u32]) -> Vec<T> {
+    percentages
+        .iter()
+        .filter_map(|percent| T::try_from((value * percent + 50) / 100).ok())
+        .collect()
+}
+
+fn zone<T: PartialOrd>(bounds: &[T], value: T) -> u32 {
+    1 + bounds.iter().filter(|bound| value >= **bound).count() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zone_bounds() {
+        assert_eq!(
+            PowerZones::from_ftp(200).bounds_watts,
+            vec![110, 150, 180, 210]
+        );
+        // 1:45.0/500m is 302 W.
+        assert_eq!(
+            PowerZones::from_2k_pace(105_000).bounds_watts,
+            vec![166, 211, 242, 317]
+        );
+        assert_eq!(
+            HeartRateZones::from_max_hr(190).bounds_bpm,
+            vec![114, 133, 152, 171]
+        );
+        assert_eq!(
+            HeartRateZones::from_heart_rate_reserve(190, 50).bounds_bpm,
+            vec![134, 148, 162, 176]
+        );
+        assert_eq!(
+            HeartRateZones::from_lthr(170).bounds_bpm,
+            vec![145, 153, 162, 170]
+        );
+    }
+
+    #[test]
+    fn test_zone() {
+        let zones = PowerZones::from_ftp(200);
+        assert_eq!(zones.zone(0), 1);
+        assert_eq!(zones.zone(110), 2);
+        assert_eq!(zones.zone(209), 4);
+        assert_eq!(zones.zone(400), 5);
+    }
+}